@@ -0,0 +1,78 @@
+use std::time::{Duration, Instant};
+
+use bevy::core::CorePlugin;
+use bevy::ecs::schedule::{Stage, SystemStage};
+use bevy::prelude::*;
+use tactician_bevy::bench::*;
+
+const BODIES: usize = 100;
+/// Real time between updates, as if the game was running at 60 fps
+const FRAME: Duration = Duration::from_nanos(1_000_000_000 / 60);
+/// Updates that get timed, once the trails have filled up
+const MEASURED_UPDATES: u32 = 600;
+
+/// Time spent in `update_snail_trails` with 100 bodies orbiting a star. Runs headless, on a clock
+/// that moves on by one [`FRAME`] every update, so it takes the same number of updates however
+/// fast the machine is: `cargo bench --bench trails`.
+///
+/// The trails are left to fill up before timing starts, since that's where drawing them costs the
+/// most.
+fn main() {
+    let mut app = App::new();
+    // MinimalPlugins, except for the time plugin, so that the clock only moves when we say
+    app.add_plugin(CorePlugin::default())
+        .init_resource::<Time>()
+        .add_plugin(PhysicsPlugin)
+        .add_plugin(TeamsPlugin)
+        .init_resource::<CameraController>()
+        .init_resource::<TrailFrame>()
+        .init_resource::<TrailLength>()
+        .add_startup_system(spawn_bodies);
+    let mut trails = SystemStage::single_threaded().with_system(update_snail_trails);
+
+    let warm_up = (TrailLength::default().0 / FRAME.as_secs_f32()).ceil() as u32;
+    let start = Instant::now();
+    let mut spent = Duration::ZERO;
+    for update in 1..=warm_up + MEASURED_UPDATES {
+        app.world
+            .resource_mut::<Time>()
+            .update_with_instant(start + FRAME * update);
+        app.update();
+
+        let started = Instant::now();
+        trails.run(&mut app.world);
+        if update > warm_up {
+            spent += started.elapsed();
+        }
+    }
+
+    println!(
+        "{} trailed bodies: {:.3} ms per update",
+        BODIES,
+        spent.as_secs_f64() * 1000. / MEASURED_UPDATES as f64
+    );
+}
+
+fn spawn_bodies(mut commands: Commands) {
+    let star_mass = 3e15;
+    commands.spawn_bundle(StarBundle {
+        mass: Mass(star_mass),
+        ..Default::default()
+    });
+
+    // spread the bodies out over a range of orbits, so that their trails don't all overlap
+    for idx in 0..BODIES {
+        let fraction = idx as f32 / BODIES as f32;
+        let radius = 300. + 1700. * fraction;
+        let angle = std::f32::consts::TAU * fraction * 7.;
+        let dir = Vec2::new(angle.cos(), angle.sin());
+        let speed = (G * star_mass / radius).sqrt();
+        commands.spawn_bundle(MissileBundle {
+            position: Position(dir * radius),
+            velocity: Velocity(dir.perp() * speed),
+            snail_trail: SnailTrail::default(),
+            trail_style: TrailStyle::ship(),
+            ..Default::default()
+        });
+    }
+}
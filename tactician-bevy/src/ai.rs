@@ -0,0 +1,183 @@
+use bevy::prelude::*;
+
+use crate::colliders::Collider;
+use crate::components::Size;
+use crate::components::*;
+use crate::events::SpawnMissileFromShip;
+use crate::physics::SimulationTime;
+use crate::teams::TeamRegistry;
+
+/// How fast missiles leave the ship that fires them, on top of its own velocity
+const MISSILE_SPEED: f32 = 45.;
+
+/// Seconds ahead that a ship looks for a planet or star that it's about to run into
+const COLLISION_LOOKAHEAD: f32 = 5.;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Difficulty {
+    Easy,
+    Normal,
+    Hard,
+}
+
+impl Default for Difficulty {
+    fn default() -> Self {
+        Self::Normal
+    }
+}
+
+impl Difficulty {
+    pub const ALL: [Difficulty; 3] = [Difficulty::Easy, Difficulty::Normal, Difficulty::Hard];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Difficulty::Easy => "Easy",
+            Difficulty::Normal => "Normal",
+            Difficulty::Hard => "Hard",
+        }
+    }
+
+    /// How far off (in radians) the aim can be for it to take the shot anyway
+    fn aim_tolerance(&self) -> f32 {
+        match self {
+            Difficulty::Easy => 0.35,
+            Difficulty::Normal => 0.15,
+            Difficulty::Hard => 0.05,
+        }
+    }
+
+    /// Simulated seconds between shots
+    fn reload_time(&self) -> f32 {
+        match self {
+            Difficulty::Easy => 8.,
+            Difficulty::Normal => 4.,
+            Difficulty::Hard => 2.,
+        }
+    }
+
+    /// Whether it aims at where the target is going to be, rather than where it is now
+    fn leads_target(&self) -> bool {
+        *self != Difficulty::Easy
+    }
+
+    /// Furthest away that it takes shots from
+    fn range(&self) -> f32 {
+        match self {
+            Difficulty::Easy => 400.,
+            Difficulty::Normal => 700.,
+            Difficulty::Hard => 1000.,
+        }
+    }
+}
+
+/// Flies a ship that nobody is controlling: it turns towards the closest enemy ship and fires
+/// when it has a shot, while trying not to fly into anything
+#[derive(Debug, Clone, Component)]
+pub struct AiPilot {
+    pub difficulty: Difficulty,
+    /// Simulated seconds until it can fire again
+    reload: f32,
+}
+
+impl AiPilot {
+    pub fn new(difficulty: Difficulty) -> Self {
+        Self {
+            difficulty,
+            reload: difficulty.reload_time(),
+        }
+    }
+}
+
+pub fn fly_ai_ships(
+    sim_time: Res<SimulationTime>,
+    registry: Res<TeamRegistry>,
+    mut pilots: Query<(
+        Entity,
+        &mut AiPilot,
+        &Position,
+        &Velocity,
+        &Team,
+        &mut EnginePhysics,
+        &mut Magazine,
+    )>,
+    ships: Query<(&Position, &Velocity, &Team), With<Ship>>,
+    sources: Query<(&Position, &Size, Option<&Collider>), With<GravitySource>>,
+    mut spawn_missile_event: EventWriter<SpawnMissileFromShip>,
+) {
+    for (ship, mut pilot, Position(pos), Velocity(vel), team, mut engine, mut magazine) in
+        pilots.iter_mut()
+    {
+        pilot.reload -= sim_time.delta;
+        if vel.length_squared() <= f32::EPSILON {
+            engine.set_controls(0., 0.);
+            continue;
+        }
+
+        // steering clear of planets and stars comes before anything else
+        if let Some(to_source) = sources
+            .iter()
+            .map(|(Position(source_pos), Size(size), collider)| {
+                let radius = collider.map_or_else(
+                    || Collider::default().bounding_radius(),
+                    Collider::bounding_radius,
+                ) * size;
+                (*source_pos - *pos, radius)
+            })
+            .find(|(to_source, radius)| on_collision_course(*to_source, *vel, *radius))
+            .map(|(to_source, _)| to_source)
+        {
+            // swing the velocity away from whichever side the source is on
+            engine.set_controls(0., -vel.perp_dot(to_source).signum());
+            continue;
+        }
+
+        let difficulty = pilot.difficulty;
+        let target = ships
+            .iter()
+            .filter(|(_, _, other_team)| !registry.are_allied(team, other_team))
+            .min_by(|(Position(a), ..), (Position(b), ..)| {
+                pos.distance_squared(*a)
+                    .total_cmp(&pos.distance_squared(*b))
+            });
+        let (Position(target_pos), Velocity(target_vel), _) = match target {
+            Some(target) => target,
+            None => {
+                engine.set_controls(0., 0.);
+                continue;
+            }
+        };
+
+        let offset = *target_pos - *pos;
+        let aim = if difficulty.leads_target() {
+            // where the target will be by the time a missile gets there, roughly
+            let flight_time = offset.length() / (vel.length() + MISSILE_SPEED);
+            offset + *target_vel * flight_time
+        } else {
+            offset
+        };
+        let angle = vel.angle_between(aim);
+        engine.set_controls(0., 2. * angle);
+
+        if pilot.reload <= 0.
+            && angle.abs() < difficulty.aim_tolerance()
+            && offset.length() < difficulty.range()
+            && magazine.take()
+        {
+            pilot.reload = difficulty.reload_time();
+            spawn_missile_event.send(SpawnMissileFromShip {
+                ship,
+                position: Position(*pos),
+                velocity: Velocity(*vel + (MISSILE_SPEED * vel.normalize())),
+                team: team.clone(),
+            });
+        }
+    }
+}
+
+/// Whether going in a straight line at `vel` would pass within `radius` of something at
+/// `to_source` in the next few seconds
+fn on_collision_course(to_source: Vec2, vel: Vec2, radius: f32) -> bool {
+    let time_to_closest = to_source.dot(vel) / vel.length_squared();
+    (0. ..COLLISION_LOOKAHEAD).contains(&time_to_closest)
+        && to_source.distance(vel * time_to_closest) < 1.5 * radius
+}
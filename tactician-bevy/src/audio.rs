@@ -0,0 +1,290 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use bevy::prelude::*;
+use bevy_kira_audio::{
+    Audio, AudioControl, AudioInstance, AudioPlugin, AudioSource, AudioTween, PlaybackState,
+};
+
+use crate::components::*;
+use crate::controls::ActionState;
+use crate::events::{CreateExplosionEvent, SpawnMissileFromShip};
+use crate::physics::{SimulationAuthority, SimulationPaused};
+use crate::settings::Settings;
+
+/// How far off the middle of the screen a sound is when it's half as loud, in half-widths of the
+/// window. Zooming out brings everything closer, so it all gets louder.
+const FALLOFF: f32 = 1.5;
+/// Sounds quieter than this aren't worth playing at all
+const MIN_GAIN: f32 = 0.02;
+/// How far towards one ear a sound at the edge of the screen is panned, where 1 is all the way
+const PAN_WIDTH: f32 = 0.8;
+/// How long engine sounds take to fade out, so they don't click
+const ENGINE_FADE: Duration = Duration::from_millis(120);
+
+/// What a sound is, for the purposes of how loud the [`AudioMixer`] plays it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SoundCategory {
+    Engines,
+    Launches,
+    Explosions,
+}
+
+/// Volume of each kind of sound, between 0 and 1. The master volume comes from the settings and
+/// applies on top of the rest.
+#[derive(Debug, Clone)]
+pub struct AudioMixer {
+    pub master: f32,
+    pub engines: f32,
+    pub launches: f32,
+    pub explosions: f32,
+}
+
+impl Default for AudioMixer {
+    fn default() -> Self {
+        Self {
+            master: 0.8,
+            engines: 0.4,
+            launches: 0.7,
+            explosions: 1.,
+        }
+    }
+}
+
+impl AudioMixer {
+    pub fn volume(&self, category: SoundCategory) -> f32 {
+        let volume = match category {
+            SoundCategory::Engines => self.engines,
+            SoundCategory::Launches => self.launches,
+            SoundCategory::Explosions => self.explosions,
+        };
+        self.master * volume
+    }
+}
+
+pub struct SoundEffects {
+    pub thrust: Handle<AudioSource>,
+    pub launch: Handle<AudioSource>,
+    pub explosion: Handle<AudioSource>,
+}
+
+impl FromWorld for SoundEffects {
+    fn from_world(world: &mut World) -> Self {
+        let asset_server = world
+            .get_resource::<AssetServer>()
+            .expect("Unable to get AssetServer when initializing SoundEffects resource");
+
+        SoundEffects {
+            thrust: asset_server.load("sounds/thrust.wav"),
+            launch: asset_server.load("sounds/launch.wav"),
+            explosion: asset_server.load("sounds/explosion.wav"),
+        }
+    }
+}
+
+/// The looping engine sound of every ship that's thrusting
+#[derive(Debug, Default)]
+pub struct EngineLoops(HashMap<Entity, Handle<AudioInstance>>);
+
+/// Where sounds are heard from, which is wherever the camera is looking
+#[derive(Debug, Clone, Copy)]
+struct Listener {
+    transform: Transform,
+    half_width: f32,
+}
+
+impl Listener {
+    fn find(windows: &Windows, camera: &Query<&Transform, With<ShipCamera>>) -> Option<Self> {
+        let window = windows.get_primary()?;
+        let transform = *camera.get_single().ok()?;
+        Some(Self {
+            transform,
+            half_width: window.width() / 2.,
+        })
+    }
+
+    /// How loud (between 0 and 1) and how far to the right (between 0 and 1, with 0.5 in the
+    /// middle) something at `position` sounds
+    fn gain_and_panning(&self, position: Vec2) -> (f32, f32) {
+        // where it is on screen relative to the middle, in half-widths of the window
+        let on_screen = (self.transform.rotation.inverse()
+            * (position.extend(0.) - self.transform.translation))
+            .truncate()
+            / (self.transform.scale.x * self.half_width);
+        let gain = 1. / (1. + (on_screen.length() / FALLOFF).powi(2));
+        let panning = 0.5 + 0.5 * PAN_WIDTH * on_screen.x.clamp(-1., 1.);
+        (gain, panning)
+    }
+
+    fn play(
+        &self,
+        audio: &Audio,
+        mixer: &AudioMixer,
+        sound: &Handle<AudioSource>,
+        category: SoundCategory,
+        position: Vec2,
+    ) {
+        let (gain, panning) = self.gain_and_panning(position);
+        let volume = gain * mixer.volume(category);
+        if volume < MIN_GAIN {
+            return;
+        }
+        audio
+            .play(sound.clone())
+            .with_volume(volume as f64)
+            .with_panning(panning as f64);
+    }
+}
+
+pub struct SoundPlugin;
+
+impl Plugin for SoundPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugin(AudioPlugin)
+            .init_resource::<AudioMixer>()
+            .init_resource::<SoundEffects>()
+            .init_resource::<EngineLoops>()
+            .add_system(apply_volume_setting)
+            .add_system(play_engine_sounds.after(apply_volume_setting))
+            .add_system(play_launch_sounds.after(apply_volume_setting))
+            .add_system(play_explosion_sounds.after(apply_volume_setting));
+    }
+}
+
+pub fn apply_volume_setting(settings: Res<Settings>, mut mixer: ResMut<AudioMixer>) {
+    if settings.is_changed() {
+        mixer.master = settings.clamped_volume();
+    }
+}
+
+/// Keeps a looping engine sound going for every ship that's thrusting, following it around as it
+/// moves, and fades it out once it stops (or is destroyed)
+pub fn play_engine_sounds(
+    audio: Res<Audio>,
+    effects: Res<SoundEffects>,
+    mixer: Res<AudioMixer>,
+    authority: Res<SimulationAuthority>,
+    paused: Res<SimulationPaused>,
+    actions: Res<ActionState>,
+    windows: Res<Windows>,
+    camera: Query<&Transform, With<ShipCamera>>,
+    ships: Query<(Entity, &Position, &EnginePhysics, Option<&LocalPlayer>), With<Ship>>,
+    mut loops: ResMut<EngineLoops>,
+    mut instances: ResMut<Assets<AudioInstance>>,
+) {
+    let listener = Listener::find(&windows, &camera);
+    let thrusting: HashMap<Entity, Vec2> = ships
+        .iter()
+        .filter(|(_, _, engine, local_player)| {
+            // network clients don't run the engines themselves, but they do know what the
+            // player is asking of theirs
+            !paused.0
+                && match *authority {
+                    SimulationAuthority::Local => engine.current_accel != 0.,
+                    SimulationAuthority::Remote => {
+                        local_player.is_some() && actions.throttle() != 0.
+                    }
+                }
+        })
+        .map(|(entity, Position(pos), ..)| (entity, *pos))
+        .collect();
+
+    loops.0.retain(|entity, handle| {
+        let instance = match instances.get_mut(handle) {
+            Some(instance) => instance,
+            // it hasn't started yet, so hang on to it until it can be stopped
+            None => return true,
+        };
+        if matches!(instance.state(), PlaybackState::Stopped) {
+            return false;
+        }
+        match (thrusting.get(entity), listener) {
+            (Some(position), Some(listener)) => {
+                let (gain, panning) = listener.gain_and_panning(*position);
+                let volume = gain * mixer.volume(SoundCategory::Engines);
+                instance.set_volume(volume as f64, AudioTween::default());
+                instance.set_panning(panning as f64, AudioTween::default());
+                true
+            }
+            _ => {
+                instance.stop(AudioTween::linear(ENGINE_FADE));
+                false
+            }
+        }
+    });
+
+    let listener = match listener {
+        Some(listener) => listener,
+        None => return,
+    };
+    for (entity, position) in thrusting {
+        if loops.0.contains_key(&entity) {
+            continue;
+        }
+        let (gain, panning) = listener.gain_and_panning(position);
+        let volume = gain * mixer.volume(SoundCategory::Engines);
+        let handle = audio
+            .play(effects.thrust.clone())
+            .looped()
+            .with_volume(volume as f64)
+            .with_panning(panning as f64)
+            .handle();
+        loops.0.insert(entity, handle);
+    }
+}
+
+pub fn play_launch_sounds(
+    audio: Res<Audio>,
+    effects: Res<SoundEffects>,
+    mixer: Res<AudioMixer>,
+    authority: Res<SimulationAuthority>,
+    windows: Res<Windows>,
+    camera: Query<&Transform, With<ShipCamera>>,
+    mut launches: EventReader<SpawnMissileFromShip>,
+    new_missiles: Query<&Position, Added<Missile>>,
+) {
+    let listener = match Listener::find(&windows, &camera) {
+        Some(listener) => listener,
+        None => return,
+    };
+    let play = |Position(pos): &Position| {
+        listener.play(
+            &audio,
+            &mixer,
+            &effects.launch,
+            SoundCategory::Launches,
+            *pos,
+        )
+    };
+
+    for launch in launches.iter() {
+        play(&launch.position);
+    }
+    // network clients only find out about missiles when they show up in a snapshot
+    if *authority == SimulationAuthority::Remote {
+        new_missiles.iter().for_each(play);
+    }
+}
+
+pub fn play_explosion_sounds(
+    audio: Res<Audio>,
+    effects: Res<SoundEffects>,
+    mixer: Res<AudioMixer>,
+    windows: Res<Windows>,
+    camera: Query<&Transform, With<ShipCamera>>,
+    mut explosions: EventReader<CreateExplosionEvent>,
+) {
+    let listener = match Listener::find(&windows, &camera) {
+        Some(listener) => listener,
+        None => return,
+    };
+    for explosion in explosions.iter() {
+        listener.play(
+            &audio,
+            &mixer,
+            &effects.explosion,
+            SoundCategory::Explosions,
+            explosion.position.0,
+        );
+    }
+}
@@ -0,0 +1,15 @@
+use tactician_bevy::{run_server, ServerConfig};
+
+/// Headless, authoritative server for networked matches.
+///
+/// `tactician-server [--bind <addr>] [--snapshot-rate <hz>] [--latency <ms>] [--jitter <ms>] [--loss <fraction>]`
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match ServerConfig::from_args(&args) {
+        Ok(config) => run_server(config),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+}
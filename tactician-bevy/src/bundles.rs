@@ -1,24 +1,43 @@
+use crate::colliders::{Collider, ColliderShapes};
 use crate::components::*;
+use crate::particles::ParticleField;
+use crate::trails::{SnailTrail, TrailStyle};
 use bevy::{
     prelude::{Bundle, Component},
     time::Timer,
 };
-use bevy_prototype_lyon::prelude::{
-    tess::path::{builder::PathBuilder, path::Builder, Polygon},
-    Geometry,
-};
-use lyon_geom::euclid::default::Point2D;
 
-#[derive(Bundle, Default)]
+#[derive(Bundle)]
 pub struct StarBundle {
     pub position: Position,
+    pub velocity: Velocity,
     pub mass: Mass,
     pub size: Size,
     pub star: Star,
     pub gravity_source: GravitySource,
+    pub collider: Collider,
+    pub snail_trail: SnailTrail,
+    pub trail_style: TrailStyle,
 }
 
-#[derive(Bundle, Default)]
+impl Default for StarBundle {
+    fn default() -> Self {
+        Self {
+            position: Position::default(),
+            velocity: Velocity::default(),
+            mass: Mass::default(),
+            size: Size::default(),
+            star: Star::default(),
+            gravity_source: GravitySource::default(),
+            // stars are drawn with the planet sprite
+            collider: ColliderShapes::loaded().planet.clone(),
+            snail_trail: SnailTrail::default(),
+            trail_style: TrailStyle::star(),
+        }
+    }
+}
+
+#[derive(Bundle)]
 pub struct PlanetBundle {
     pub position: Position,
     pub velocity: Velocity,
@@ -28,10 +47,34 @@ pub struct PlanetBundle {
 
     pub planet: Planet,
     pub gravity_source: GravitySource,
+    pub collider: Collider,
     pub snail_trail: SnailTrail,
+    pub trail_style: TrailStyle,
 }
 
+impl Default for PlanetBundle {
+    fn default() -> Self {
+        Self {
+            position: Position::default(),
+            velocity: Velocity::default(),
+            mass: Mass::default(),
+            size: Size::default(),
+            planet: Planet::default(),
+            gravity_source: GravitySource::default(),
+            collider: ColliderShapes::loaded().planet.clone(),
+            snail_trail: SnailTrail::default(),
+            trail_style: TrailStyle::default(),
+        }
+    }
+}
+
+/// A whole asteroid belt (or debris field) in one entity. See [`ParticleField`].
 #[derive(Bundle, Default)]
+pub struct ParticleFieldBundle {
+    pub field: ParticleField,
+}
+
+#[derive(Bundle)]
 pub struct ShipBundle {
     pub position: Position,
     pub velocity: Velocity,
@@ -39,22 +82,69 @@ pub struct ShipBundle {
 
     pub size: Size,
     pub engine: EnginePhysics,
+    pub heat: Heat,
+    pub magazine: Magazine,
 
     pub ship: Ship,
     pub team: Team,
+    pub collider: Collider,
     pub snail_trail: SnailTrail,
+    pub trail_style: TrailStyle,
+}
+
+impl Default for ShipBundle {
+    fn default() -> Self {
+        Self {
+            position: Position::default(),
+            velocity: Velocity::default(),
+            mass: Mass::default(),
+            size: Size::default(),
+            engine: EnginePhysics::default(),
+            heat: Heat::default(),
+            magazine: Magazine::default(),
+            ship: Ship::default(),
+            team: Team::default(),
+            collider: ColliderShapes::loaded().ship.clone(),
+            snail_trail: SnailTrail::with_history(60.),
+            trail_style: TrailStyle::ship(),
+        }
+    }
 }
 
-#[derive(Debug, Bundle, Default)]
+#[derive(Debug, Bundle)]
 pub struct MissileBundle {
     pub snail_trail: SnailTrail,
+    pub trail_style: TrailStyle,
     pub position: Position,
     pub velocity: Velocity,
     pub size: Size,
     pub mass: Mass,
     pub lifespan: Lifespan,
+    pub heat: Heat,
     pub missile: Missile,
+    pub fired_by: FiredBy,
     pub team: Team,
+    pub collider: Collider,
+}
+
+impl Default for MissileBundle {
+    fn default() -> Self {
+        Self {
+            // just a short streak behind it
+            snail_trail: SnailTrail::with_history(2.),
+            trail_style: TrailStyle::missile(),
+            position: Position::default(),
+            velocity: Velocity::default(),
+            size: Size::default(),
+            mass: Mass::default(),
+            lifespan: Lifespan::default(),
+            heat: Heat::default(),
+            missile: Missile::default(),
+            fired_by: FiredBy::default(),
+            team: Team::default(),
+            collider: ColliderShapes::loaded().missile.clone(),
+        }
+    }
 }
 
 #[derive(Debug, Component)]
@@ -92,32 +182,3 @@ impl Default for ExplosionBundle {
         }
     }
 }
-
-#[derive(Debug, Clone, Component)]
-pub struct SnailTrail {
-    pub points: Vec<Point2D<f32>>,
-    pub max_points: usize,
-}
-
-impl Default for SnailTrail {
-    fn default() -> Self {
-        Self {
-            points: Vec::with_capacity(500),
-            max_points: 7000,
-        }
-    }
-}
-
-impl Geometry for SnailTrail {
-    fn add_geometry(&self, b: &mut Builder) {
-        b.add_polygon(Polygon {
-            points: self.points.as_slice(),
-            closed: false,
-        })
-    }
-}
-
-/// The snail trail component is separate from the actual ship/planet/missile
-/// The entire trail is despawned and redrawn
-#[derive(Debug, Clone, Copy, Component)]
-pub struct SnailTrailEntityMarker;
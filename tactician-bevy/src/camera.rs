@@ -0,0 +1,456 @@
+use std::f32::consts::FRAC_PI_2;
+
+use bevy::input::mouse::{MouseScrollUnit, MouseWheel};
+use bevy::prelude::*;
+
+use crate::components::*;
+use crate::controls::{Action, ActionState};
+use crate::physics::{barycenter, dominant_source};
+use crate::player::PlayerStatus;
+
+/// Closest and furthest the camera can zoom by default, in world units per pixel
+pub const MIN_SCALE: f32 = 1.0;
+pub const MAX_SCALE: f32 = 20.0;
+
+/// How quickly the camera catches up with whatever it's looking at. Higher is snappier.
+const FOLLOW_SHARPNESS: f32 = 8.0;
+
+/// Fraction of the screen left empty around the edges in [`CameraMode::FitAll`]
+const FIT_MARGIN: f32 = 0.2;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, SystemLabel)]
+pub enum CameraSystem {
+    /// Works out where the camera's frame is
+    Frame,
+    /// Reacts to the player moving the camera around
+    Input,
+    /// Moves the camera to where it should be
+    Move,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CameraMode {
+    /// Keep [`CameraController::target`] in the middle of the screen
+    Follow,
+    /// Stay put (relative to the frame) and let the player drag the view around
+    Free,
+    /// Keep every body in view
+    FitAll,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CameraFrame {
+    /// The fixed frame that the simulation runs in
+    Inertial,
+    /// Centered on the body and turning with it as it orbits whatever pulls on it hardest, so
+    /// that its primary always stays straight "down" the screen
+    Rotating(Entity),
+    /// Moving along with the center of mass of every star and planet, which stays put even while
+    /// the stars wobble around each other
+    CenterOfMass,
+}
+
+/// Closest and furthest the camera can zoom, in world units per pixel
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ZoomLimits {
+    pub min: f32,
+    pub max: f32,
+}
+
+impl Default for ZoomLimits {
+    fn default() -> Self {
+        Self {
+            min: MIN_SCALE,
+            max: MAX_SCALE,
+        }
+    }
+}
+
+/// What the [`ShipCamera`] is looking at, and how
+#[derive(Debug)]
+pub struct CameraController {
+    pub mode: CameraMode,
+    /// What to follow in [`CameraMode::Follow`]. When this is `None` (or the entity is gone) we
+    /// follow the player's own ship instead.
+    pub target: Option<Entity>,
+    pub frame: CameraFrame,
+    /// Middle of the screen in [`CameraMode::Free`], relative to the frame
+    center: Vec2,
+    /// Where the frame currently is, refreshed every update
+    frame_origin: Vec2,
+    frame_rotation: f32,
+}
+
+impl Default for CameraController {
+    fn default() -> Self {
+        Self {
+            mode: CameraMode::Follow,
+            target: None,
+            frame: CameraFrame::Inertial,
+            center: Vec2::ZERO,
+            frame_origin: Vec2::ZERO,
+            frame_rotation: 0.,
+        }
+    }
+}
+
+impl CameraController {
+    /// Converts a point in the world into the camera's frame
+    pub fn to_frame(&self, point: Vec2) -> Vec2 {
+        Mat2::from_angle(-self.frame_rotation) * (point - self.frame_origin)
+    }
+
+    /// Converts a point in the camera's frame back into the world
+    pub fn from_frame(&self, point: Vec2) -> Vec2 {
+        self.frame_origin + Mat2::from_angle(self.frame_rotation) * point
+    }
+
+    /// Stops following anything and looks at `point` (in world coordinates) instead
+    pub fn look_at(&mut self, point: Vec2) {
+        self.mode = CameraMode::Free;
+        self.center = self.to_frame(point);
+    }
+
+    pub fn follow(&mut self, target: Entity) {
+        self.mode = CameraMode::Follow;
+        self.target = Some(target);
+    }
+}
+
+/// Where the mouse cursor is pointing in the world, if it's over the window
+pub fn cursor_world_position(window: &Window, camera: &Transform) -> Option<Vec2> {
+    let cursor = window.cursor_position()?;
+    let from_center = cursor - Vec2::new(window.width(), window.height()) / 2.;
+    Some(
+        camera.translation.truncate()
+            + (camera.rotation * (from_center * camera.scale.x).extend(0.)).truncate(),
+    )
+}
+
+pub fn reset_camera(mut controller: ResMut<CameraController>) {
+    *controller = CameraController::default();
+}
+
+/// Works out where the selected frame is this update
+pub fn update_camera_frame(
+    mut controller: ResMut<CameraController>,
+    bodies: Query<&Position>,
+    gravity_sources: Query<(Entity, &Position, &Mass), With<GravitySource>>,
+) {
+    let body = match controller.frame {
+        CameraFrame::Inertial => None,
+        CameraFrame::Rotating(body) => Some(body),
+        CameraFrame::CenterOfMass => {
+            let (center, _, _) = barycenter(
+                gravity_sources
+                    .iter()
+                    .map(|(_, Position(pos), Mass(mass))| (*pos, Vec2::ZERO, *mass)),
+            );
+            controller.frame_origin = center;
+            controller.frame_rotation = 0.;
+            return;
+        }
+    };
+    let (origin, rotation) = match body.map(|body| (body, bodies.get(body))) {
+        None => (Vec2::ZERO, 0.),
+        Some((body, Ok(Position(body_pos)))) => {
+            let primary = dominant_source(
+                *body_pos,
+                gravity_sources
+                    .iter()
+                    .filter(|(source, _, _)| *source != body)
+                    .map(|(_, Position(pos), Mass(mass))| (*pos, *mass)),
+            );
+            let rotation = match primary {
+                Some((primary_pos, _)) => {
+                    let outward = *body_pos - primary_pos;
+                    outward.y.atan2(outward.x) - FRAC_PI_2
+                }
+                // nothing to orbit, so there's nothing to rotate with either
+                None => controller.frame_rotation,
+            };
+            (*body_pos, rotation)
+        }
+        // the body was destroyed
+        Some((_, Err(_))) => {
+            controller.frame = CameraFrame::Inertial;
+            (Vec2::ZERO, 0.)
+        }
+    };
+
+    controller.frame_origin = origin;
+    controller.frame_rotation = rotation;
+}
+
+/// Things the camera can be pointed at, in the order that they are cycled through
+fn camera_targets(
+    bodies: &Query<(
+        Entity,
+        &Position,
+        Option<&Ship>,
+        Option<&Planet>,
+        Option<&Missile>,
+    )>,
+) -> Vec<Entity> {
+    let mut targets: Vec<(u8, Entity)> = bodies
+        .iter()
+        .filter_map(|(entity, _, ship, planet, missile)| {
+            match (ship.is_some(), planet.is_some(), missile.is_some()) {
+                (true, _, _) => Some((0, entity)),
+                (_, true, _) => Some((1, entity)),
+                (_, _, true) => Some((2, entity)),
+                _ => None,
+            }
+        })
+        .collect();
+    targets.sort_by_key(|(kind, entity)| (*kind, entity.id()));
+    targets.into_iter().map(|(_, entity)| entity).collect()
+}
+
+pub fn handle_camera_actions(
+    actions: Res<ActionState>,
+    mut controller: ResMut<CameraController>,
+    camera: Query<&Transform, With<ShipCamera>>,
+    bodies: Query<(
+        Entity,
+        &Position,
+        Option<&Ship>,
+        Option<&Planet>,
+        Option<&Missile>,
+    )>,
+    planets: Query<(Entity, &Position), With<Planet>>,
+) {
+    let camera_pos = camera.single().translation.truncate();
+
+    let step = if actions.just_pressed(Action::NextTarget) {
+        1
+    } else if actions.just_pressed(Action::PreviousTarget) {
+        -1
+    } else {
+        0
+    };
+    if step != 0 {
+        let targets = camera_targets(&bodies);
+        if !targets.is_empty() {
+            let current = controller
+                .target
+                .and_then(|target| targets.iter().position(|e| *e == target));
+            let next = match current {
+                Some(idx) => (idx as isize + step).rem_euclid(targets.len() as isize) as usize,
+                None if step > 0 => 0,
+                None => targets.len() - 1,
+            };
+            controller.follow(targets[next]);
+        }
+    }
+
+    if actions.just_pressed(Action::FreeCamera) {
+        if controller.mode == CameraMode::Free {
+            controller.mode = CameraMode::Follow;
+        } else {
+            controller.look_at(camera_pos);
+        }
+    }
+
+    if actions.just_pressed(Action::FitAll) {
+        controller.mode = if controller.mode == CameraMode::FitAll {
+            CameraMode::Follow
+        } else {
+            CameraMode::FitAll
+        };
+    }
+
+    if actions.just_pressed(Action::RotatingFrame) {
+        controller.frame = match controller.frame {
+            CameraFrame::Rotating(_) => CameraFrame::Inertial,
+            // lock on to the planet we're following, or otherwise whichever one is closest
+            CameraFrame::Inertial | CameraFrame::CenterOfMass => {
+                let followed = controller.target.filter(|target| planets.contains(*target));
+                let nearest = || {
+                    planets
+                        .iter()
+                        .min_by(|(_, Position(a)), (_, Position(b))| {
+                            a.distance_squared(camera_pos)
+                                .total_cmp(&b.distance_squared(camera_pos))
+                        })
+                        .map(|(planet, _)| planet)
+                };
+                match followed.or_else(nearest) {
+                    Some(planet) => CameraFrame::Rotating(planet),
+                    None => CameraFrame::Inertial,
+                }
+            }
+        };
+    }
+
+    if actions.just_pressed(Action::CenterOfMassFrame) {
+        controller.frame = match controller.frame {
+            CameraFrame::CenterOfMass => CameraFrame::Inertial,
+            _ => CameraFrame::CenterOfMass,
+        };
+    }
+}
+
+/// Zooming with the zoom actions, which zooms in on the middle of the screen
+pub fn handle_window_zoom(
+    actions: Res<ActionState>,
+    zoom: Res<ZoomLimits>,
+    mut camera: Query<&mut Transform, With<ShipCamera>>,
+) {
+    let mut cam = camera.single_mut();
+    let (zoom_in, zoom_out) = (
+        actions.value(Action::ZoomIn),
+        actions.value(Action::ZoomOut),
+    );
+    let scale = if zoom_in > 0. {
+        cam.scale.x / (1. + 0.01 * zoom_in)
+    } else if zoom_out > 0. {
+        cam.scale.x * (1. + 0.01 * zoom_out)
+    } else {
+        return;
+    };
+    cam.scale = Vec3::splat(scale.clamp(zoom.min, zoom.max));
+}
+
+/// Dragging the view around (with the right or middle mouse button) and zooming towards the
+/// cursor with the mouse wheel
+pub fn handle_camera_mouse(
+    windows: Res<Windows>,
+    mouse_buttons: Res<Input<MouseButton>>,
+    mut wheel_events: EventReader<MouseWheel>,
+    mut last_cursor: Local<Option<Vec2>>,
+    zoom: Res<ZoomLimits>,
+    mut controller: ResMut<CameraController>,
+    mut camera: Query<&mut Transform, With<ShipCamera>>,
+) {
+    let window = match windows.get_primary() {
+        Some(window) => window,
+        None => return,
+    };
+    let mut cam = camera.single_mut();
+    let cursor = window.cursor_position();
+
+    let dragging =
+        mouse_buttons.pressed(MouseButton::Right) || mouse_buttons.pressed(MouseButton::Middle);
+    if let (true, Some(cursor), Some(last)) = (dragging, cursor, *last_cursor) {
+        let delta = cursor - last;
+        if delta != Vec2::ZERO {
+            let world_delta = (cam.rotation * (delta * cam.scale.x).extend(0.)).truncate();
+            let new_center = cam.translation.truncate() - world_delta;
+            controller.look_at(new_center);
+            cam.translation = new_center.extend(cam.translation.z);
+        }
+    }
+    *last_cursor = cursor;
+
+    let scroll: f32 = wheel_events
+        .iter()
+        .map(|ev| match ev.unit {
+            MouseScrollUnit::Line => ev.y,
+            // roughly one line's worth of pixels
+            MouseScrollUnit::Pixel => ev.y / 20.,
+        })
+        .sum();
+    if scroll == 0. {
+        return;
+    }
+
+    let old_scale = cam.scale.x;
+    let new_scale = (old_scale * 0.9f32.powf(scroll)).clamp(zoom.min, zoom.max);
+    // fitting everything in would immediately undo the zoom
+    if controller.mode == CameraMode::FitAll {
+        controller.look_at(cam.translation.truncate());
+    }
+    // keep whatever is under the cursor where it is. When following something we zoom in on
+    // that instead, since it's going to be put back in the middle anyway
+    if controller.mode == CameraMode::Free {
+        if let Some(anchor) = cursor_world_position(window, &cam) {
+            let new_center =
+                anchor - (anchor - cam.translation.truncate()) * (new_scale / old_scale);
+            controller.look_at(new_center);
+            cam.translation = new_center.extend(cam.translation.z);
+        }
+    }
+    cam.scale = Vec3::splat(new_scale);
+}
+
+/// Moves the camera towards whatever it should be looking at
+pub fn move_camera(
+    time: Res<Time>,
+    windows: Res<Windows>,
+    status: Res<PlayerStatus>,
+    zoom: Res<ZoomLimits>,
+    mut controller: ResMut<CameraController>,
+    mut camera: Query<&mut Transform, With<ShipCamera>>,
+    bodies: Query<&Position>,
+    ships: Query<(Entity, Option<&LocalPlayer>), With<Ship>>,
+    everything: Query<&Position, Or<(With<Star>, With<Planet>, With<Ship>, With<Missile>)>>,
+) {
+    let mut cam = camera.single_mut();
+    let mut desired_scale = cam.scale.x;
+
+    let desired_center = match controller.mode {
+        CameraMode::Follow => {
+            let target = controller.target.filter(|target| bodies.contains(*target));
+            // there might not be a ship to follow: we could be dead, or a network client that
+            // hasn't heard from the server yet
+            let target = target.or_else(|| {
+                match ships.iter().find(|(_, local)| local.is_some()) {
+                    Some((own_ship, _)) => Some(own_ship),
+                    // keep an eye on whoever is left
+                    None if *status == PlayerStatus::Spectating => {
+                        ships.iter().next().map(|(ship, _)| ship)
+                    }
+                    None => None,
+                }
+            });
+            target
+                .and_then(|target| bodies.get(target).ok())
+                .map(|Position(pos)| *pos)
+        }
+        CameraMode::Free => Some(controller.from_frame(controller.center)),
+        CameraMode::FitAll => {
+            // measure the bounding box along the screen's axes, since the frame may be rotated
+            let (min, max) = everything.iter().fold(
+                (Vec2::splat(f32::INFINITY), Vec2::splat(f32::NEG_INFINITY)),
+                |(min, max), Position(pos)| {
+                    let pos = controller.to_frame(*pos);
+                    (min.min(pos), max.max(pos))
+                },
+            );
+            match (min.is_finite(), windows.get_primary()) {
+                (true, Some(window)) => {
+                    let extent = (max - min) * (1. + FIT_MARGIN);
+                    desired_scale = (extent.x / window.width())
+                        .max(extent.y / window.height())
+                        .clamp(zoom.min, zoom.max);
+                    Some(controller.from_frame((min + max) / 2.))
+                }
+                _ => None,
+            }
+        }
+    };
+
+    // dragging and free camera zooming already put the camera exactly where it needs to be
+    let smoothing = match controller.mode {
+        CameraMode::Free => 1.,
+        _ => 1. - (-FOLLOW_SHARPNESS * time.delta_seconds()).exp(),
+    };
+    if let Some(center) = desired_center {
+        let z = cam.translation.z;
+        cam.translation = cam.translation.lerp(center.extend(z), smoothing);
+    }
+    cam.rotation = cam
+        .rotation
+        .slerp(Quat::from_rotation_z(controller.frame_rotation), smoothing);
+    if desired_scale != cam.scale.x {
+        cam.scale = Vec3::splat(cam.scale.x + (desired_scale - cam.scale.x) * smoothing);
+    }
+
+    if controller.mode == CameraMode::Follow
+        && controller.target.map_or(false, |t| !bodies.contains(t))
+    {
+        // whatever we were following is gone, go back to the default
+        controller.target = None;
+    }
+}
@@ -0,0 +1,362 @@
+use std::sync::OnceLock;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::components::Size;
+use crate::components::*;
+
+/// The hitboxes for each sprite. These are compiled in, so that the server and every client agree
+/// on them without having to ship the assets directory around.
+const COLLIDER_SHAPES: &str = include_str!("../assets/colliders.ron");
+
+/// Directions the outline of a rounded shape is traced in, for the debug overlay
+const OUTLINE_DIRECTIONS: usize = 32;
+
+/// Shape that a body is hit tested with. Sizes are in sprite pixels at `Size(1.)`, relative to the
+/// middle of the sprite with +y pointing the way the body is heading (which is also the way its
+/// sprite points).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Component)]
+pub enum Collider {
+    Circle {
+        radius: f32,
+    },
+    /// A line from `half_length` behind the middle to `half_length` in front of it, `radius` thick
+    Capsule {
+        half_length: f32,
+        radius: f32,
+    },
+    /// Corners of a convex polygon, going around in either direction
+    Polygon(Vec<(f32, f32)>),
+}
+
+impl Default for Collider {
+    fn default() -> Self {
+        // what every sprite was assumed to be before there were colliders
+        Self::Circle { radius: 25. }
+    }
+}
+
+impl Collider {
+    /// The corners of the shape (before it's rounded off) and how much it's rounded off by
+    fn hull(&self) -> (Vec<Vec2>, f32) {
+        match self {
+            Self::Circle { radius } => (vec![Vec2::ZERO], *radius),
+            Self::Capsule {
+                half_length,
+                radius,
+            } => (
+                vec![Vec2::new(0., -half_length), Vec2::new(0., *half_length)],
+                *radius,
+            ),
+            Self::Polygon(corners) => {
+                (corners.iter().map(|(x, y)| Vec2::new(*x, *y)).collect(), 0.)
+            }
+        }
+    }
+
+    /// Furthest the shape reaches from the middle of the sprite, at `Size(1.)`
+    pub fn bounding_radius(&self) -> f32 {
+        let (corners, radius) = self.hull();
+        corners
+            .iter()
+            .map(|corner| corner.length())
+            .fold(0., f32::max)
+            + radius
+    }
+
+    /// The shape as it is in the world, on a body at `position` moving along `velocity`
+    pub fn placed(&self, position: Vec2, velocity: Vec2, Size(size): &Size) -> PlacedCollider {
+        let heading = velocity.try_normalize().unwrap_or(Vec2::Y);
+        // the sprite's +x, which ends up to the right of the heading
+        let right = -heading.perp();
+        let (corners, radius) = self.hull();
+        PlacedCollider {
+            corners: corners
+                .into_iter()
+                .map(|corner| position + (right * corner.x + heading * corner.y) * *size)
+                .collect(),
+            radius: radius * size,
+        }
+    }
+}
+
+/// A [`Collider`] moved, turned and scaled to where its body is
+#[derive(Debug, Clone)]
+pub struct PlacedCollider {
+    corners: Vec<Vec2>,
+    radius: f32,
+}
+
+impl PlacedCollider {
+    pub fn circle(center: Vec2, radius: f32) -> Self {
+        Self {
+            corners: vec![center],
+            radius,
+        }
+    }
+
+    pub fn overlaps(&self, other: &PlacedCollider) -> bool {
+        hull_distance(&self.corners, &other.corners) < self.radius + other.radius
+    }
+
+    /// The outline of the shape, for drawing it
+    pub fn outline(&self) -> Vec<Vec2> {
+        (0..OUTLINE_DIRECTIONS)
+            .map(|idx| {
+                let angle = std::f32::consts::TAU * idx as f32 / OUTLINE_DIRECTIONS as f32;
+                let dir = Vec2::new(angle.cos(), angle.sin());
+                let furthest = self
+                    .corners
+                    .iter()
+                    .copied()
+                    .max_by(|a, b| a.dot(dir).total_cmp(&b.dot(dir)))
+                    .unwrap_or_default();
+                furthest + dir * self.radius
+            })
+            .collect()
+    }
+}
+
+/// Every edge of a convex shape given by its corners. A single point or a line counts as its own
+/// edge.
+fn edges(corners: &[Vec2]) -> Vec<(Vec2, Vec2)> {
+    match corners.len() {
+        0 => Vec::new(),
+        1 => vec![(corners[0], corners[0])],
+        2 => vec![(corners[0], corners[1])],
+        n => (0..n)
+            .map(|idx| (corners[idx], corners[(idx + 1) % n]))
+            .collect(),
+    }
+}
+
+/// Whether `point` is inside the convex polygon with these corners. Points and lines don't have
+/// an inside.
+fn contains(corners: &[Vec2], point: Vec2) -> bool {
+    if corners.len() < 3 {
+        return false;
+    }
+    let sides: Vec<f32> = edges(corners)
+        .into_iter()
+        .map(|(start, end)| (end - start).perp_dot(point - start))
+        .collect();
+    sides.iter().all(|side| *side >= 0.) || sides.iter().all(|side| *side <= 0.)
+}
+
+fn point_segment_distance(point: Vec2, (start, end): (Vec2, Vec2)) -> f32 {
+    let along = end - start;
+    let t = if along.length_squared() > 0. {
+        ((point - start).dot(along) / along.length_squared()).clamp(0., 1.)
+    } else {
+        0.
+    };
+    point.distance(start + along * t)
+}
+
+fn segment_distance(a: (Vec2, Vec2), b: (Vec2, Vec2)) -> f32 {
+    // two segments cross when each one's ends are on opposite sides of the other
+    let side = |(start, end): (Vec2, Vec2), point: Vec2| (end - start).perp_dot(point - start);
+    let crosses = |a: (Vec2, Vec2), b: (Vec2, Vec2)| side(a, b.0) * side(a, b.1) < 0.;
+    if crosses(a, b) && crosses(b, a) {
+        return 0.;
+    }
+    point_segment_distance(a.0, b)
+        .min(point_segment_distance(a.1, b))
+        .min(point_segment_distance(b.0, a))
+        .min(point_segment_distance(b.1, a))
+}
+
+/// How far apart two convex shapes are, or 0 if they overlap. If they do, either one is inside
+/// the other or their edges cross.
+fn hull_distance(a: &[Vec2], b: &[Vec2]) -> f32 {
+    match (a.first(), b.first()) {
+        (Some(a_corner), Some(b_corner)) => {
+            if contains(a, *b_corner) || contains(b, *a_corner) {
+                return 0.;
+            }
+        }
+        _ => return f32::INFINITY,
+    }
+    let b_edges = edges(b);
+    edges(a)
+        .into_iter()
+        .flat_map(|a_edge| {
+            b_edges
+                .iter()
+                .map(move |b_edge| segment_distance(a_edge, *b_edge))
+        })
+        .fold(f32::INFINITY, f32::min)
+}
+
+/// The collider of each kind of body, loaded from `assets/colliders.ron`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ColliderShapes {
+    pub ship: Collider,
+    pub missile: Collider,
+    pub planet: Collider,
+}
+
+impl ColliderShapes {
+    /// The shapes from `assets/colliders.ron`, which are only read the first time they're needed.
+    /// The bundles get their colliders from here.
+    pub fn loaded() -> &'static Self {
+        static SHAPES: OnceLock<ColliderShapes> = OnceLock::new();
+        SHAPES.get_or_init(|| match ron::from_str(COLLIDER_SHAPES) {
+            Ok(shapes) => shapes,
+            Err(e) => {
+                warn!(
+                    "couldn't read assets/colliders.ron, hit testing with circles\n{}",
+                    e
+                );
+                Self {
+                    ship: Collider::default(),
+                    missile: Collider::default(),
+                    planet: Collider::default(),
+                }
+            }
+        })
+    }
+}
+
+impl Default for ColliderShapes {
+    fn default() -> Self {
+        Self::loaded().clone()
+    }
+}
+
+pub struct CollidersPlugin;
+
+impl Plugin for CollidersPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ColliderShapes>()
+            .add_system(attach_colliders);
+    }
+}
+
+/// Gives every ship, missile, planet and star the collider for its sprite, unless it was spawned
+/// with one already. The bundles all come with one, so this only catches bodies put together by
+/// hand.
+pub fn attach_colliders(
+    mut commands: Commands,
+    shapes: Res<ColliderShapes>,
+    bodies: Query<
+        (Entity, Option<&Ship>, Option<&Missile>),
+        (
+            Without<Collider>,
+            Or<(With<GravitySource>, With<Ship>, With<Missile>)>,
+        ),
+    >,
+) {
+    for (entity, ship, missile) in bodies.iter() {
+        let collider = if ship.is_some() {
+            &shapes.ship
+        } else if missile.is_some() {
+            &shapes.missile
+        } else {
+            &shapes.planet
+        };
+        commands.entity(entity).insert(collider.clone());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Corners of a square `half` out from `center` each way, going around anticlockwise
+    fn square(center: Vec2, half: f32) -> Vec<Vec2> {
+        [(-1., -1.), (1., -1.), (1., 1.), (-1., 1.)]
+            .into_iter()
+            .map(|(x, y)| center + Vec2::new(x, y) * half)
+            .collect()
+    }
+
+    #[test]
+    fn circle_against_capsule() {
+        // lying along the y axis from (0, -10) to (0, 10), 2 thick
+        let capsule = Collider::Capsule {
+            half_length: 10.,
+            radius: 2.,
+        }
+        .placed(Vec2::ZERO, Vec2::Y, &Size(1.));
+
+        // alongside the middle of it
+        assert!(PlacedCollider::circle(Vec2::new(4.9, 5.), 3.).overlaps(&capsule));
+        assert!(!PlacedCollider::circle(Vec2::new(5.1, 5.), 3.).overlaps(&capsule));
+        // off the end, where it's rounded rather than square
+        assert!(PlacedCollider::circle(Vec2::new(0., 14.9), 3.).overlaps(&capsule));
+        assert!(!PlacedCollider::circle(Vec2::new(4., 14.), 3.).overlaps(&capsule));
+    }
+
+    #[test]
+    fn circles_just_out_of_reach_dont_overlap() {
+        let circle = PlacedCollider::circle(Vec2::ZERO, 5.);
+        assert!(PlacedCollider::circle(Vec2::new(9.99, 0.), 5.).overlaps(&circle));
+        assert!(!PlacedCollider::circle(Vec2::new(10.01, 0.), 5.).overlaps(&circle));
+        assert!(!PlacedCollider::circle(Vec2::new(7.1, 7.1), 5.).overlaps(&circle));
+    }
+
+    #[test]
+    fn polygon_inside_another_polygon() {
+        let outer = square(Vec2::ZERO, 10.);
+        let inner = square(Vec2::new(2., -3.), 1.);
+        // none of the edges cross, so this only works out because one contains the other
+        assert!(contains(&outer, inner[0]));
+        assert!(!contains(&inner, outer[0]));
+        assert_eq!(hull_distance(&outer, &inner), 0.);
+        assert_eq!(hull_distance(&inner, &outer), 0.);
+    }
+
+    #[test]
+    fn crossing_edges() {
+        assert_eq!(
+            segment_distance(
+                (Vec2::new(-1., 0.), Vec2::new(1., 0.)),
+                (Vec2::new(0., -1.), Vec2::new(0., 1.))
+            ),
+            0.
+        );
+        assert_eq!(
+            segment_distance(
+                (Vec2::new(0., 0.), Vec2::new(1., 0.)),
+                (Vec2::new(0., 2.), Vec2::new(1., 2.))
+            ),
+            2.
+        );
+
+        // a plus sign: no corner of either bar is inside the other, but their edges cross
+        let across = vec![
+            Vec2::new(-10., -1.),
+            Vec2::new(10., -1.),
+            Vec2::new(10., 1.),
+            Vec2::new(-10., 1.),
+        ];
+        let down = vec![
+            Vec2::new(-1., -10.),
+            Vec2::new(1., -10.),
+            Vec2::new(1., 10.),
+            Vec2::new(-1., 10.),
+        ];
+        assert!(across.iter().all(|corner| !contains(&down, *corner)));
+        assert!(down.iter().all(|corner| !contains(&across, *corner)));
+        assert_eq!(hull_distance(&across, &down), 0.);
+    }
+
+    #[test]
+    fn corners_can_go_around_clockwise() {
+        let anticlockwise = square(Vec2::ZERO, 1.);
+        let clockwise: Vec<Vec2> = anticlockwise.iter().rev().copied().collect();
+
+        for corners in [&anticlockwise, &clockwise] {
+            assert!(contains(corners, Vec2::new(0.5, -0.5)));
+            assert!(!contains(corners, Vec2::new(1.5, 0.)));
+        }
+        let other = square(Vec2::new(5., 0.), 1.);
+        assert_eq!(hull_distance(&clockwise, &other), 3.);
+        assert_eq!(
+            hull_distance(&clockwise, &other),
+            hull_distance(&anticlockwise, &other)
+        );
+    }
+}
@@ -1,11 +1,11 @@
 use std::num::NonZeroU32;
 
-use bevy::{math::Vec2, prelude::Component};
+use bevy::{math::Vec2, prelude::{Component, Entity}};
 
 #[derive(Debug, Default, Clone, Copy, Component)]
 pub struct FPSCount;
 #[derive(Debug, Default, Clone, Copy, Component)]
-pub struct MissileCount;
+pub struct WarpDisplay;
 
 #[derive(Debug, Default, Clone, Component)]
 pub struct Position(pub Vec2);
@@ -32,17 +32,55 @@ pub struct EnginePhysics {
 
     // m/s^2
     pub current_accel: f32,
+
+    // rad/s
+    pub max_turn_rate: f32,
+
+    /// rad/s, positive is counterclockwise
+    pub current_turn_rate: f32,
 }
 
 impl std::default::Default for EnginePhysics {
     fn default() -> Self {
         Self {
             max_accel: 10.,
-            current_accel: 0.
+            current_accel: 0.,
+            max_turn_rate: 1.,
+            current_turn_rate: 0.,
         }
     }
 }
 
+impl EnginePhysics {
+    /// Sets the throttle and steering from control inputs between -1 and 1
+    pub fn set_controls(&mut self, throttle: f32, turn: f32) {
+        self.current_accel = throttle.clamp(-1., 1.) * self.max_accel;
+        self.current_turn_rate = turn.clamp(-1., 1.) * self.max_turn_rate;
+    }
+}
+
+/// Air around a planet, which slows down (and heats up) anything with [`Heat`] flying through it.
+/// It starts at the planet's surface and thins out exponentially with altitude.
+#[derive(Debug, Clone, Copy, PartialEq, Component)]
+pub struct Atmosphere {
+    /// Height over which the density drops by a factor of e
+    pub scale_height: f32,
+    pub surface_density: f32,
+}
+
+impl std::default::Default for Atmosphere {
+    fn default() -> Self {
+        Self {
+            scale_height: 10.,
+            surface_density: 1.,
+        }
+    }
+}
+
+/// How hot a ship or missile has got from flying through atmospheres, as a fraction of what it can
+/// take before burning up. Only things with this component feel atmospheric drag.
+#[derive(Debug, Default, Clone, Copy, Component)]
+pub struct Heat(pub f32);
 
 /// Component for entities that should be displayed at a certain size
 /// These circles should also have physics pos
@@ -62,6 +100,10 @@ pub struct Ship;
 #[derive(Debug, Default, Component)]
 pub struct Missile;
 
+/// The ship that fired a missile, so that its kills can be credited to the right ship
+#[derive(Debug, Default, Clone, Copy, Component)]
+pub struct FiredBy(pub Option<Entity>);
+
 #[derive(Debug, Default, Component)]
 pub struct Explosion;
 
@@ -71,6 +113,10 @@ pub struct Star;
 #[derive(Debug, Default, Component)]
 pub struct Planet;
 
+/// Wreckage of a destroyed ship. Its particle field goes away once every fragment has decayed.
+#[derive(Debug, Default, Component)]
+pub struct Debris;
+
 #[derive(Debug, Default, Component)]
 pub struct GravitySource;
 
@@ -79,7 +125,7 @@ pub struct Team(pub Option<NonZeroU32>);
 
 #[derive(Debug, Default, Component)]
 pub struct Lifespan {
-    /// Seconds since program startup that this component was created on
+    /// Simulated seconds since startup (see `SimulationTime`) that this component was created on
     pub created_on: f64,
 
     /// Number of seconds for which this item should be alive
@@ -91,3 +137,30 @@ pub struct AnimateOnce;
 
 #[derive(Debug, Clone, Copy, Component)]
 pub struct ShipCamera;
+
+/// How many missiles a ship has left to fire, or `None` if it can keep firing forever
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Component)]
+pub struct Magazine(pub Option<u32>);
+
+impl Magazine {
+    /// Takes a missile out to be fired, if there are any left
+    pub fn take(&mut self) -> bool {
+        match &mut self.0 {
+            None => true,
+            Some(0) => false,
+            Some(remaining) => {
+                *remaining -= 1;
+                true
+            }
+        }
+    }
+}
+
+/// Marks the ship that is controlled by whoever is sitting in front of this app
+#[derive(Debug, Default, Clone, Copy, Component)]
+pub struct LocalPlayer;
+
+/// Identifies a body across the network -- the server hands these out and clients use them to
+/// match snapshot entries up with their own entities
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Component)]
+pub struct NetId(pub u32);
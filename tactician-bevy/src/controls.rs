@@ -0,0 +1,411 @@
+use std::collections::{BTreeMap, HashMap};
+
+use bevy::input::InputSystem;
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::settings::{read_config, write_config};
+
+/// Something the player can do, independent of which key/button/stick they do it with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum Action {
+    Thrust,
+    Brake,
+    RotateLeft,
+    RotateRight,
+    Fire,
+    ZoomIn,
+    ZoomOut,
+    Pause,
+    /// Point the camera at the next ship/planet/missile
+    NextTarget,
+    PreviousTarget,
+    /// Stop following anything, so the camera can be dragged around with the mouse
+    FreeCamera,
+    /// Zoom out until everything is in view
+    FitAll,
+    /// Toggles between the fixed frame and the rotating frame of the nearest planet
+    RotatingFrame,
+    /// Toggles between the fixed frame and one that moves along with the center of mass of the
+    /// stars and planets
+    CenterOfMassFrame,
+    WarpFaster,
+    WarpSlower,
+    /// Shows the Lagrange points of the planet the camera is looking at
+    LagrangeOverlay,
+    /// Switches which frame the snail trails are drawn in
+    TrailFrame,
+    /// Shows the hitbox of everything
+    ColliderOverlay,
+    /// Shows the velocity of everything that moves, and the accelerations acting on it
+    VectorOverlay,
+    /// Shows how well energy and momentum are being conserved
+    ConservationGraph,
+}
+
+impl Action {
+    pub const ALL: [Action; 21] = [
+        Action::Thrust,
+        Action::Brake,
+        Action::RotateLeft,
+        Action::RotateRight,
+        Action::Fire,
+        Action::ZoomIn,
+        Action::ZoomOut,
+        Action::Pause,
+        Action::NextTarget,
+        Action::PreviousTarget,
+        Action::FreeCamera,
+        Action::FitAll,
+        Action::RotatingFrame,
+        Action::CenterOfMassFrame,
+        Action::WarpFaster,
+        Action::WarpSlower,
+        Action::LagrangeOverlay,
+        Action::TrailFrame,
+        Action::ColliderOverlay,
+        Action::VectorOverlay,
+        Action::ConservationGraph,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Action::Thrust => "Thrust",
+            Action::Brake => "Brake",
+            Action::RotateLeft => "Rotate left",
+            Action::RotateRight => "Rotate right",
+            Action::Fire => "Fire",
+            Action::ZoomIn => "Zoom in",
+            Action::ZoomOut => "Zoom out",
+            Action::Pause => "Pause",
+            Action::NextTarget => "Next camera target",
+            Action::PreviousTarget => "Previous camera target",
+            Action::FreeCamera => "Free camera",
+            Action::FitAll => "Show everything",
+            Action::RotatingFrame => "Rotating frame",
+            Action::CenterOfMassFrame => "Center of mass frame",
+            Action::WarpFaster => "Speed up time",
+            Action::WarpSlower => "Slow down time",
+            Action::LagrangeOverlay => "Lagrange points",
+            Action::TrailFrame => "Trail frame",
+            Action::ColliderOverlay => "Hitboxes",
+            Action::VectorOverlay => "Force vectors",
+            Action::ConservationGraph => "Conservation graph",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Binding {
+    Key(KeyCode),
+    GamepadButton(GamepadButtonType),
+    /// One direction of a stick, e.g `positive: false` on `LeftStickY` is pushing the left stick down
+    GamepadAxis {
+        axis: GamepadAxisType,
+        positive: bool,
+    },
+}
+
+impl Binding {
+    fn is_gamepad(&self) -> bool {
+        !matches!(self, Binding::Key(_))
+    }
+
+    pub fn describe(&self) -> String {
+        match self {
+            Binding::Key(key) => format!("{:?}", key),
+            Binding::GamepadButton(button) => format!("Pad {:?}", button),
+            Binding::GamepadAxis { axis, positive } => {
+                format!("Pad {:?}{}", axis, if *positive { "+" } else { "-" })
+            }
+        }
+    }
+}
+
+/// Which bindings trigger each action. Loaded from (and saved to) [`InputBindings::CONFIG_PATH`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InputBindings(pub BTreeMap<Action, Vec<Binding>>);
+
+impl Default for InputBindings {
+    fn default() -> Self {
+        let key = Binding::Key;
+        let pad = Binding::GamepadButton;
+        let stick = |axis, positive| Binding::GamepadAxis { axis, positive };
+
+        InputBindings(BTreeMap::from([
+            (
+                Action::Thrust,
+                vec![
+                    key(KeyCode::Up),
+                    key(KeyCode::W),
+                    pad(GamepadButtonType::RightTrigger2),
+                ],
+            ),
+            (
+                Action::Brake,
+                vec![
+                    key(KeyCode::Down),
+                    key(KeyCode::S),
+                    pad(GamepadButtonType::LeftTrigger2),
+                ],
+            ),
+            (
+                Action::RotateLeft,
+                vec![
+                    key(KeyCode::Left),
+                    key(KeyCode::A),
+                    stick(GamepadAxisType::LeftStickX, false),
+                ],
+            ),
+            (
+                Action::RotateRight,
+                vec![
+                    key(KeyCode::Right),
+                    key(KeyCode::D),
+                    stick(GamepadAxisType::LeftStickX, true),
+                ],
+            ),
+            (
+                Action::Fire,
+                vec![key(KeyCode::Space), pad(GamepadButtonType::South)],
+            ),
+            (
+                Action::ZoomIn,
+                vec![key(KeyCode::Equals), pad(GamepadButtonType::DPadUp)],
+            ),
+            (
+                Action::ZoomOut,
+                vec![key(KeyCode::Minus), pad(GamepadButtonType::DPadDown)],
+            ),
+            (
+                Action::Pause,
+                vec![key(KeyCode::Escape), pad(GamepadButtonType::Start)],
+            ),
+            (
+                Action::NextTarget,
+                vec![key(KeyCode::Tab), pad(GamepadButtonType::RightTrigger)],
+            ),
+            (
+                Action::PreviousTarget,
+                vec![key(KeyCode::Q), pad(GamepadButtonType::LeftTrigger)],
+            ),
+            (
+                Action::FreeCamera,
+                vec![key(KeyCode::F), pad(GamepadButtonType::North)],
+            ),
+            (
+                Action::FitAll,
+                vec![key(KeyCode::V), pad(GamepadButtonType::West)],
+            ),
+            (
+                Action::RotatingFrame,
+                vec![key(KeyCode::R), pad(GamepadButtonType::East)],
+            ),
+            (
+                Action::CenterOfMassFrame,
+                vec![key(KeyCode::B), pad(GamepadButtonType::Select)],
+            ),
+            (
+                Action::WarpFaster,
+                vec![key(KeyCode::Period), pad(GamepadButtonType::DPadRight)],
+            ),
+            (
+                Action::WarpSlower,
+                vec![key(KeyCode::Comma), pad(GamepadButtonType::DPadLeft)],
+            ),
+            (Action::LagrangeOverlay, vec![key(KeyCode::L)]),
+            (Action::TrailFrame, vec![key(KeyCode::T)]),
+            (Action::ColliderOverlay, vec![key(KeyCode::H)]),
+            (Action::VectorOverlay, vec![key(KeyCode::G)]),
+            (Action::ConservationGraph, vec![key(KeyCode::C)]),
+        ]))
+    }
+}
+
+impl InputBindings {
+    pub const CONFIG_PATH: &'static str = "bindings.ron";
+
+    /// Reads the bindings saved last time (see [`read_config`]), falling back to the defaults if
+    /// there aren't any. Actions that didn't exist yet when they were saved get their default
+    /// bindings.
+    pub fn load() -> Self {
+        match read_config(Self::CONFIG_PATH) {
+            Ok(Some(contents)) => match ron::from_str::<InputBindings>(&contents) {
+                Ok(mut bindings) => {
+                    for (action, defaults) in InputBindings::default().0 {
+                        bindings.0.entry(action).or_insert(defaults);
+                    }
+                    return bindings;
+                }
+                Err(e) => warn!("ignoring malformed {}: {}", Self::CONFIG_PATH, e),
+            },
+            Ok(None) => {}
+            Err(e) => warn!("unable to read {}: {}", Self::CONFIG_PATH, e),
+        }
+
+        InputBindings::default()
+    }
+
+    pub fn save(&self) {
+        let result = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())
+            .map_err(|e| e.to_string())
+            .and_then(|contents| write_config(Self::CONFIG_PATH, &contents));
+        if let Err(e) = result {
+            warn!("unable to save {}: {}", Self::CONFIG_PATH, e);
+        }
+    }
+
+    pub fn get(&self, action: Action) -> &[Binding] {
+        self.0.get(&action).map(Vec::as_slice).unwrap_or(&[])
+    }
+
+    /// Makes `binding` trigger `action`, replacing whatever binding `action` had on the same kind
+    /// of device (keyboard or gamepad). The binding is taken away from any other action that had it.
+    pub fn rebind(&mut self, action: Action, binding: Binding) {
+        for bindings in self.0.values_mut() {
+            bindings.retain(|b| *b != binding);
+        }
+
+        let bindings = self.0.entry(action).or_default();
+        match bindings
+            .iter()
+            .position(|b| b.is_gamepad() == binding.is_gamepad())
+        {
+            Some(idx) => bindings[idx] = binding,
+            None => bindings.push(binding),
+        }
+    }
+}
+
+/// How much each action is currently being asked for, between 0 and 1.
+/// Buttons are either 0 or 1, analog triggers and sticks can be anywhere in between.
+#[derive(Debug, Default)]
+pub struct ActionState {
+    values: HashMap<Action, f32>,
+    previous: HashMap<Action, f32>,
+}
+
+impl ActionState {
+    pub fn value(&self, action: Action) -> f32 {
+        self.values.get(&action).copied().unwrap_or(0.)
+    }
+
+    pub fn pressed(&self, action: Action) -> bool {
+        self.value(action) > 0.5
+    }
+
+    pub fn just_pressed(&self, action: Action) -> bool {
+        self.pressed(action) && self.previous.get(&action).copied().unwrap_or(0.) <= 0.5
+    }
+
+    /// Analog throttle between -1 (full brake) and 1 (full thrust)
+    pub fn throttle(&self) -> f32 {
+        self.value(Action::Thrust) - self.value(Action::Brake)
+    }
+
+    /// Steering between -1 (clockwise) and 1 (counterclockwise)
+    pub fn turn(&self) -> f32 {
+        self.value(Action::RotateLeft) - self.value(Action::RotateRight)
+    }
+}
+
+/// Turns raw keyboard/gamepad input into [`ActionState`] using the player's [`InputBindings`]
+pub struct ControlsPlugin;
+
+impl Plugin for ControlsPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(InputBindings::load())
+            .init_resource::<ActionState>()
+            .add_system_to_stage(CoreStage::PreUpdate, update_action_state.after(InputSystem));
+    }
+}
+
+fn update_action_state(
+    bindings: Res<InputBindings>,
+    mut actions: ResMut<ActionState>,
+    keys: Res<Input<KeyCode>>,
+    gamepads: Res<Gamepads>,
+    gamepad_buttons: Res<Input<GamepadButton>>,
+    gamepad_button_axes: Res<Axis<GamepadButton>>,
+    gamepad_axes: Res<Axis<GamepadAxis>>,
+) {
+    let actions = &mut *actions;
+    std::mem::swap(&mut actions.values, &mut actions.previous);
+    actions.values.clear();
+
+    for action in Action::ALL {
+        let value = bindings
+            .get(action)
+            .iter()
+            .map(|binding| match *binding {
+                Binding::Key(key) => {
+                    if keys.pressed(key) {
+                        1.
+                    } else {
+                        0.
+                    }
+                }
+                Binding::GamepadButton(button_type) => gamepads
+                    .iter()
+                    .map(|gamepad| {
+                        let button = GamepadButton(*gamepad, button_type);
+                        // triggers are analog, everything else just reports pressed or not
+                        gamepad_button_axes.get(button).unwrap_or_else(|| {
+                            if gamepad_buttons.pressed(button) {
+                                1.
+                            } else {
+                                0.
+                            }
+                        })
+                    })
+                    .fold(0., f32::max),
+                Binding::GamepadAxis { axis, positive } => gamepads
+                    .iter()
+                    .filter_map(|gamepad| gamepad_axes.get(GamepadAxis(*gamepad, axis)))
+                    .map(|value| if positive { value } else { -value })
+                    .fold(0., f32::max),
+            })
+            .fold(0., f32::max);
+
+        actions.values.insert(action, value.clamp(0., 1.));
+    }
+}
+
+/// Returns the first binding the player is pressing right now, if any.
+/// Used by the rebinding screen to find out what the player wants to bind an action to.
+pub fn detect_binding(
+    keys: &Input<KeyCode>,
+    gamepads: &Gamepads,
+    gamepad_buttons: &Input<GamepadButton>,
+    gamepad_axes: &Axis<GamepadAxis>,
+) -> Option<Binding> {
+    const AXES: [GamepadAxisType; 6] = [
+        GamepadAxisType::LeftStickX,
+        GamepadAxisType::LeftStickY,
+        GamepadAxisType::LeftZ,
+        GamepadAxisType::RightStickX,
+        GamepadAxisType::RightStickY,
+        GamepadAxisType::RightZ,
+    ];
+
+    if let Some(key) = keys.get_just_pressed().next() {
+        return Some(Binding::Key(*key));
+    }
+    if let Some(GamepadButton(_, button_type)) = gamepad_buttons.get_just_pressed().next() {
+        return Some(Binding::GamepadButton(*button_type));
+    }
+    for gamepad in gamepads.iter() {
+        for axis in AXES {
+            match gamepad_axes.get(GamepadAxis(*gamepad, axis)) {
+                Some(value) if value.abs() > 0.6 => {
+                    return Some(Binding::GamepadAxis {
+                        axis,
+                        positive: value > 0.,
+                    })
+                }
+                _ => {}
+            }
+        }
+    }
+    None
+}
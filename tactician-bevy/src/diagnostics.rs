@@ -0,0 +1,198 @@
+use bevy::diagnostic::{Diagnostic, DiagnosticId, Diagnostics};
+use bevy::math::DVec2;
+use bevy::prelude::*;
+
+use crate::components::*;
+use crate::physics::{PhysicsSystem, SimulationAuthority, SimulationTime, G};
+
+/// Frames of history kept for each diagnostic, which is also how much the HUD graph shows
+const HISTORY: usize = 300;
+
+/// How well the simulation conserves energy and momentum, which it would do exactly if the
+/// integrator were perfect. Only stars and planets are counted: ships and missiles get pushed
+/// around by their engines and come and go, which would swamp any drift from the integrator.
+pub struct ConservationDiagnosticsPlugin;
+
+impl ConservationDiagnosticsPlugin {
+    /// Kinetic plus potential energy
+    pub const ENERGY: DiagnosticId =
+        DiagnosticId::from_u128(0x5b1f_44d2_9a0e_4c27_8f3b_61d0_e2a4_0001);
+    /// Length of the total linear momentum
+    pub const MOMENTUM: DiagnosticId =
+        DiagnosticId::from_u128(0x5b1f_44d2_9a0e_4c27_8f3b_61d0_e2a4_0002);
+    /// Angular momentum around the origin, positive being counterclockwise
+    pub const ANGULAR_MOMENTUM: DiagnosticId =
+        DiagnosticId::from_u128(0x5b1f_44d2_9a0e_4c27_8f3b_61d0_e2a4_0003);
+    /// How far the energy has wandered since the start of the match, as a percentage of what it was
+    pub const ENERGY_DRIFT: DiagnosticId =
+        DiagnosticId::from_u128(0x5b1f_44d2_9a0e_4c27_8f3b_61d0_e2a4_0004);
+    /// How far the momentum has wandered since the start of the match. This is measured against
+    /// the sum of the bodies' own momenta, since the total is usually close to zero.
+    pub const MOMENTUM_DRIFT: DiagnosticId =
+        DiagnosticId::from_u128(0x5b1f_44d2_9a0e_4c27_8f3b_61d0_e2a4_0005);
+    /// How far the angular momentum has wandered since the start of the match, as a percentage of
+    /// what it was
+    pub const ANGULAR_MOMENTUM_DRIFT: DiagnosticId =
+        DiagnosticId::from_u128(0x5b1f_44d2_9a0e_4c27_8f3b_61d0_e2a4_0006);
+
+    pub const DRIFTS: [DiagnosticId; 3] = [
+        Self::ENERGY_DRIFT,
+        Self::MOMENTUM_DRIFT,
+        Self::ANGULAR_MOMENTUM_DRIFT,
+    ];
+
+    pub const ALL: [DiagnosticId; 6] = [
+        Self::ENERGY,
+        Self::MOMENTUM,
+        Self::ANGULAR_MOMENTUM,
+        Self::ENERGY_DRIFT,
+        Self::MOMENTUM_DRIFT,
+        Self::ANGULAR_MOMENTUM_DRIFT,
+    ];
+}
+
+impl Plugin for ConservationDiagnosticsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ConservationBaseline>()
+            .add_startup_system(setup_conservation_diagnostics)
+            .add_system(measure_conservation.after(PhysicsSystem::Integrate));
+    }
+}
+
+/// Totals of the stars and planets at a moment in time. These are added up in double precision,
+/// since the kinetic and potential energy mostly cancel out.
+#[derive(Debug, Clone, Copy)]
+pub struct ConservedQuantities {
+    pub energy: f64,
+    pub momentum: DVec2,
+    pub angular_momentum: f64,
+    /// Sum of how much momentum each body has on its own
+    pub momentum_scale: f64,
+}
+
+impl ConservedQuantities {
+    /// Takes the position, velocity and mass of every source. Sources that don't have a velocity
+    /// are pinned in place, so they count towards the potential energy but can't have any kinetic
+    /// energy or momentum.
+    fn measure(sources: &[(Vec2, Option<Vec2>, f32)]) -> Self {
+        let mut quantities = Self {
+            energy: 0.,
+            momentum: DVec2::ZERO,
+            angular_momentum: 0.,
+            momentum_scale: 0.,
+        };
+        for (idx, (pos, vel, mass)) in sources.iter().enumerate() {
+            let (pos, mass) = (pos.as_dvec2(), *mass as f64);
+            let vel = vel.unwrap_or(Vec2::ZERO).as_dvec2();
+            quantities.energy += 0.5 * mass * vel.length_squared();
+            quantities.momentum += vel * mass;
+            quantities.angular_momentum += mass * pos.perp_dot(vel);
+            quantities.momentum_scale += mass * vel.length();
+
+            // every pair once
+            for (other_pos, _, other_mass) in sources.iter().skip(idx + 1) {
+                let distance = pos.distance(other_pos.as_dvec2());
+                if distance > f64::EPSILON {
+                    quantities.energy -= G as f64 * mass * *other_mass as f64 / distance;
+                }
+            }
+        }
+        quantities
+    }
+}
+
+/// What the conserved quantities were at the start of the match, which the drifts are measured
+/// from. Gets taken the first time they're measured after a reset.
+#[derive(Debug, Default)]
+pub struct ConservationBaseline(pub Option<ConservedQuantities>);
+
+fn setup_conservation_diagnostics(mut diagnostics: ResMut<Diagnostics>) {
+    for (id, name, suffix) in [
+        (ConservationDiagnosticsPlugin::ENERGY, "energy", ""),
+        (ConservationDiagnosticsPlugin::MOMENTUM, "momentum", ""),
+        (
+            ConservationDiagnosticsPlugin::ANGULAR_MOMENTUM,
+            "angular_momentum",
+            "",
+        ),
+        (
+            ConservationDiagnosticsPlugin::ENERGY_DRIFT,
+            "energy_drift",
+            "%",
+        ),
+        (
+            ConservationDiagnosticsPlugin::MOMENTUM_DRIFT,
+            "momentum_drift",
+            "%",
+        ),
+        (
+            ConservationDiagnosticsPlugin::ANGULAR_MOMENTUM_DRIFT,
+            "angular_momentum_drift",
+            "%",
+        ),
+    ] {
+        diagnostics.add(Diagnostic::new(id, name, HISTORY).with_suffix(suffix));
+    }
+}
+
+pub fn reset_conservation_baseline(mut baseline: ResMut<ConservationBaseline>) {
+    baseline.0 = None;
+}
+
+/// Measures the totals every frame that the simulation moved forward. Network clients don't run
+/// the simulation, so there's nothing of theirs to check.
+pub fn measure_conservation(
+    authority: Res<SimulationAuthority>,
+    sim_time: Res<SimulationTime>,
+    mut baseline: ResMut<ConservationBaseline>,
+    mut diagnostics: ResMut<Diagnostics>,
+    sources: Query<(&Position, Option<&Velocity>, &Mass), With<GravitySource>>,
+) {
+    if *authority != SimulationAuthority::Local || sim_time.delta <= 0. || sources.is_empty() {
+        return;
+    }
+
+    let sources: Vec<(Vec2, Option<Vec2>, f32)> = sources
+        .iter()
+        .map(|(Position(pos), vel, Mass(mass))| (*pos, vel.map(|v| v.0), *mass))
+        .collect();
+    let now = ConservedQuantities::measure(&sources);
+    let start = *baseline.0.get_or_insert(now);
+    // as a percentage, and left at zero when there was nothing to drift from
+    let drift = |change: f64, scale: f64| {
+        if scale.abs() > f64::EPSILON {
+            100. * change / scale.abs()
+        } else {
+            0.
+        }
+    };
+
+    for (id, value) in [
+        (ConservationDiagnosticsPlugin::ENERGY, now.energy),
+        (
+            ConservationDiagnosticsPlugin::MOMENTUM,
+            now.momentum.length(),
+        ),
+        (
+            ConservationDiagnosticsPlugin::ANGULAR_MOMENTUM,
+            now.angular_momentum,
+        ),
+        (
+            ConservationDiagnosticsPlugin::ENERGY_DRIFT,
+            drift(now.energy - start.energy, start.energy),
+        ),
+        (
+            ConservationDiagnosticsPlugin::MOMENTUM_DRIFT,
+            drift(now.momentum.distance(start.momentum), start.momentum_scale),
+        ),
+        (
+            ConservationDiagnosticsPlugin::ANGULAR_MOMENTUM_DRIFT,
+            drift(
+                now.angular_momentum - start.angular_momentum,
+                start.angular_momentum,
+            ),
+        ),
+    ] {
+        diagnostics.add_measurement(id, value);
+    }
+}
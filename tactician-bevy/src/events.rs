@@ -1,6 +1,8 @@
 use crate::components::{Position, Team, Velocity};
+use bevy::prelude::Entity;
 
 pub struct SpawnMissileFromShip {
+    pub ship: Entity,
     pub position: Position,
     pub velocity: Velocity,
     pub team: Team,
@@ -13,3 +15,15 @@ pub struct CreateExplosionEvent {
     pub velocity: Velocity
 }
 
+
+#[derive(Debug)]
+pub struct ShipDestroyedEvent {
+    pub ship: Entity,
+    pub team: Team,
+    pub killer_team: Team,
+    /// The ship whose missile did it, if it was a missile
+    pub killer: Option<Entity>,
+    /// Where the ship was (and how fast it was going) when it was destroyed
+    pub position: Position,
+    pub velocity: Velocity,
+}
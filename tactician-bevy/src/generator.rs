@@ -0,0 +1,932 @@
+use std::f32::consts::TAU;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::bundles::*;
+use crate::components::Size;
+use crate::components::*;
+use crate::particles::ParticleField;
+use crate::physics::{barycenter, hill_radius, G};
+
+/// Planets are placed with their semi-major axes somewhere in this range
+const INNERMOST_ORBIT: f32 = 250.;
+const OUTERMOST_ORBIT: f32 = 2000.;
+
+/// Planet masses, as a fraction of their star's
+const PLANET_MASS_RATIO: (f32, f32) = (1e-3, 5e-2);
+/// Moon masses, as a fraction of their planet's
+const MOON_MASS_RATIO: (f32, f32) = (1e-4, 1e-2);
+/// Moons orbit somewhere in this fraction of their planet's Hill radius. Much further out than
+/// about half of it and the star pulls them away.
+const MOON_HILL_FRACTION: (f32, f32) = (0.15, 0.4);
+
+/// Gaps narrower than this don't get an asteroid belt (or the players' ships) put in them
+const MIN_GAP_WIDTH: f32 = 100.;
+
+/// Planets that don't fit anywhere are retried this many times before giving up on them
+const MAX_ATTEMPTS: u32 = 50;
+
+/// How much lighter the second star of a generated binary is than the first
+const BINARY_MASS_RATIO: (f32, f32) = (0.3, 1.);
+
+/// Small, fast random number generator (SplitMix64). We use our own rather than `rand` so that a
+/// seed always gives the same system, whatever the platform or version of `rand`.
+#[derive(Debug, Clone)]
+pub struct SeededRng(u64);
+
+impl SeededRng {
+    pub fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Uniformly distributed between 0 (inclusive) and 1 (exclusive)
+    pub fn next_f32(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    pub fn range(&mut self, min: f32, max: f32) -> f32 {
+        min + (max - min) * self.next_f32()
+    }
+
+    /// Spread evenly over orders of magnitude, which suits things like masses
+    pub fn log_range(&mut self, min: f32, max: f32) -> f32 {
+        self.range(min.ln(), max.ln()).exp()
+    }
+
+    pub fn chance(&mut self, probability: f32) -> bool {
+        self.next_f32() < probability
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GeneratorSettings {
+    pub seed: u64,
+    pub max_planets: u32,
+    /// Chance of the planets going around a close pair of stars rather than a single one
+    pub binary_chance: f32,
+    /// Chance of each planet getting a moon
+    pub moon_chance: f32,
+    pub atmosphere_chance: f32,
+    pub belt_chance: f32,
+    pub max_eccentricity: f32,
+    /// Seconds that the system should stay stable for. Longer horizons space the planets
+    /// further apart.
+    pub stability_horizon: f32,
+}
+
+impl Default for GeneratorSettings {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            max_planets: 6,
+            binary_chance: 0.25,
+            moon_chance: 0.5,
+            atmosphere_chance: 0.5,
+            belt_chance: 0.6,
+            max_eccentricity: 0.15,
+            stability_horizon: 3600.,
+        }
+    }
+}
+
+/// Where the star system for a match comes from
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum StarSystem {
+    /// The hand-made ring of five planets
+    Classic,
+    /// Two stars with planets going around the pair of them
+    Binary,
+    /// A close binary with a third star going around it, each with planets of their own
+    Triple,
+    Generated(GeneratorSettings),
+}
+
+impl StarSystem {
+    /// Looks up one of the hand-made systems by the name used on the command line
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "classic" => Some(Self::Classic),
+            "binary" => Some(Self::Binary),
+            "triple" => Some(Self::Triple),
+            _ => None,
+        }
+    }
+
+    /// Lays out the bodies and spawn points for this system
+    pub fn build(&self, teams: &[Team]) -> GeneratedSystem {
+        match self {
+            Self::Classic => classic_system(teams),
+            Self::Binary => binary_system(teams),
+            Self::Triple => triple_system(teams),
+            Self::Generated(settings) => generate_system(settings, teams),
+        }
+    }
+}
+
+impl Default for StarSystem {
+    fn default() -> Self {
+        Self::Classic
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GeneratedKind {
+    Star,
+    Planet,
+    Moon,
+    Asteroid,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GeneratedBody {
+    pub kind: GeneratedKind,
+    pub position: Vec2,
+    pub velocity: Vec2,
+    pub mass: f32,
+    pub size: f32,
+    pub atmosphere: Option<Atmosphere>,
+}
+
+/// Where (and how fast) a team's ships start out
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpawnPoint {
+    pub team: Team,
+    pub position: Vec2,
+    pub velocity: Vec2,
+}
+
+/// Spawn points for the match that is being played
+#[derive(Debug, Default)]
+pub struct SpawnPoints(pub Vec<SpawnPoint>);
+
+impl SpawnPoints {
+    pub fn for_team(&self, team: &Team) -> Option<&SpawnPoint> {
+        self.0.iter().find(|point| point.team == *team)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GeneratedSystem {
+    pub bodies: Vec<GeneratedBody>,
+    pub spawn_points: Vec<SpawnPoint>,
+}
+
+impl GeneratedSystem {
+    /// Moves everything so that the center of mass sits still at the origin. Otherwise the whole
+    /// system slowly drifts off, now that the stars move too.
+    pub fn recenter(&mut self) {
+        let (center, center_vel, _) = barycenter(
+            self.bodies
+                .iter()
+                .map(|body| (body.position, body.velocity, body.mass)),
+        );
+        for body in self.bodies.iter_mut() {
+            body.position -= center;
+            body.velocity -= center_vel;
+        }
+        for spawn in self.spawn_points.iter_mut() {
+            spawn.position -= center;
+            spawn.velocity -= center_vel;
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Orbit {
+    semi_major: f32,
+    eccentricity: f32,
+    mass: f32,
+}
+
+impl Orbit {
+    fn periapsis(&self) -> f32 {
+        self.semi_major * (1. - self.eccentricity)
+    }
+
+    fn apoapsis(&self) -> f32 {
+        self.semi_major * (1. + self.eccentricity)
+    }
+}
+
+/// How many mutual Hill radii apart neighbouring planets need to be to survive `orbits` orbits
+/// of the inner one. A rough fit to Chambers et al. (1996), and never less than the 2√3 needed
+/// for two planets to be stable forever.
+fn required_hill_spacing(orbits: f32) -> f32 {
+    ((orbits.max(1.).log10() + 0.36) / 0.76).max(2. * 3f32.sqrt())
+}
+
+/// Whether two planets stay far enough apart, even with the inner one at its furthest out and
+/// the outer one at its closest in
+fn well_separated(a: &Orbit, b: &Orbit, star_mass: f32, spacing: f32) -> bool {
+    let (inner, outer) = if a.semi_major < b.semi_major {
+        (a, b)
+    } else {
+        (b, a)
+    };
+    let mutual_hill = ((inner.mass + outer.mass) / (3. * star_mass)).cbrt()
+        * (inner.semi_major + outer.semi_major)
+        / 2.;
+    outer.periapsis() - inner.apoapsis() >= spacing * mutual_hill
+}
+
+/// Closest that a planet can orbit around both stars of a binary `separation` apart, where
+/// `mass_ratio` is the lighter star's share of their total mass. Fit from Holman & Wiegert (1999).
+fn circumbinary_limit(separation: f32, eccentricity: f32, mass_ratio: f32) -> f32 {
+    let (e, mu) = (eccentricity, mass_ratio);
+    (1.60 + 5.10 * e - 2.22 * e * e + 4.12 * mu - 4.27 * e * mu - 5.09 * mu * mu
+        + 4.61 * e * e * mu * mu)
+        * separation
+}
+
+/// Furthest that a planet can orbit around one star of a binary `separation` apart before the
+/// other one pulls it away, where `mass_ratio` is the other star's share of their total mass.
+/// Also from Holman & Wiegert (1999).
+fn s_type_limit(separation: f32, eccentricity: f32, mass_ratio: f32) -> f32 {
+    let (e, mu) = (eccentricity, mass_ratio);
+    (0.464 - 0.380 * mu - 0.631 * e + 0.586 * mu * e + 0.150 * e * e - 0.198 * mu * e * e)
+        * separation
+}
+
+/// A star (or anything else) on a circular orbit around `center`, as (position, velocity)
+fn circular_orbit(
+    center: (Vec2, Vec2),
+    central_mass: f32,
+    radius: f32,
+    angle: f32,
+    direction: f32,
+) -> (Vec2, Vec2) {
+    let dir = Vec2::from(angle.sin_cos());
+    let speed = (G * central_mass / radius).sqrt();
+    (
+        center.0 + dir * radius,
+        center.1 + dir.perp() * speed * direction,
+    )
+}
+
+/// Two stars on circular orbits around their common center of mass, which sits still at
+/// `center`
+fn binary_stars(
+    center: Vec2,
+    masses: (f32, f32),
+    separation: f32,
+    angle: f32,
+    direction: f32,
+) -> [GeneratedBody; 2] {
+    let total = masses.0 + masses.1;
+    let dir = Vec2::from(angle.sin_cos());
+    // each star goes around the center of mass at a distance (and speed) inversely proportional
+    // to its mass
+    let relative_speed = (G * total / separation).sqrt();
+    let star = |mass: f32, other_mass: f32, side: f32| GeneratedBody {
+        kind: GeneratedKind::Star,
+        position: center + dir * side * separation * other_mass / total,
+        velocity: dir.perp() * side * relative_speed * other_mass / total * direction,
+        mass,
+        size: star_size(mass),
+        atmosphere: None,
+    };
+    [star(masses.0, masses.1, 1.), star(masses.1, masses.0, -1.)]
+}
+
+fn star_size(mass: f32) -> f32 {
+    1.5 * (mass / 3e15).cbrt()
+}
+
+/// Every team starts at the same distance from `center` and evenly spread around it, so nobody
+/// gets an easier start than anybody else
+fn ring_spawn_points(
+    teams: &[Team],
+    center: Vec2,
+    central_mass: f32,
+    radius: f32,
+    first_angle: f32,
+    direction: f32,
+) -> Vec<SpawnPoint> {
+    teams
+        .iter()
+        .enumerate()
+        .map(|(idx, team)| {
+            let angle = first_angle + TAU * idx as f32 / teams.len() as f32;
+            let (position, velocity) =
+                circular_orbit((center, Vec2::ZERO), central_mass, radius, angle, direction);
+            SpawnPoint {
+                team: team.clone(),
+                position,
+                velocity,
+            }
+        })
+        .collect()
+}
+
+/// A typical atmosphere for a planet of the given (sprite) size, which reaches up about as high
+/// as the planet is wide
+fn atmosphere_for_size(size: f32) -> Atmosphere {
+    Atmosphere {
+        scale_height: 10. * size,
+        ..Default::default()
+    }
+}
+
+/// Sprite size of something weighing `mass`, assuming everything is about as dense as the
+/// original planets (1e15 at size 1)
+fn size_for_mass(mass: f32) -> f32 {
+    (mass / 1e15).cbrt().max(0.25)
+}
+
+/// Generates a star system from `settings.seed`, with spawn points for each of `teams`. The same
+/// settings always give the same system.
+pub fn generate_system(settings: &GeneratorSettings, teams: &[Team]) -> GeneratedSystem {
+    let mut rng = SeededRng::new(settings.seed);
+    let mut system = GeneratedSystem::default();
+
+    let star_mass = rng.log_range(2e15, 5e15);
+    let gm = G * star_mass;
+
+    // everything goes around the same way, like it all formed from one disc
+    let direction = if rng.chance(0.5) { 1. } else { -1. };
+    if rng.chance(settings.binary_chance) {
+        // split the star in two, close enough together that every planet is circumbinary
+        let secondary = star_mass / (1. + 1. / rng.range(BINARY_MASS_RATIO.0, BINARY_MASS_RATIO.1));
+        let mass_ratio = secondary / star_mass;
+        let separation = 0.8 * INNERMOST_ORBIT / circumbinary_limit(1., 0., mass_ratio);
+        system.bodies.extend(binary_stars(
+            Vec2::ZERO,
+            (star_mass - secondary, secondary),
+            separation,
+            rng.range(0., TAU),
+            direction,
+        ));
+    } else {
+        system.bodies.push(GeneratedBody {
+            kind: GeneratedKind::Star,
+            position: Vec2::ZERO,
+            velocity: Vec2::ZERO,
+            mass: star_mass,
+            size: star_size(star_mass),
+            atmosphere: None,
+        });
+    }
+    let circular_speed = |radius: f32| (gm / radius).sqrt();
+
+    let innermost_period = TAU * (INNERMOST_ORBIT.powi(3) / gm).sqrt();
+    let spacing = required_hill_spacing(settings.stability_horizon / innermost_period);
+
+    let mut orbits: Vec<Orbit> = Vec::new();
+    let mut attempts = 0;
+    while orbits.len() < settings.max_planets as usize && attempts < MAX_ATTEMPTS {
+        attempts += 1;
+        let candidate = Orbit {
+            semi_major: rng.range(INNERMOST_ORBIT, OUTERMOST_ORBIT),
+            eccentricity: rng.range(0., settings.max_eccentricity),
+            mass: star_mass * rng.log_range(PLANET_MASS_RATIO.0, PLANET_MASS_RATIO.1),
+        };
+        if orbits
+            .iter()
+            .all(|other| well_separated(&candidate, other, star_mass, spacing))
+        {
+            orbits.push(candidate);
+        }
+    }
+    orbits.sort_by(|a, b| a.semi_major.total_cmp(&b.semi_major));
+
+    for orbit in orbits.iter() {
+        // start at periapsis, where the velocity is straight sideways
+        let dir = Vec2::from(rng.range(0., TAU).sin_cos());
+        let radius = orbit.periapsis();
+        let speed = (gm * (1. + orbit.eccentricity) / radius).sqrt();
+        let position = dir * radius;
+        let velocity = dir.perp() * speed * direction;
+        let size = size_for_mass(orbit.mass);
+        system.bodies.push(GeneratedBody {
+            kind: GeneratedKind::Planet,
+            position,
+            velocity,
+            mass: orbit.mass,
+            size,
+            atmosphere: rng
+                .chance(settings.atmosphere_chance)
+                .then(|| atmosphere_for_size(size)),
+        });
+
+        if rng.chance(settings.moon_chance) {
+            // periapsis is where the star's pull is strongest, so that's the Hill radius to fit in
+            let hill = hill_radius(orbit.mass, star_mass, radius);
+            let moon_dist = hill * rng.range(MOON_HILL_FRACTION.0, MOON_HILL_FRACTION.1);
+            // sprites are 50 pixels across at size 1, and a moon inside its planet is no moon
+            if moon_dist > 50. * size {
+                let moon_dir = Vec2::from(rng.range(0., TAU).sin_cos());
+                let moon_mass = orbit.mass * rng.log_range(MOON_MASS_RATIO.0, MOON_MASS_RATIO.1);
+                system.bodies.push(GeneratedBody {
+                    kind: GeneratedKind::Moon,
+                    position: position + moon_dir * moon_dist,
+                    velocity: velocity
+                        + moon_dir.perp() * (G * orbit.mass / moon_dist).sqrt() * direction,
+                    mass: moon_mass,
+                    size: size_for_mass(moon_mass) * 0.5,
+                    atmosphere: None,
+                });
+            }
+        }
+    }
+
+    // the gaps between the planets' orbits (plus some room either side of them) are free for
+    // asteroids and ships
+    let mut gaps = Vec::new();
+    let mut inner_edge = INNERMOST_ORBIT / 2.;
+    for orbit in orbits.iter() {
+        let room = spacing * hill_radius(orbit.mass, star_mass, orbit.periapsis());
+        gaps.push((inner_edge, orbit.periapsis() - room));
+        inner_edge = orbit.apoapsis() + room;
+    }
+    gaps.push((
+        inner_edge,
+        inner_edge.max(OUTERMOST_ORBIT) + 2. * MIN_GAP_WIDTH,
+    ));
+    gaps.retain(|(inner, outer)| outer - inner >= MIN_GAP_WIDTH);
+    gaps.sort_by(|(a_in, a_out), (b_in, b_out)| (b_out - b_in).total_cmp(&(a_out - a_in)));
+
+    let mut free_gaps = gaps.into_iter();
+    if rng.chance(settings.belt_chance) {
+        if let Some((inner, outer)) = free_gaps.next() {
+            let margin = (outer - inner) * 0.2;
+            let count = rng.range(500., 1500.) as u32;
+            for _ in 0..count {
+                let radius = rng.range(inner + margin, outer - margin);
+                let dir = Vec2::from(rng.range(0., TAU).sin_cos());
+                // a little variation in speed spreads the belt out over time
+                let speed = circular_speed(radius) * rng.range(0.97, 1.03);
+                system.bodies.push(GeneratedBody {
+                    kind: GeneratedKind::Asteroid,
+                    position: dir * radius,
+                    velocity: dir.perp() * speed * direction,
+                    // asteroids are test particles, so they don't pull on anything
+                    mass: 0.,
+                    size: rng.range(0.08, 0.15),
+                    atmosphere: None,
+                });
+            }
+        }
+    }
+
+    let spawn_radius = match free_gaps.next() {
+        Some((inner, outer)) => (inner + outer) / 2.,
+        None => OUTERMOST_ORBIT + MIN_GAP_WIDTH,
+    };
+    system.spawn_points = ring_spawn_points(
+        teams,
+        Vec2::ZERO,
+        star_mass,
+        spawn_radius,
+        rng.range(0., TAU),
+        direction,
+    );
+
+    system.recenter();
+    system
+}
+
+/// The star with a ring of five planets (each with a small moon) that matches took place in before
+/// there were any other systems. The first two teams start where they always have, and any others
+/// are spread around the star.
+pub fn classic_system(teams: &[Team]) -> GeneratedSystem {
+    let mut system = GeneratedSystem::default();
+    let star_mass = 3e15;
+    system.bodies.push(GeneratedBody {
+        kind: GeneratedKind::Star,
+        position: Vec2::ZERO,
+        velocity: Vec2::ZERO,
+        mass: star_mass,
+        size: 1.,
+        atmosphere: None,
+    });
+
+    const PLANETS: u32 = 5;
+    for i in 0..PLANETS {
+        let angle = TAU * i as f32 / PLANETS as f32;
+        let position = Vec2::new(angle.sin(), angle.cos()) * 500.;
+        system.bodies.push(GeneratedBody {
+            kind: GeneratedKind::Planet,
+            position,
+            velocity: Vec2::new(-angle.cos(), angle.sin()) * 90.,
+            mass: 1e15,
+            size: 1.,
+            atmosphere: None,
+        });
+        system.bodies.push(GeneratedBody {
+            kind: GeneratedKind::Moon,
+            position: position + Vec2::new(45., 45.),
+            velocity: Vec2::new(-20., -1.),
+            mass: 1e9,
+            size: 0.5,
+            atmosphere: None,
+        });
+    }
+
+    system.spawn_points = match teams {
+        [first, second] => vec![
+            SpawnPoint {
+                team: first.clone(),
+                position: Vec2::new(70., 240.),
+                velocity: Vec2::new(40.0, -20.0),
+            },
+            SpawnPoint {
+                team: second.clone(),
+                position: Vec2::new(300., 0.),
+                velocity: Vec2::new(0.0, -40.0),
+            },
+        ],
+        // the second team's spot, and going the same way around
+        _ => ring_spawn_points(teams, Vec2::ZERO, star_mass, 300., TAU / 4., -1.),
+    };
+    system
+}
+
+/// Planets (and a moon) going around both stars of a binary
+pub fn binary_system(teams: &[Team]) -> GeneratedSystem {
+    let mut system = GeneratedSystem::default();
+    let masses = (2e15, 1.2e15);
+    let total = masses.0 + masses.1;
+    let separation = 160.;
+    system
+        .bodies
+        .extend(binary_stars(Vec2::ZERO, masses, separation, 0., 1.));
+
+    // keep well clear of the binary, which would fling anything closer in right out
+    let inner_edge = circumbinary_limit(separation, 0., masses.1 / total);
+    for (idx, (radius, mass)) in [(1.3 * inner_edge, 1e14), (4. * inner_edge, 2e14)]
+        .into_iter()
+        .enumerate()
+    {
+        let (position, velocity) = circular_orbit(
+            (Vec2::ZERO, Vec2::ZERO),
+            total,
+            radius,
+            TAU * idx as f32 / 2.,
+            1.,
+        );
+        system.bodies.push(GeneratedBody {
+            kind: GeneratedKind::Planet,
+            position,
+            velocity,
+            mass,
+            size: size_for_mass(mass),
+            // the outer planet is big enough to hold on to some air
+            atmosphere: (idx == 1).then(|| atmosphere_for_size(size_for_mass(mass))),
+        });
+        if idx == 1 {
+            let moon_dist = 0.3 * hill_radius(mass, total, radius);
+            let (position, velocity) =
+                circular_orbit((position, velocity), mass, moon_dist, 0., 1.);
+            system.bodies.push(GeneratedBody {
+                kind: GeneratedKind::Moon,
+                position,
+                velocity,
+                mass: 1e9,
+                size: 0.5,
+                atmosphere: None,
+            });
+        }
+    }
+
+    system.spawn_points =
+        ring_spawn_points(teams, Vec2::ZERO, total, 2.2 * inner_edge, TAU / 4., 1.);
+    system.recenter();
+    system
+}
+
+/// A close binary with circumbinary planets, and a third star going around the pair of them with
+/// a planet of its own
+pub fn triple_system(teams: &[Team]) -> GeneratedSystem {
+    let mut system = GeneratedSystem::default();
+    let inner_masses = (2e15, 1e15);
+    let inner_total = inner_masses.0 + inner_masses.1;
+    let inner_separation = 120.;
+    system.bodies.extend(binary_stars(
+        Vec2::ZERO,
+        inner_masses,
+        inner_separation,
+        0.,
+        1.,
+    ));
+
+    let outer_mass = 1.5e15;
+    let outer_separation = 1600.;
+    let (outer_pos, outer_vel) = circular_orbit(
+        (Vec2::ZERO, Vec2::ZERO),
+        inner_total + outer_mass,
+        outer_separation,
+        TAU / 4.,
+        1.,
+    );
+    system.bodies.push(GeneratedBody {
+        kind: GeneratedKind::Star,
+        position: outer_pos,
+        velocity: outer_vel,
+        mass: outer_mass,
+        size: star_size(outer_mass),
+        atmosphere: None,
+    });
+
+    // planets around the inner pair have to stay outside of it, but also well inside of the
+    // third star's reach
+    let outer_ratio = outer_mass / (inner_total + outer_mass);
+    let inner_edge = circumbinary_limit(inner_separation, 0., inner_masses.1 / inner_total);
+    let outer_edge = s_type_limit(outer_separation, 0., outer_ratio);
+    let circumbinary_radius = (inner_edge + outer_edge) / 2.;
+    let (position, velocity) = circular_orbit(
+        (Vec2::ZERO, Vec2::ZERO),
+        inner_total,
+        circumbinary_radius,
+        -TAU / 4.,
+        1.,
+    );
+    system.bodies.push(GeneratedBody {
+        kind: GeneratedKind::Planet,
+        position,
+        velocity,
+        mass: 6e14,
+        size: size_for_mass(6e14),
+        atmosphere: None,
+    });
+
+    // an S-type planet, which only goes around the third star
+    let s_type_radius = 0.6 * s_type_limit(outer_separation, 0., 1. - outer_ratio);
+    let (position, velocity) =
+        circular_orbit((outer_pos, outer_vel), outer_mass, s_type_radius, 0., 1.);
+    system.bodies.push(GeneratedBody {
+        kind: GeneratedKind::Planet,
+        position,
+        velocity,
+        mass: 8e14,
+        size: size_for_mass(8e14),
+        atmosphere: Some(atmosphere_for_size(size_for_mass(8e14))),
+    });
+
+    // both teams start around the inner pair, where the fighting is
+    system.spawn_points = ring_spawn_points(
+        teams,
+        Vec2::ZERO,
+        inner_total,
+        (inner_edge + circumbinary_radius) / 2.,
+        0.,
+        1.,
+    );
+    system.recenter();
+    system
+}
+
+/// Spawns everything in `system`. The asteroids all go into one [`ParticleField`].
+pub fn spawn_generated_system(commands: &mut Commands, system: &GeneratedSystem) {
+    let mut belt = ParticleField::default();
+    for body in system.bodies.iter() {
+        let (position, velocity, mass, size) = (
+            Position(body.position),
+            Velocity(body.velocity),
+            Mass(body.mass),
+            Size(body.size),
+        );
+        match body.kind {
+            GeneratedKind::Star => {
+                commands.spawn_bundle(StarBundle {
+                    position,
+                    velocity,
+                    mass,
+                    size,
+                    ..Default::default()
+                });
+            }
+            GeneratedKind::Planet | GeneratedKind::Moon => {
+                let mut planet = commands.spawn_bundle(PlanetBundle {
+                    position,
+                    velocity,
+                    mass,
+                    size,
+                    ..Default::default()
+                });
+                if let Some(atmosphere) = body.atmosphere {
+                    planet.insert(atmosphere);
+                }
+            }
+            GeneratedKind::Asteroid => belt.push(body.position, body.velocity, body.size),
+        }
+    }
+
+    if !belt.is_empty() {
+        commands.spawn_bundle(ParticleFieldBundle { field: belt });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::num::NonZeroU32;
+
+    use super::*;
+
+    fn teams() -> Vec<Team> {
+        (1..=3).map(|id| Team(NonZeroU32::new(id))).collect()
+    }
+
+    /// A single star, a binary and whatever the default settings come up with
+    fn variants(seed: u64) -> [GeneratorSettings; 3] {
+        [0., 1., GeneratorSettings::default().binary_chance].map(|binary_chance| {
+            GeneratorSettings {
+                seed,
+                binary_chance,
+                ..Default::default()
+            }
+        })
+    }
+
+    #[test]
+    fn same_seed_gives_same_system() {
+        for seed in [0, 1, 42, u64::MAX] {
+            for settings in variants(seed) {
+                let first = generate_system(&settings, &teams());
+                assert!(!first.bodies.is_empty());
+                assert_eq!(first, generate_system(&settings, &teams()));
+            }
+        }
+    }
+
+    #[test]
+    fn different_seeds_give_different_systems() {
+        for (a, b) in variants(1).iter().zip(variants(2).iter()) {
+            assert_ne!(
+                generate_system(a, &teams()).bodies,
+                generate_system(b, &teams()).bodies
+            );
+        }
+    }
+
+    #[test]
+    fn binary_chance_picks_the_number_of_stars() {
+        let stars = |system: &GeneratedSystem| {
+            system
+                .bodies
+                .iter()
+                .filter(|body| body.kind == GeneratedKind::Star)
+                .count()
+        };
+        for seed in 0..10 {
+            let [single, binary, _] = variants(seed);
+            assert_eq!(stars(&generate_system(&single, &teams())), 1);
+            assert_eq!(stars(&generate_system(&binary, &teams())), 2);
+        }
+    }
+
+    /// The orbit `body` is on around something of `central_mass` at `center`, worked back out
+    /// from where it is and how fast it's going
+    fn orbit_around(body: &GeneratedBody, center: (Vec2, Vec2), central_mass: f32) -> Orbit {
+        let gm = G * central_mass;
+        let (pos, vel) = (body.position - center.0, body.velocity - center.1);
+        let eccentricity =
+            ((vel.length_squared() - gm / pos.length()) * pos - pos.dot(vel) * vel) / gm;
+        Orbit {
+            semi_major: 1. / (2. / pos.length() - vel.length_squared() / gm),
+            eccentricity: eccentricity.length(),
+            mass: body.mass,
+        }
+    }
+
+    #[test]
+    fn generated_systems_are_stable() {
+        // the orbits are worked back out from positions and velocities, so allow for rounding
+        const TOLERANCE: f32 = 1e-3;
+
+        for seed in 0..200 {
+            for settings in variants(seed) {
+                let system = generate_system(&settings, &teams());
+                let stars: Vec<&GeneratedBody> = system
+                    .bodies
+                    .iter()
+                    .filter(|body| body.kind == GeneratedKind::Star)
+                    .collect();
+                let (center, center_vel, star_mass) = barycenter(
+                    stars
+                        .iter()
+                        .map(|star| (star.position, star.velocity, star.mass)),
+                );
+
+                let innermost_period = TAU * (INNERMOST_ORBIT.powi(3) / (G * star_mass)).sqrt();
+                let spacing = required_hill_spacing(settings.stability_horizon / innermost_period);
+
+                let mut planets = Vec::new();
+                let mut last_planet = None;
+                for body in system.bodies.iter() {
+                    match body.kind {
+                        GeneratedKind::Planet => {
+                            let orbit = orbit_around(body, (center, center_vel), star_mass);
+                            assert!(orbit.eccentricity <= settings.max_eccentricity + TOLERANCE);
+                            planets.push(orbit);
+                            last_planet = Some((body, orbit));
+                        }
+                        GeneratedKind::Moon => {
+                            // each moon comes right after the planet it goes around, and has to
+                            // stay well inside of its Hill sphere
+                            let (planet, orbit) = last_planet.expect("moon without a planet");
+                            let moon =
+                                orbit_around(body, (planet.position, planet.velocity), planet.mass);
+                            let hill = hill_radius(planet.mass, star_mass, orbit.periapsis());
+                            assert!(
+                                moon.apoapsis() <= MOON_HILL_FRACTION.1 * hill * (1. + TOLERANCE),
+                                "seed {}: moon reaches {} out of a Hill radius of {}",
+                                seed,
+                                moon.apoapsis(),
+                                hill
+                            );
+                        }
+                        GeneratedKind::Star | GeneratedKind::Asteroid => (),
+                    }
+                }
+
+                planets.sort_by(|a, b| a.semi_major.total_cmp(&b.semi_major));
+                for pair in planets.windows(2) {
+                    assert!(
+                        well_separated(&pair[0], &pair[1], star_mass, spacing * (1. - TOLERANCE)),
+                        "seed {}: planets at {} and {} are too close",
+                        seed,
+                        pair[0].semi_major,
+                        pair[1].semi_major
+                    );
+                }
+
+                if let [a, b] = stars[..] {
+                    let inner_edge = circumbinary_limit(
+                        a.position.distance(b.position),
+                        0.,
+                        a.mass.min(b.mass) / star_mass,
+                    );
+                    for orbit in planets.iter() {
+                        assert!(
+                            orbit.periapsis() >= inner_edge,
+                            "seed {}: planet comes within {} of a binary that clears {}",
+                            seed,
+                            orbit.periapsis(),
+                            inner_edge
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn hand_made_planets_stay_where_the_stars_let_them() {
+        // the binary's planets go around both stars
+        let binary = StarSystem::Binary.build(&teams());
+        let (stars, rest) = binary.bodies.split_at(2);
+        let (center, center_vel, total) = barycenter(
+            stars
+                .iter()
+                .map(|star| (star.position, star.velocity, star.mass)),
+        );
+        let inner_edge = circumbinary_limit(
+            stars[0].position.distance(stars[1].position),
+            0.,
+            stars[1].mass / total,
+        );
+        for planet in rest
+            .iter()
+            .filter(|body| body.kind == GeneratedKind::Planet)
+        {
+            assert!(orbit_around(planet, (center, center_vel), total).periapsis() >= inner_edge);
+        }
+
+        // the triple has one planet around the inner pair and another around the third star
+        let triple = StarSystem::Triple.build(&teams());
+        let (stars, rest) = triple.bodies.split_at(3);
+        let (center, center_vel, inner_total) = barycenter(
+            stars[..2]
+                .iter()
+                .map(|star| (star.position, star.velocity, star.mass)),
+        );
+        let outer = &stars[2];
+        let separation = outer.position.distance(center);
+        let outer_ratio = outer.mass / (inner_total + outer.mass);
+        let (circumbinary, s_type) = (&rest[0], &rest[1]);
+
+        let around_pair = orbit_around(circumbinary, (center, center_vel), inner_total);
+        assert!(
+            around_pair.periapsis()
+                >= circumbinary_limit(
+                    stars[0].position.distance(stars[1].position),
+                    0.,
+                    stars[1].mass / inner_total
+                )
+        );
+        assert!(around_pair.apoapsis() <= s_type_limit(separation, 0., outer_ratio));
+
+        let around_third = orbit_around(s_type, (outer.position, outer.velocity), outer.mass);
+        assert!(around_third.apoapsis() <= s_type_limit(separation, 0., 1. - outer_ratio));
+    }
+}
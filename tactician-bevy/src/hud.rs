@@ -0,0 +1,420 @@
+use bevy::prelude::*;
+
+use crate::camera::CameraController;
+use crate::colliders::Collider;
+use crate::components::Size;
+use crate::components::*;
+use crate::controls::ActionState;
+use crate::physics::SimulationAuthority;
+use crate::resources::Typography;
+use crate::teams::{CaptureTarget, MatchRules, MatchState, TeamRegistry, VictoryCondition};
+
+/// How far in from the edge of the window the indicators for off-screen bodies sit, in pixels
+const EDGE_MARGIN: f32 = 24.;
+/// Width of an edge indicator, in pixels
+const INDICATOR_SIZE: f32 = 10.;
+/// Incoming missiles are flagged in this colour rather than their team's, so they stand out
+const INCOMING_COLOR: Color = Color::rgb(1., 0.45, 0.1);
+
+/// Readout of the player's own ship: how fast it's going, how high up it is, what the engine is
+/// doing and how many missiles are left
+#[derive(Debug, Default, Clone, Copy, Component)]
+pub struct TelemetryPanel;
+
+/// Readout of the ship or missile being tracked: how far away it is and how soon it'll get here
+#[derive(Debug, Default, Clone, Copy, Component)]
+pub struct TargetPanel;
+
+/// What it takes to win the match, and how every team is getting on
+#[derive(Debug, Default, Clone, Copy, Component)]
+pub struct ObjectivePanel;
+
+/// Points at something that's off the edge of the screen. These are all thrown away and made again
+/// every frame.
+#[derive(Debug, Default, Clone, Copy, Component)]
+pub struct EdgeIndicator;
+
+pub fn spawn_panels(commands: &mut Commands, typography: &Typography) {
+    commands
+        .spawn()
+        .insert(TelemetryPanel)
+        .insert_bundle(TextBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    top: Val::Px(30.0),
+                    left: Val::Px(10.0),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            text: Text::from_section("", typography.hud.clone()),
+            ..Default::default()
+        });
+
+    commands
+        .spawn()
+        .insert(TargetPanel)
+        .insert_bundle(TextBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    top: Val::Px(30.0),
+                    right: Val::Px(10.0),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            text: Text::from_section("", typography.hud.clone()),
+            ..Default::default()
+        });
+
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    top: Val::Px(10.0),
+                    ..Default::default()
+                },
+                size: bevy::ui::Size::new(Val::Percent(100.0), Val::Undefined),
+                justify_content: JustifyContent::Center,
+                ..Default::default()
+            },
+            color: Color::NONE.into(),
+            ..Default::default()
+        })
+        .with_children(|parent| {
+            parent
+                .spawn()
+                .insert(ObjectivePanel)
+                .insert_bundle(TextBundle {
+                    text: Text::from_section("", typography.hud.clone()),
+                    ..Default::default()
+                });
+        });
+}
+
+/// One line per readout, with its label greyed out
+fn readouts(typography: &Typography, lines: &[(&str, String)]) -> Vec<TextSection> {
+    lines
+        .iter()
+        .flat_map(|(label, value)| {
+            [
+                TextSection::new(format!("{:<6}", label), typography.hud_label.clone()),
+                TextSection::new(format!("{}\n", value), typography.hud.clone()),
+            ]
+        })
+        .collect()
+}
+
+/// How far away `pos` is and how fast it's getting closer, as seen from `from`. The closing speed
+/// is negative when they're getting further apart.
+fn range_and_closing(from: (Vec2, Vec2), to: (Vec2, Vec2)) -> (f32, f32) {
+    let (offset, relative_vel) = (to.0 - from.0, to.1 - from.1);
+    let range = offset.length();
+    let closing = if range > 0. {
+        -offset.dot(relative_vel) / range
+    } else {
+        0.
+    };
+    (range, closing)
+}
+
+pub fn update_telemetry(
+    typography: Res<Typography>,
+    authority: Res<SimulationAuthority>,
+    actions: Res<ActionState>,
+    ship: Query<(&Position, &Velocity, &EnginePhysics, Option<&Magazine>), With<LocalPlayer>>,
+    sources: Query<(&Position, &Size, Option<&Collider>), With<GravitySource>>,
+    mut panels: Query<&mut Text, With<TelemetryPanel>>,
+) {
+    let lines = match ship.iter().next() {
+        Some((Position(pos), Velocity(vel), engine, magazine)) => {
+            let altitude = sources
+                .iter()
+                .map(|(Position(source_pos), Size(size), collider)| {
+                    let radius = collider.map_or_else(
+                        || Collider::default().bounding_radius(),
+                        Collider::bounding_radius,
+                    );
+                    pos.distance(*source_pos) - radius * size
+                })
+                .reduce(f32::min);
+            // network clients don't run the engine themselves, so show what's being asked of it
+            let (throttle, turn) = if *authority == SimulationAuthority::Local {
+                (
+                    engine.current_accel / engine.max_accel,
+                    engine.current_turn_rate / engine.max_turn_rate,
+                )
+            } else {
+                (actions.throttle(), actions.turn())
+            };
+            vec![
+                ("SPD", format!("{:.1} px/s", vel.length())),
+                (
+                    "ALT",
+                    altitude.map_or_else(|| "--".to_string(), |alt| format!("{:.0} px", alt)),
+                ),
+                ("THR", format!("{:+.0}%", throttle * 100.)),
+                ("TURN", format!("{:+.0}%", turn * 100.)),
+                (
+                    "MSL",
+                    match magazine.and_then(|magazine| magazine.0) {
+                        Some(remaining) => remaining.to_string(),
+                        None => "unlimited".to_string(),
+                    },
+                ),
+            ]
+        }
+        None => vec![("SHIP", "destroyed".to_string())],
+    };
+
+    for mut text in panels.iter_mut() {
+        text.sections = readouts(&typography, &lines);
+    }
+}
+
+/// Shows the score, or how close each team is to capturing the planet. A last team standing
+/// match has nothing to keep track of, so this stays empty.
+pub fn update_objective_panel(
+    typography: Res<Typography>,
+    rules: Res<MatchRules>,
+    registry: Res<TeamRegistry>,
+    match_state: Res<MatchState>,
+    ship: Query<&Position, With<LocalPlayer>>,
+    target: Query<&Position, With<CaptureTarget>>,
+    mut panels: Query<&mut Text, With<ObjectivePanel>>,
+) {
+    let team_sections = |progress: Vec<(Team, String)>| -> Vec<TextSection> {
+        progress
+            .into_iter()
+            .flat_map(|(team, value)| {
+                [
+                    TextSection::new(
+                        format!("  {} ", registry.name(&team)),
+                        TextStyle {
+                            color: registry.color(&team),
+                            ..typography.hud.clone()
+                        },
+                    ),
+                    TextSection::new(value, typography.hud.clone()),
+                ]
+            })
+            .collect()
+    };
+
+    let sections: Vec<TextSection> = match rules.victory {
+        VictoryCondition::LastTeamStanding => Vec::new(),
+        VictoryCondition::TimedScore { duration } => {
+            let mut scores: Vec<(Team, String)> = match_state
+                .scores
+                .iter()
+                .map(|(id, score)| (Team(*id), score.to_string()))
+                .collect();
+            scores.sort_by_key(|(Team(id), _)| *id);
+            let remaining = (duration - match_state.elapsed).max(0.);
+            std::iter::once(TextSection::new(
+                format!(
+                    "Most kills, {}:{:02} left",
+                    remaining as u32 / 60,
+                    remaining as u32 % 60
+                ),
+                typography.hud_label.clone(),
+            ))
+            .chain(team_sections(scores))
+            .collect()
+        }
+        VictoryCondition::CapturePlanet { hold_time, .. } => {
+            let distance = match (ship.iter().next(), target.iter().next()) {
+                (Some(Position(pos)), Some(Position(target_pos))) => {
+                    format!(" ({:.0} px away)", pos.distance(*target_pos))
+                }
+                _ => String::new(),
+            };
+            let mut progress: Vec<(Team, String)> = match_state
+                .capture_progress
+                .iter()
+                .map(|(id, held)| (Team(*id), format!("{:.0}/{:.0}s", held, hold_time)))
+                .collect();
+            progress.sort_by_key(|(Team(id), _)| *id);
+            std::iter::once(TextSection::new(
+                format!("Hold the heaviest planet{}", distance),
+                typography.hud_label.clone(),
+            ))
+            .chain(team_sections(progress))
+            .collect()
+        }
+    };
+
+    for mut text in panels.iter_mut() {
+        text.sections = sections.clone();
+    }
+}
+
+/// Shows whatever the camera is following if it's hostile, and otherwise the closest enemy ship
+pub fn update_target_panel(
+    typography: Res<Typography>,
+    registry: Res<TeamRegistry>,
+    controller: Res<CameraController>,
+    ship: Query<(&Position, &Velocity, &Team), With<LocalPlayer>>,
+    bodies: Query<
+        (Entity, &Position, &Velocity, &Team, Option<&Missile>),
+        (Or<(With<Ship>, With<Missile>)>, Without<LocalPlayer>),
+    >,
+    mut panels: Query<&mut Text, With<TargetPanel>>,
+) {
+    let sections = ship
+        .iter()
+        .next()
+        .and_then(|(Position(pos), Velocity(vel), team)| {
+            let hostile = |body_team: &Team| !registry.are_allied(team, body_team);
+            let followed = controller
+                .target
+                .and_then(|target| bodies.get(target).ok())
+                .filter(|(_, _, _, body_team, _)| hostile(body_team));
+            let (_, Position(target_pos), Velocity(target_vel), _, missile) =
+                followed.or_else(|| {
+                    bodies
+                        .iter()
+                        .filter(|(_, _, _, body_team, missile)| {
+                            missile.is_none() && hostile(body_team)
+                        })
+                        .min_by(|(_, Position(a), ..), (_, Position(b), ..)| {
+                            pos.distance_squared(*a)
+                                .total_cmp(&pos.distance_squared(*b))
+                        })
+                })?;
+
+            let (range, closing) = range_and_closing((*pos, *vel), (*target_pos, *target_vel));
+            let intercept = if closing > 0. {
+                format!("{:.0} s", range / closing)
+            } else {
+                "--".to_string()
+            };
+            Some(readouts(
+                &typography,
+                &[
+                    (
+                        "TGT",
+                        if missile.is_some() { "missile" } else { "ship" }.to_string(),
+                    ),
+                    ("RNG", format!("{:.0} px", range)),
+                    ("CLS", format!("{:+.1} px/s", closing)),
+                    ("TTI", intercept),
+                ],
+            ))
+        });
+
+    for mut text in panels.iter_mut() {
+        text.sections = sections.clone().unwrap_or_default();
+    }
+}
+
+/// Puts a marker at the edge of the screen for every enemy ship that's out of view, and for every
+/// enemy missile out of view that's heading towards the player
+pub fn update_edge_indicators(
+    mut commands: Commands,
+    typography: Res<Typography>,
+    registry: Res<TeamRegistry>,
+    windows: Res<Windows>,
+    camera: Query<&Transform, With<ShipCamera>>,
+    ship: Query<(&Position, &Velocity, &Team), With<LocalPlayer>>,
+    bodies: Query<
+        (&Position, &Velocity, &Team, Option<&Missile>),
+        (Or<(With<Ship>, With<Missile>)>, Without<LocalPlayer>),
+    >,
+    indicators: Query<Entity, With<EdgeIndicator>>,
+) {
+    for entity in indicators.iter() {
+        commands.entity(entity).despawn();
+    }
+
+    let (window, camera) = match (windows.get_primary(), camera.get_single()) {
+        (Some(window), Ok(camera)) => (window, camera),
+        _ => return,
+    };
+    let (Position(pos), Velocity(vel), team) = match ship.iter().next() {
+        Some(ship) => ship,
+        None => return,
+    };
+    let half_window = Vec2::new(window.width(), window.height()) / 2.;
+    let inner = half_window - EDGE_MARGIN;
+
+    for (Position(body_pos), Velocity(body_vel), body_team, missile) in bodies.iter() {
+        if registry.are_allied(team, body_team) {
+            continue;
+        }
+        let (range, closing) = range_and_closing((*pos, *vel), (*body_pos, *body_vel));
+        if missile.is_some() && closing <= 0. {
+            continue;
+        }
+
+        // where it is on screen, relative to the middle
+        let on_screen = (camera.rotation.inverse() * (body_pos.extend(0.) - camera.translation))
+            .truncate()
+            / camera.scale.x;
+        if on_screen.x.abs() < inner.x && on_screen.y.abs() < inner.y {
+            continue;
+        }
+        // pulled in along the line from the middle until it's just inside the window
+        let scale = (inner.x / on_screen.x.abs()).min(inner.y / on_screen.y.abs());
+        let marker = half_window + on_screen * scale;
+
+        let (color, label) = match missile {
+            Some(_) => (INCOMING_COLOR, format!("MSL {:.0}", range)),
+            None => (registry.color(body_team), format!("{:.0}", range)),
+        };
+        commands
+            .spawn()
+            .insert(EdgeIndicator)
+            .insert_bundle(NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    position: UiRect {
+                        left: Val::Px(marker.x - INDICATOR_SIZE / 2.),
+                        bottom: Val::Px(marker.y - INDICATOR_SIZE / 2.),
+                        ..Default::default()
+                    },
+                    size: bevy::ui::Size::new(Val::Px(INDICATOR_SIZE), Val::Px(INDICATOR_SIZE)),
+                    ..Default::default()
+                },
+                color: color.into(),
+                ..Default::default()
+            });
+
+        // the distance goes on whichever side is towards the middle of the screen, so it stays in
+        // view
+        let (left, right) = if on_screen.x > 0. {
+            (
+                Val::Undefined,
+                Val::Px(2. * half_window.x - marker.x + INDICATOR_SIZE),
+            )
+        } else {
+            (Val::Px(marker.x + INDICATOR_SIZE), Val::Undefined)
+        };
+        commands
+            .spawn()
+            .insert(EdgeIndicator)
+            .insert_bundle(TextBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    position: UiRect {
+                        left,
+                        right,
+                        bottom: Val::Px(marker.y - INDICATOR_SIZE),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                },
+                text: Text::from_section(
+                    label,
+                    TextStyle {
+                        color,
+                        ..typography.hud.clone()
+                    },
+                ),
+                ..Default::default()
+            });
+    }
+}
@@ -1,14 +1,14 @@
-use std::num::NonZeroU32;
-
 use crate::bundles::*;
 use crate::components::Size;
 use crate::components::*;
+use crate::controls::{Action, ActionState, ControlsPlugin};
 use crate::events::*;
 use crate::misc::AppState;
 use crate::resources::*;
 use bevy::math::Vec2;
 use bevy::{
     diagnostic::{Diagnostics, FrameTimeDiagnosticsPlugin},
+    ecs::schedule::ShouldRun,
     prelude::*,
 };
 use bevy_prototype_lyon::prelude::*;
@@ -16,64 +16,439 @@ use bevy_prototype_lyon::prelude::*;
 #[cfg(target_arch = "wasm32")]
 use wasm_bindgen::prelude::*;
 
+mod ai;
+#[cfg(feature = "audio")]
+mod audio;
 mod bundles;
+mod camera;
+mod colliders;
 mod components;
+mod controls;
+mod diagnostics;
 mod events;
+mod generator;
+mod hud;
 mod menu;
+mod minimap;
 mod misc;
+#[cfg(not(target_arch = "wasm32"))]
+mod net;
+mod overlays;
+mod particles;
 mod physics;
+mod player;
 mod resources;
-use physics::PhysicsPlugin;
+mod scenarios;
+mod settings;
+mod teams;
+mod trails;
+use ai::AiPilot;
+use camera::{CameraController, CameraSystem, ZoomLimits};
+use colliders::{Collider, CollidersPlugin};
+use diagnostics::ConservationDiagnosticsPlugin;
+use generator::{SpawnPoint, SpawnPoints};
+use minimap::{MinimapImages, MinimapView};
+use overlays::{OverlaySystem, Overlays};
+use physics::{
+    PhysicsPlugin, PhysicsSystem, SimulationAuthority, SimulationStep, SimulationTime, TimeWarp,
+};
+use player::{PlayerStats, PlayerStatus};
+use scenarios::{MatchSetup, Scenario, Scenarios};
+use settings::SettingsPlugin;
+use teams::{MatchRules, MatchState, TeamRegistry, TeamsPlugin};
+use trails::{SnailTrail, TrailFrame, TrailLength, TrailStyle};
+
+pub use generator::{GeneratorSettings, StarSystem};
+#[cfg(not(target_arch = "wasm32"))]
+pub use net::{ClientConfig, LinkConditions, ServerConfig};
+
+/// What `benches/trails.rs` needs to put bodies with trails in an app, without the rest of the game
+#[doc(hidden)]
+pub mod bench {
+    pub use crate::bundles::{MissileBundle, StarBundle};
+    pub use crate::camera::CameraController;
+    pub use crate::components::{Mass, Position, Velocity};
+    pub use crate::physics::{PhysicsPlugin, G};
+    pub use crate::teams::TeamsPlugin;
+    pub use crate::trails::{update_snail_trails, SnailTrail, TrailFrame, TrailLength, TrailStyle};
+}
+
+/// Simulated seconds before a missile can hit ships on its own side, when friendly fire is on
+const MISSILE_ARMING_TIME: f64 = 2.;
+
+/// Whether a missile can hit a ship on `ship_team`. With friendly fire on, missiles still can't
+/// hit their own side until they're clear of the ship that fired them.
+fn missile_can_hit(
+    registry: &TeamRegistry,
+    rules: &MatchRules,
+    sim_time: &SimulationTime,
+    ship_team: &Team,
+    missile_team: &Team,
+    lifespan: &Lifespan,
+) -> bool {
+    !registry.are_allied(ship_team, missile_team)
+        || (rules.friendly_fire && sim_time.elapsed - lifespan.created_on > MISSILE_ARMING_TIME)
+}
 
 // #[cfg(all(not(feature = "wasm"), not(feature = "native")))]
 // compile_error!("You have to build this binary (tactician-bevy) with either the 'wasm' feature or 'native' feature");
 
 #[cfg_attr(target_arch = "wasm32", wasm_bindgen(start))]
 pub fn run_game() {
+    run_game_with(Scenarios::load())
+}
+
+/// Same as [`run_game`], but with `star_system` at the top of the scenario list (and picked to
+/// begin with)
+pub fn run_game_in(star_system: StarSystem) {
+    let mut scenarios = Scenarios::load();
+    scenarios.0.insert(
+        0,
+        Scenario {
+            name: "Command line".to_string(),
+            description: "The star system picked on the command line.".to_string(),
+            system: star_system,
+        },
+    );
+    run_game_with(scenarios)
+}
+
+fn run_game_with(scenarios: Scenarios) {
+    let mut app = App::new();
+    add_client_plugins(&mut app);
+    app.insert_resource(scenarios)
+        .init_resource::<MatchSetup>()
+        .init_resource::<SpawnPoints>()
+        .init_resource::<Destroyed>();
+
+    // Add default menu state
+    app.add_state(AppState::Menu);
+
+    // menu stuff
+    app.add_system_set(SystemSet::on_enter(AppState::Menu).with_system(menu::init_menu))
+        .add_system_set(SystemSet::on_update(AppState::Menu).with_system(menu::update_menu))
+        .add_system_set(SystemSet::on_exit(AppState::Menu).with_system(delete_all_entities));
+
+    // rebinding screen
+    app.init_resource::<menu::Rebinding>()
+        .add_system_set(
+            SystemSet::on_enter(AppState::Controls).with_system(menu::init_controls_menu),
+        )
+        .add_system_set(
+            SystemSet::on_update(AppState::Controls)
+                .with_system(menu::update_menu)
+                .with_system(menu::update_controls_menu)
+                .with_system(menu::capture_binding)
+                .with_system(menu::update_binding_labels),
+        )
+        .add_system_set(
+            SystemSet::on_exit(AppState::Controls)
+                .with_system(menu::save_bindings)
+                .with_system(delete_all_entities),
+        );
+
+    // settings screen
+    app.add_system_set(
+        SystemSet::on_enter(AppState::Settings).with_system(menu::init_settings_menu),
+    )
+    .add_system_set(
+        SystemSet::on_update(AppState::Settings)
+            .with_system(menu::update_menu)
+            .with_system(menu::update_settings_menu)
+            .with_system(menu::update_setting_labels),
+    )
+    .add_system_set(
+        SystemSet::on_exit(AppState::Settings)
+            .with_system(menu::save_settings)
+            .with_system(delete_all_entities),
+    );
+
+    // match setup screen
+    app.add_system_set(
+        SystemSet::on_enter(AppState::MatchSetup).with_system(menu::init_match_setup),
+    )
+    .add_system_set(
+        SystemSet::on_update(AppState::MatchSetup)
+            .with_system(menu::update_menu)
+            .with_system(menu::update_match_setup)
+            .with_system(menu::update_match_setup_labels)
+            .with_system(menu::update_scenario_preview),
+    )
+    .add_system_set(SystemSet::on_exit(AppState::MatchSetup).with_system(delete_all_entities));
+
+    // In game stuff
+    app.add_system_set(
+        SystemSet::on_enter(AppState::Game)
+            .with_system(initialize_components)
+            .with_system(minimap::spawn_minimap)
+            .with_system(teams::reset_match)
+            .with_system(player::reset_player)
+            .with_system(camera::reset_camera)
+            .with_system(physics::reset_time_warp)
+            .with_system(diagnostics::reset_conservation_baseline),
+    )
+    .add_system_set(game_presentation(SystemSet::on_update(AppState::Game)))
+    .add_system_set(particle_rules(SystemSet::on_update(AppState::Game)))
+    .add_system_set(
+        collision_rules(
+            SystemSet::new().with_run_criteria(RunCriteria::pipe(SimulationStep, during_match)),
+        )
+        .with_system(particles::collide_particles),
+    )
+    .add_system_set(
+        game_rules(SystemSet::on_update(AppState::Game))
+            .with_system(connect_ship_acceleration_to_user_input)
+            .with_system(handle_time_warp_input.before(PhysicsSystem::Plan))
+            // has to run before the frame is planned, or one last frame gets the full warp
+            .with_system(
+                drop_time_warp_near_danger
+                    .after(handle_time_warp_input)
+                    .before(PhysicsSystem::Plan),
+            )
+            .with_system(player::track_player_stats)
+            .with_system(player::detect_player_death)
+            .with_system(player::update_death_menu)
+            .with_system(end_game_when_match_is_decided),
+    )
+    .add_system_set(SystemSet::on_exit(AppState::Game).with_system(delete_all_entities));
+
+    // pausing freezes the match underneath the pause menu
+    app.add_system(player::toggle_pause)
+        .add_system_set(
+            SystemSet::on_enter(AppState::Paused)
+                .with_system(menu::init_pause_menu)
+                .with_system(physics::pause_simulation),
+        )
+        .add_system_set(SystemSet::on_update(AppState::Paused).with_system(menu::update_menu))
+        .add_system_set(
+            SystemSet::on_exit(AppState::Paused)
+                .with_system(menu::close_pause_menu)
+                .with_system(physics::resume_simulation),
+        );
+
+    // results screens
+    app.add_system_set(SystemSet::on_enter(AppState::GameOver).with_system(menu::init_game_over))
+        .add_system_set(SystemSet::on_update(AppState::GameOver).with_system(menu::update_menu))
+        .add_system_set(SystemSet::on_exit(AppState::GameOver).with_system(delete_all_entities))
+        .add_system_set(SystemSet::on_enter(AppState::Results).with_system(menu::init_results))
+        .add_system_set(SystemSet::on_update(AppState::Results).with_system(menu::update_menu))
+        .add_system_set(SystemSet::on_exit(AppState::Results).with_system(delete_all_entities));
+
+    app.run()
+}
+
+/// Joins a match hosted by a dedicated server (see `src/bin/tactician-server.rs`) instead of
+/// simulating one locally
+#[cfg(not(target_arch = "wasm32"))]
+pub fn run_client(config: ClientConfig) {
+    let mut app = App::new();
+    add_client_plugins(&mut app);
+
+    app.insert_resource(SimulationAuthority::Remote)
+        .add_plugin(net::ClientPlugin(config))
+        .add_state(AppState::Game);
+
+    app.add_system_set(
+        SystemSet::on_enter(AppState::Game)
+            .with_system(initialize_hud)
+            .with_system(minimap::spawn_minimap),
+    )
+    .add_system_set(game_presentation(SystemSet::on_update(AppState::Game)));
+
+    app.run()
+}
+
+/// Runs a match headlessly. The server owns the simulation and clients only send it their inputs.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn run_server(config: ServerConfig) {
+    use bevy::app::ScheduleRunnerSettings;
+    use bevy::diagnostic::{DiagnosticsPlugin, LogDiagnosticsPlugin};
+    use bevy::log::LogPlugin;
+
     let mut app = App::new();
+    app.insert_resource(ScheduleRunnerSettings::run_loop(
+        std::time::Duration::from_secs_f64(1.0 / 60.0),
+    ))
+    .add_plugins(MinimalPlugins)
+    .add_plugin(LogPlugin)
+    .add_plugin(PhysicsPlugin)
+    .add_plugin(TeamsPlugin)
+    .add_plugin(CollidersPlugin)
+    .add_plugin(DiagnosticsPlugin)
+    .add_plugin(ConservationDiagnosticsPlugin)
+    // nobody is watching a graph on the server, so this is the only way to keep an eye on it
+    .add_plugin(LogDiagnosticsPlugin::filtered(
+        ConservationDiagnosticsPlugin::ALL.to_vec(),
+    ))
+    .add_event::<SpawnMissileFromShip>()
+    .add_event::<CreateExplosionEvent>()
+    .add_event::<ShipDestroyedEvent>()
+    .add_plugin(net::ServerPlugin(config))
+    .init_resource::<Destroyed>()
+    .add_startup_system(initialize_server_world)
+    .add_system_set(game_rules(SystemSet::new()))
+    .add_system_set(collision_rules(
+        SystemSet::new().with_run_criteria(SimulationStep),
+    ));
+
+    app.run()
+}
+
+/// Plugins and resources needed by anything that opens a window and draws the game
+fn add_client_plugins(app: &mut App) {
     app.add_plugins(DefaultPlugins);
 
     app.add_plugin(ShapePlugin)
         .add_plugin(PhysicsPlugin)
+        .add_plugin(ControlsPlugin)
+        .add_plugin(TeamsPlugin)
+        .add_plugin(CollidersPlugin)
         .add_plugin(FrameTimeDiagnosticsPlugin::default())
+        .add_plugin(ConservationDiagnosticsPlugin)
+        .add_plugin(SettingsPlugin)
+        .init_resource::<PlayerStatus>()
+        .init_resource::<PlayerStats>()
+        .init_resource::<CameraController>()
+        .init_resource::<ZoomLimits>()
+        .init_resource::<MinimapView>()
+        .init_resource::<MinimapImages>()
+        .init_resource::<Overlays>()
+        .init_resource::<TrailFrame>()
+        .init_resource::<TrailLength>()
         .insert_resource(ClearColor(Color::rgb(0.1, 0.1, 0.1)))
         .init_resource::<Typography>()
         .init_resource::<Materials>()
         .add_event::<SpawnMissileFromShip>()
-        .add_event::<CreateExplosionEvent>();
+        .add_event::<CreateExplosionEvent>()
+        .add_event::<ShipDestroyedEvent>();
 
-    // Add default menu state
-    app.add_state(AppState::Menu);
+    #[cfg(feature = "audio")]
+    app.add_plugin(audio::SoundPlugin);
 
     app.add_startup_system(init_camera);
+}
 
-    // menu stuff
-    app.add_system_set(SystemSet::on_enter(AppState::Menu).with_system(menu::init_menu))
-        .add_system_set(SystemSet::on_update(AppState::Menu).with_system(menu::update_menu))
-        .add_system_set(SystemSet::on_exit(AppState::Menu).with_system(delete_all_entities));
+/// Systems that decide what happens in a match. These only run where the simulation is
+/// authoritative, i.e in singleplayer and on the dedicated server.
+fn game_rules(set: SystemSet) -> SystemSet {
+    set.with_system(kill_expired_objects)
+        .with_system(forget_destroyed_bodies.before(PhysicsSystem::Plan))
+        .with_system(handle_spawn_missile_event)
+        .with_system(load_magazines)
+        .with_system(ai::fly_ai_ships)
+        .with_system(physics::enforce_world_bounds)
+        .with_system(teams::score_kills)
+        .with_system(teams::pick_capture_target)
+        .with_system(teams::check_victory)
+}
 
-    // In game stuff
-    app.add_system_set(SystemSet::on_enter(AppState::Game).with_system(initialize_components))
-        .add_system_set(
-            SystemSet::on_update(AppState::Game)
-                .with_system(handle_window_zoom)
-                .with_system(enforce_size)
-                .with_system(animate_sprite_system)
-                .with_system(connect_ship_acceleration_to_user_input)
-                .with_system(fps_counter)
-                .with_system(kill_out_of_bounds_missiles)
-                .with_system(kill_expired_objects)
-                .with_system(explode_missiles_near_planets)
-                .with_system(handle_spawn_missile_event)
-                .with_system(update_missilecount)
-                .with_system(follow_ship)
-                .with_system(render_snailtrail)
-                .with_system(check_if_missile_should_kill_ship)
-                .with_system(create_explosion),
-        )
-        .add_system_set(SystemSet::on_exit(AppState::Game).with_system(delete_all_entities));
+/// Hit tests, which run after every substep of the simulation rather than once per frame. The
+/// set has to come with a run criteria that follows [`SimulationStep`].
+fn collision_rules(set: SystemSet) -> SystemSet {
+    set.after(PhysicsSystem::Integrate)
+        .with_system(explode_missiles_near_planets)
+        .with_system(burn_up_overheated_bodies)
+        .with_system(check_if_missile_should_kill_ship)
+}
 
-    app.run()
+/// Passes [`SimulationStep`] on while a match is being played, and says no anywhere else
+fn during_match(In(step): In<ShouldRun>, state: Res<State<AppState>>) -> ShouldRun {
+    if *state.current() == AppState::Game {
+        return step;
+    }
+    match step {
+        ShouldRun::YesAndCheckAgain | ShouldRun::NoAndCheckAgain => ShouldRun::NoAndCheckAgain,
+        ShouldRun::Yes | ShouldRun::No => ShouldRun::No,
+    }
+}
+
+/// Asteroids and debris, which only singleplayer has for now. Particle fields aren't sent to
+/// network clients, so on the server they'd be invisible things that kill ships. Collisions with
+/// particles are run along with the other hit tests, see [`collision_rules`].
+fn particle_rules(set: SystemSet) -> SystemSet {
+    set.with_system(particles::spawn_ship_debris)
+        .with_system(particles::decay_particles)
+}
+
+/// Systems that draw the match, wherever the simulation is running
+fn game_presentation(set: SystemSet) -> SystemSet {
+    set.with_system(enforce_size)
+        .with_system(attach_sprites)
+        .with_system(attach_atmosphere_halos)
+        .with_system(show_heat)
+        .with_system(animate_sprite_system)
+        .with_system(fps_counter)
+        .with_system(update_warp_display)
+        .with_system(hud::update_telemetry)
+        .with_system(hud::update_target_panel)
+        .with_system(hud::update_objective_panel)
+        .with_system(hud::update_edge_indicators.after(CameraSystem::Move))
+        .with_system(
+            camera::update_camera_frame
+                .label(CameraSystem::Frame)
+                .after(PhysicsSystem::SyncSprites),
+        )
+        .with_system(
+            camera::handle_camera_actions
+                .label(CameraSystem::Input)
+                .after(CameraSystem::Frame),
+        )
+        .with_system(
+            camera::handle_camera_mouse
+                .label(CameraSystem::Input)
+                .after(CameraSystem::Frame),
+        )
+        .with_system(camera::handle_window_zoom.label(CameraSystem::Input))
+        .with_system(
+            minimap::handle_minimap_click
+                .label(CameraSystem::Input)
+                .after(CameraSystem::Frame),
+        )
+        .with_system(
+            camera::move_camera
+                .label(CameraSystem::Move)
+                .after(CameraSystem::Input),
+        )
+        .with_system(trails::cycle_trail_frame)
+        .with_system(
+            trails::update_snail_trails
+                .after(PhysicsSystem::Integrate)
+                .after(CameraSystem::Input),
+        )
+        .with_system(trails::despawn_orphaned_trails)
+        .with_system(particles::attach_particle_meshes)
+        .with_system(particles::update_particle_meshes.after(PhysicsSystem::Integrate))
+        .with_system(create_explosion)
+        .with_system(minimap::update_minimap_view)
+        .with_system(minimap::sync_minimap_blips)
+        .with_system(minimap::update_minimap_blips)
+        .with_system(overlays::toggle_overlays)
+        .with_system(overlays::clear_overlays.label(OverlaySystem::Clear))
+        .with_system(
+            overlays::draw_lagrange_overlay
+                .label(OverlaySystem::Draw)
+                .after(OverlaySystem::Clear)
+                .after(CameraSystem::Move),
+        )
+        .with_system(
+            overlays::draw_collider_overlay
+                .label(OverlaySystem::Draw)
+                .after(OverlaySystem::Clear)
+                .after(CameraSystem::Move),
+        )
+        .with_system(
+            overlays::draw_conservation_graph
+                .label(OverlaySystem::Draw)
+                .after(OverlaySystem::Clear)
+                .after(CameraSystem::Move),
+        )
+        .with_system(
+            overlays::draw_vector_overlay
+                .label(OverlaySystem::Draw)
+                .after(OverlaySystem::Clear)
+                .after(CameraSystem::Move)
+                .after(PhysicsSystem::Integrate),
+        )
 }
 
 fn init_camera(mut commands: Commands) {
@@ -88,87 +463,78 @@ fn init_camera(mut commands: Commands) {
 fn initialize_components(
     mut commands: Commands,
     typography: Res<Typography>,
-    asset_server: Res<AssetServer>,
+    scenarios: Res<Scenarios>,
+    setup: Res<MatchSetup>,
+    mut rules: ResMut<MatchRules>,
+    mut registry: ResMut<TeamRegistry>,
+    mut spawn_points: ResMut<SpawnPoints>,
 ) {
-    commands
-        .spawn_bundle(StarBundle {
-            position: Position(Vec2::new(0., 0.)),
-            mass: Mass(3e15),
-            ..Default::default()
-        })
-        .insert_bundle(SpriteBundle {
-            texture: asset_server.load("images/planet.png"),
-            ..Default::default()
-        });
+    let teams = setup.teams();
+    let system = match scenarios.0.get(setup.scenario) {
+        Some(scenario) => scenario.system.build(&teams),
+        None => StarSystem::default().build(&teams),
+    };
+    generator::spawn_generated_system(&mut commands, &system);
+    spawn_points.0 = system.spawn_points;
 
-    const max: u32 = 5;
-    const diam: f32 = 500.;
-    const speed: f32 = 90.;
-    for i in 0..max {
-        let angle = std::f32::consts::PI * 2.0 * (i as f32) / (max as f32);
-        let planet_pos = Vec2::new(angle.sin() * (diam), angle.cos() * (diam));
-        commands
-            .spawn_bundle(PlanetBundle {
-                position: Position(planet_pos),
-                mass: Mass(1e15),
-                velocity: Velocity(Vec2::new(-angle.cos() * speed, angle.sin() * speed)),
-                ..Default::default()
-            })
-            .insert_bundle(SpriteBundle {
-                texture: asset_server.load("images/planet.png"),
-                ..Default::default()
-            });
+    rules.missile_limit = setup.missile_limit;
+    rules.missile_lifespan = setup.missile_lifespan;
+    rules.friendly_fire = setup.friendly_fire;
+    rules.victory = setup.victory;
+    registry.clear_alliances();
+    for (a, b) in setup.alliances() {
+        registry.ally(a, b);
+    }
+    spawn_match_ships(&mut commands, &spawn_points.0, &setup);
+    spawn_hud(&mut commands, &typography);
+}
 
-        commands
-            .spawn_bundle(PlanetBundle {
-                position: Position(planet_pos + Vec2::new(45., 45.)),
-                mass: Mass(1e9),
-                velocity: Velocity(Vec2::new(-20.0, -1.0)),
-                size: Size(0.5),
-                ..Default::default()
-            })
-            .insert_bundle(SpriteBundle {
-                texture: asset_server.load("images/planet.png"),
-                ..Default::default()
-            });
+fn initialize_hud(mut commands: Commands, typography: Res<Typography>) {
+    spawn_hud(&mut commands, &typography);
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn initialize_server_world(mut commands: Commands) {
+    // players' ships are spawned by the server as they join
+    generator::spawn_generated_system(&mut commands, &StarSystem::Classic.build(&[]));
+}
+
+/// The player's ship at the first spawn point, plus an enemy at each of the others. The first
+/// few enemies get flown by the computer, as many as `setup` asks for.
+fn spawn_match_ships(commands: &mut Commands, spawn_points: &[SpawnPoint], setup: &MatchSetup) {
+    let (player, enemies) = match spawn_points.split_first() {
+        Some(split) => split,
+        None => return,
+    };
+    spawn_player_ship(commands, player);
+    for (idx, enemy) in enemies.iter().enumerate() {
+        let mut ship = commands.spawn_bundle(ship_at(enemy));
+        if (idx as u32) < setup.ai_opponents {
+            ship.insert(AiPilot::new(setup.difficulty));
+        }
     }
+}
 
-    commands
-        .spawn_bundle(ShipBundle {
-            position: Position(Vec2::new(70., 240.)),
-            mass: Mass(0.0001),
-            velocity: Velocity(Vec2::new(40.0, -20.0)),
-            size: Size(0.3),
-            engine: EnginePhysics {
-                current_accel: 0.0,
-                max_accel: 3.0,
-            },
-            ..Default::default()
-        })
-        .insert_bundle(SpriteBundle {
-            texture: asset_server.load("images/ship.png"),
+fn ship_at(spawn: &SpawnPoint) -> ShipBundle {
+    ShipBundle {
+        position: Position(spawn.position),
+        mass: Mass(0.0001),
+        velocity: Velocity(spawn.velocity),
+        size: Size(0.3),
+        engine: EnginePhysics {
+            max_accel: 1.0,
             ..Default::default()
-        });
+        },
+        team: spawn.team.clone(),
+        ..Default::default()
+    }
+}
 
-    commands
-        .spawn_bundle(ShipBundle {
-            position: Position(Vec2::new(300., 0.)),
-            mass: Mass(0.0001),
-            velocity: Velocity(Vec2::new(0.0, -40.0)),
-            size: Size(0.3),
-            engine: EnginePhysics {
-                current_accel: 0.0,
-                max_accel: 3.0,
-            },
-            team: Team(NonZeroU32::new(3)),
-            ..Default::default()
-        })
-        .insert_bundle(SpriteBundle {
-            // FIXME: enemy ships should use a different sprite/color
-            texture: asset_server.load("images/ship.png"),
-            ..Default::default()
-        });
+fn spawn_player_ship(commands: &mut Commands, spawn: &SpawnPoint) {
+    commands.spawn_bundle(ship_at(spawn)).insert(LocalPlayer);
+}
 
+fn spawn_hud(commands: &mut Commands, typography: &Typography) {
     // create the fps counter
     commands.spawn().insert(FPSCount).insert_bundle(TextBundle {
         style: Style {
@@ -180,27 +546,69 @@ fn initialize_components(
         ..Default::default()
     });
 
+    hud::spawn_panels(commands, typography);
+
     commands
         .spawn()
-        .insert(MissileCount)
+        .insert(WarpDisplay)
         .insert_bundle(TextBundle {
             style: Style {
-                align_self: AlignSelf::FlexStart,
-                flex_direction: FlexDirection::Row,
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    top: Val::Px(5.0),
+                    right: Val::Px(10.0),
+                    ..Default::default()
+                },
                 ..Default::default()
             },
-            text: Text::from_section("missileCount", typography.body.clone()),
+            text: Text::from_section("", typography.body.clone()),
             ..Default::default()
         });
 }
 
-fn update_missilecount(
-    missiles: Query<Entity, With<Missile>>,
-    mut missile_count_texts: Query<&mut Text, With<MissileCount>>,
+/// Gives a sprite to every body that doesn't have one yet, whether it was spawned locally or
+/// replicated from a server
+fn attach_sprites(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    registry: Res<TeamRegistry>,
+    bodies: Query<
+        (
+            Entity,
+            &Position,
+            Option<&Team>,
+            Option<&Ship>,
+            Option<&Missile>,
+        ),
+        (
+            Without<Handle<Image>>,
+            Or<(With<GravitySource>, With<Ship>, With<Missile>)>,
+        ),
+    >,
 ) {
-    let missile_count = missiles.iter().count();
-    for mut missile_count_text in missile_count_texts.iter_mut() {
-        missile_count_text.sections[0].value = format!("{}", missile_count);
+    for (id, Position(pos), team, ship, missile) in bodies.iter() {
+        let image = if ship.is_some() {
+            "images/ship.png"
+        } else if missile.is_some() {
+            "images/missile.png"
+        } else {
+            "images/planet.png"
+        };
+
+        commands.entity(id).insert_bundle(SpriteBundle {
+            texture: asset_server.load(image),
+            sprite: Sprite {
+                color: team.map_or(Color::WHITE, |team| registry.color(team)),
+                ..Default::default()
+            },
+            transform: Transform {
+                translation: pos.extend(0.0),
+                // enforce_size sets the real scale, this just stops the sprite flashing at full size
+                scale: Vec3::ZERO,
+                ..Default::default()
+            },
+            ..Default::default()
+        });
     }
 }
 
@@ -234,39 +642,6 @@ fn animate_sprite_system(
     }
 }
 
-fn render_snailtrail(
-    mut commands: Commands,
-    mut objects_with_snail_trail: Query<(&Position, &mut SnailTrail)>,
-    snail_trails: Query<Entity, With<SnailTrailEntityMarker>>,
-) {
-    // despawn the old snail trails
-    for old_snail_trail in snail_trails.iter() {
-        commands.entity(old_snail_trail).despawn();
-    }
-
-    // update the snail trail (point vec) for the objects
-    // and render the snail trail
-    // TODO: only collect the position maybe 15 times a second? does not need to run every framemaybe only collect
-    for (pos, mut trail) in objects_with_snail_trail.iter_mut() {
-        trail.points.push(Into::<(f32, f32)>::into(pos.0).into());
-
-        // TODO: remove magic number
-        if trail.points.len() > trail.max_points {
-            trail.points.remove(0);
-        }
-        commands
-            .spawn_bundle(GeometryBuilder::build_as(
-                &*trail,
-                DrawMode::Stroke(StrokeMode {
-                    options: StrokeOptions::default(),
-                    color: Color::WHITE,
-                }),
-                Transform::default(),
-            ))
-            .insert(SnailTrailEntityMarker);
-    }
-}
-
 fn fps_counter(time: Res<Diagnostics>, mut texts: Query<&mut Text, With<FPSCount>>) {
     if let Some(fps_stats) = time.get(FrameTimeDiagnosticsPlugin::FPS) {
         if let Some(fps_num) = fps_stats.average() {
@@ -277,6 +652,28 @@ fn fps_counter(time: Res<Diagnostics>, mut texts: Query<&mut Text, With<FPSCount
     }
 }
 
+fn update_warp_display(
+    warp: Res<TimeWarp>,
+    sim_time: Res<SimulationTime>,
+    mut texts: Query<&mut Text, With<WarpDisplay>>,
+) {
+    let label = if warp.factor == 1. {
+        String::new()
+    } else if sim_time.achieved_warp < warp.factor * 0.99 {
+        format!(
+            "Time warp x{} (limited to x{:.1})",
+            warp.factor, sim_time.achieved_warp
+        )
+    } else {
+        format!("Time warp x{}", warp.factor)
+    };
+    for mut text in texts.iter_mut() {
+        if text.sections[0].value != label {
+            text.sections[0].value = label.clone();
+        }
+    }
+}
+
 fn enforce_size(mut size_sprite: Query<(&mut Transform, &Size)>) {
     for (mut sprite_pos, Size(size)) in size_sprite.iter_mut() {
         sprite_pos.scale = Vec3::splat(*size);
@@ -284,28 +681,34 @@ fn enforce_size(mut size_sprite: Query<(&mut Transform, &Size)>) {
 }
 
 fn connect_ship_acceleration_to_user_input(
-    mut query: Query<&mut EnginePhysics>,
-    ship: Query<(&Position, &Velocity, &Team), With<Ship>>,
-    keyboard_input: Res<Input<KeyCode>>,
+    mut ship: Query<
+        (
+            Entity,
+            &Position,
+            &Velocity,
+            &Team,
+            &mut EnginePhysics,
+            &mut Magazine,
+        ),
+        With<LocalPlayer>,
+    >,
+    actions: Res<ActionState>,
+    mut stats: ResMut<PlayerStats>,
     mut spawn_missile_event: EventWriter<SpawnMissileFromShip>,
 ) {
-    if let Some(mut val) = query.iter_mut().next() {
-        if keyboard_input.pressed(KeyCode::Up) {
-            val.current_accel = 1.;
-        } else if keyboard_input.pressed(KeyCode::Down) {
-            val.current_accel = -1.;
-        } else {
-            val.current_accel = 0.;
-        }
+    if let Some((ship_id, ship_pos, ship_vel, ship_team, mut engine, mut magazine)) =
+        ship.iter_mut().next()
+    {
+        engine.set_controls(actions.throttle(), actions.turn());
 
-        if keyboard_input.pressed(KeyCode::Space) {
-            if let Some((ship_pos, ship_vel, ship_team)) = ship.iter().next() {
-                spawn_missile_event.send(SpawnMissileFromShip {
-                    position: ship_pos.clone(),
-                    velocity: Velocity(ship_vel.0 + (45.0 * ship_vel.0.normalize())),
-                    team: ship_team.clone(),
-                });
-            }
+        if actions.pressed(Action::Fire) && magazine.take() {
+            stats.shots_fired += 1;
+            spawn_missile_event.send(SpawnMissileFromShip {
+                ship: ship_id,
+                position: ship_pos.clone(),
+                velocity: Velocity(ship_vel.0 + (45.0 * ship_vel.0.normalize())),
+                team: ship_team.clone(),
+            });
         }
     }
 }
@@ -313,82 +716,123 @@ fn connect_ship_acceleration_to_user_input(
 fn handle_spawn_missile_event(
     mut event_reader: EventReader<SpawnMissileFromShip>,
     mut commands: Commands,
-    time: Res<Time>,
-    asset_server: Res<AssetServer>,
+    sim_time: Res<SimulationTime>,
+    rules: Res<MatchRules>,
 ) {
     for missile_spawn_request in event_reader.iter() {
-        commands
-            .spawn_bundle(MissileBundle {
-                position: missile_spawn_request.position.clone(),
-                velocity: missile_spawn_request.velocity.clone(),
-                team: missile_spawn_request.team.clone(),
-                size: Size(0.17),
-                lifespan: Lifespan {
-                    created_on: time.seconds_since_startup(),
-                    lifespan: 150000.0,
-                },
-                snail_trail: SnailTrail {
-                    max_points: 30,
-                    points: Vec::with_capacity(3),
-                },
-                ..Default::default()
-            })
-            .insert_bundle(SpriteBundle {
-                texture: asset_server.load("images/missile.png"),
-                transform: Transform {
-                    translation: Vec3::new(
-                        missile_spawn_request.position.0.x,
-                        missile_spawn_request.position.0.y,
-                        0.0,
-                    ),
-                    scale: Vec3::ZERO,
-                    ..Default::default()
-                },
-                ..Default::default()
-            });
+        commands.spawn_bundle(MissileBundle {
+            position: missile_spawn_request.position.clone(),
+            velocity: missile_spawn_request.velocity.clone(),
+            team: missile_spawn_request.team.clone(),
+            fired_by: FiredBy(Some(missile_spawn_request.ship)),
+            size: Size(0.17),
+            lifespan: Lifespan {
+                created_on: sim_time.elapsed,
+                lifespan: rules.missile_lifespan.unwrap_or(f64::INFINITY),
+            },
+            ..Default::default()
+        });
+    }
+}
+
+/// Gives every newly spawned ship as many missiles as the rules allow
+fn load_magazines(rules: Res<MatchRules>, mut magazines: Query<&mut Magazine, Added<Magazine>>) {
+    for mut magazine in magazines.iter_mut() {
+        magazine.0 = rules.missile_limit;
     }
 }
 
 fn kill_expired_objects(
     mut commands: Commands,
-    time: Res<Time>,
+    sim_time: Res<SimulationTime>,
+    mut destroyed: ResMut<Destroyed>,
     lifespan_objects: Query<(Entity, &Lifespan)>,
 ) {
     for (id, lifespan) in lifespan_objects.iter() {
-        if lifespan.created_on + lifespan.lifespan < time.seconds_since_startup() {
+        if lifespan.created_on + lifespan.lifespan < sim_time.elapsed && destroyed.0.insert(id) {
             commands.entity(id).despawn();
         }
     }
 }
 
+/// Everything destroyed last frame has been despawned by now
+fn forget_destroyed_bodies(mut destroyed: ResMut<Destroyed>) {
+    destroyed.0.clear();
+}
+
 fn check_if_missile_should_kill_ship(
     mut commands: Commands,
-    ships: Query<(Entity, &Position, &Velocity, &Team, &Sprite, &Size), With<Ship>>,
-    missiles: Query<(Entity, &Position, &Velocity, &Team), With<Missile>>,
+    mut destroyed: ResMut<Destroyed>,
+    registry: Res<TeamRegistry>,
+    rules: Res<MatchRules>,
+    sim_time: Res<SimulationTime>,
+    ships: Query<(Entity, &Position, &Velocity, &Team, &Size, &Collider), With<Ship>>,
+    missiles: Query<
+        (
+            Entity,
+            &Position,
+            &Velocity,
+            &Team,
+            &Size,
+            &Collider,
+            &Lifespan,
+            &FiredBy,
+        ),
+        With<Missile>,
+    >,
     mut explosion_event: EventWriter<CreateExplosionEvent>,
+    mut destroyed_event: EventWriter<ShipDestroyedEvent>,
 ) {
-    for (
-        ship_id,
-        Position(ship_pos),
-        Velocity(ship_vel),
-        Team(ship_team_id),
-        ship_sprite,
-        Size(ship_size),
-    ) in ships.iter()
+    for (ship_id, Position(ship_pos), Velocity(ship_vel), ship_team, ship_size, ship_collider) in
+        ships.iter()
     {
-        for (missile_id, Position(missile_pos), Velocity(missile_vel), Team(missile_team_id)) in
-            missiles.iter()
+        if destroyed.0.contains(&ship_id) {
+            continue;
+        }
+        let ship_hitbox = ship_collider.placed(*ship_pos, *ship_vel, ship_size);
+        for (
+            missile_id,
+            Position(missile_pos),
+            Velocity(missile_vel),
+            missile_team,
+            missile_size,
+            missile_collider,
+            lifespan,
+            FiredBy(shooter),
+        ) in missiles.iter()
         {
-            if ship_team_id != missile_team_id {
-                let dist_from_ship_to_missile = (*ship_pos - *missile_pos).length();
-                let ship_size = 50.0 * 0.5 * ship_size;
-                if dist_from_ship_to_missile < ship_size {
+            if destroyed.0.contains(&missile_id) {
+                continue;
+            }
+            if missile_can_hit(
+                &registry,
+                &rules,
+                &sim_time,
+                ship_team,
+                missile_team,
+                lifespan,
+            ) {
+                let missile_hitbox =
+                    missile_collider.placed(*missile_pos, *missile_vel, missile_size);
+                if ship_hitbox.overlaps(&missile_hitbox) {
                     commands.entity(ship_id).despawn();
                     commands.entity(missile_id).despawn();
+                    destroyed.0.insert(ship_id);
+                    destroyed.0.insert(missile_id);
                     explosion_event.send(CreateExplosionEvent {
                         position: Position(*ship_pos),
                         velocity: Velocity(*ship_vel + *missile_vel),
                     });
+                    destroyed_event.send(ShipDestroyedEvent {
+                        ship: ship_id,
+                        team: ship_team.clone(),
+                        killer_team: missile_team.clone(),
+                        killer: *shooter,
+                        position: Position(*ship_pos),
+                        velocity: Velocity(*ship_vel),
+                    });
+                    // the ship is gone, don't let a second missile kill it again
+                    break;
                 }
             }
         }
@@ -416,19 +860,24 @@ fn create_explosion(
 
 fn explode_missiles_near_planets(
     mut commands: Commands,
-    missiles: Query<(Entity, &Position, &Velocity), With<Missile>>,
-    planets: Query<(&Position, &Transform, &Size), With<GravitySource>>,
+    mut destroyed: ResMut<Destroyed>,
+    missiles: Query<(Entity, &Position, &Velocity, &Size, &Collider), With<Missile>>,
+    planets: Query<(&Position, Option<&Velocity>, &Size, &Collider), With<GravitySource>>,
     mut explosion_event: EventWriter<CreateExplosionEvent>,
 ) {
-    for (missile_id, missile_pos, missile_vel) in missiles.iter() {
-        'planets_loop: for (planet_pos, planet_sprite, Size(planet_size)) in planets.iter() {
-            // Assumes that the planet is circular, this will need to get changed if we want lumpy asteroid type things or smth
-            // tD
-            let planet_radius = 50.0 * planet_size * 0.5;
-            let distance_between_missile_and_planet = (planet_pos.0 - missile_pos.0).length();
-
-            if distance_between_missile_and_planet < planet_radius {
+    for (missile_id, missile_pos, missile_vel, missile_size, missile_collider) in missiles.iter() {
+        if destroyed.0.contains(&missile_id) {
+            continue;
+        }
+        let missile_hitbox = missile_collider.placed(missile_pos.0, missile_vel.0, missile_size);
+        'planets_loop: for (planet_pos, planet_vel, planet_size, planet_collider) in planets.iter()
+        {
+            let planet_vel = planet_vel.map_or(Vec2::ZERO, |v| v.0);
+            let planet_hitbox = planet_collider.placed(planet_pos.0, planet_vel, planet_size);
+
+            if missile_hitbox.overlaps(&planet_hitbox) {
                 commands.entity(missile_id).despawn();
+                destroyed.0.insert(missile_id);
                 explosion_event.send(CreateExplosionEvent {
                     position: missile_pos.clone(),
                     velocity: missile_vel.clone(),
@@ -439,51 +888,184 @@ fn explode_missiles_near_planets(
     }
 }
 
-fn kill_out_of_bounds_missiles(
+/// Ships and missiles that got too hot coming through an atmosphere burn up
+fn burn_up_overheated_bodies(
     mut commands: Commands,
-    cam_trans_query: Query<&Transform, With<Camera>>,
-    window: Res<Windows>,
-    missiles: Query<(Entity, &Transform), With<Missile>>,
+    mut destroyed: ResMut<Destroyed>,
+    bodies: Query<(
+        Entity,
+        &Heat,
+        &Position,
+        &Velocity,
+        Option<&Team>,
+        Option<&Ship>,
+    )>,
+    mut explosion_event: EventWriter<CreateExplosionEvent>,
+    mut destroyed_event: EventWriter<ShipDestroyedEvent>,
 ) {
-    let cam_trans = cam_trans_query.single();
-    let cam_scale = cam_trans.scale.max_element();
-    let cam_offset = cam_trans.translation;
-
-    let window = window.get_primary().unwrap();
-    let (half_width, half_height) = (
-        window.width() * cam_scale / 2.0,
-        window.height() * cam_scale / 2.0,
-    );
-    for (missile_id, missile_pos) in missiles.iter() {
-        if !(-half_width < missile_pos.translation.x - cam_offset.x
-            && missile_pos.translation.x - cam_offset.x < half_width
-            && -half_height < missile_pos.translation.y - cam_offset.y
-            && missile_pos.translation.y - cam_offset.y < half_height)
-        {
-            commands.entity(missile_id).despawn();
+    for (entity, Heat(heat), pos, vel, team, ship) in bodies.iter() {
+        if *heat < 1. || destroyed.0.contains(&entity) {
+            continue;
+        }
+
+        commands.entity(entity).despawn();
+        destroyed.0.insert(entity);
+        explosion_event.send(CreateExplosionEvent {
+            position: pos.clone(),
+            velocity: vel.clone(),
+        });
+        if let (Some(_), Some(team)) = (ship, team) {
+            destroyed_event.send(ShipDestroyedEvent {
+                ship: entity,
+                team: team.clone(),
+                // nobody shot it down, so nobody gets the kill
+                killer_team: Team(None),
+                killer: None,
+                position: pos.clone(),
+                velocity: vel.clone(),
+            });
         }
     }
 }
 
-fn handle_window_zoom(
-    keyboard_input: ResMut<Input<KeyCode>>,
-    mut camera: Query<&mut Transform, With<ShipCamera>>,
+/// Draws a faint haze around planets with an atmosphere, thickest at the surface
+fn attach_atmosphere_halos(
+    mut commands: Commands,
+    planets: Query<(Entity, &Atmosphere, &Size), Added<Atmosphere>>,
 ) {
-    let mut cam = camera.single_mut();
-    // scale vec should always have x == y == z. so if x == y == z == 1, length squared == 3
-    if keyboard_input.pressed(KeyCode::Equals) && cam.scale.length_squared() > 3. {
-        cam.scale /= 1.01;
-    } else if keyboard_input.pressed(KeyCode::Minus) && cam.scale.length_squared() < 200. {
-        cam.scale *= 1.01;
+    for (planet, atmosphere, Size(size)) in planets.iter() {
+        commands.entity(planet).with_children(|parent| {
+            for layers in [1., 2., 4.] {
+                // the halo is scaled along with the planet's sprite, which is 50 pixels across
+                let radius = 25. + layers * atmosphere.scale_height / size;
+                parent.spawn_bundle(GeometryBuilder::build_as(
+                    &bevy_prototype_lyon::shapes::Circle {
+                        radius,
+                        center: Vec2::ZERO,
+                    },
+                    DrawMode::Fill(FillMode::color(Color::rgba(0.5, 0.7, 1.0, 0.1))),
+                    Transform::from_xyz(0., 0., -0.5),
+                ));
+            }
+        });
+    }
+}
+
+/// Tints ships and missiles towards orange as they heat up
+fn show_heat(
+    registry: Res<TeamRegistry>,
+    mut bodies: Query<(&Heat, &mut Sprite, Option<&Team>), Changed<Heat>>,
+) {
+    let hot = Vec4::from(Color::rgb(1.0, 0.45, 0.1).as_rgba_f32());
+    for (Heat(heat), mut sprite, team) in bodies.iter_mut() {
+        let base = team.map_or(Color::WHITE, |team| registry.color(team));
+        sprite.color = Vec4::from(base.as_rgba_f32())
+            .lerp(hot, heat.clamp(0., 1.))
+            .into();
+    }
+}
+
+fn handle_time_warp_input(actions: Res<ActionState>, mut warp: ResMut<TimeWarp>) {
+    if actions.just_pressed(Action::WarpFaster) {
+        warp.faster();
+    } else if actions.just_pressed(Action::WarpSlower) {
+        warp.slower();
+    }
+}
+
+/// Seconds until something at `offset` moving at `rel_vel` (relative to something else) comes
+/// within `radius` of it, assuming both keep going in a straight line. Infinite if it never does.
+fn time_to_contact(offset: Vec2, rel_vel: Vec2, radius: f32) -> f32 {
+    // solve |offset + rel_vel * t| = radius for t
+    let a = rel_vel.length_squared();
+    let b = 2. * offset.dot(rel_vel);
+    let c = offset.length_squared() - radius * radius;
+    if c <= 0. {
+        return 0.;
+    }
+    // heading apart (or not moving at all)
+    if a <= f32::EPSILON || b >= 0. {
+        return f32::INFINITY;
+    }
+    let discriminant = b * b - 4. * a * c;
+    if discriminant < 0. {
+        return f32::INFINITY;
     }
+    (-b - discriminant.sqrt()) / (2. * a)
 }
 
-fn follow_ship(
-    mut camera: Query<&mut Transform, (With<ShipCamera>, Without<Ship>)>,
-    ship: Query<&Transform, (With<Ship>, Without<ShipCamera>)>,
+/// Drops back to real time when a ship is about to hit a planet or an enemy missile is closing
+/// in, so that the player gets to see it coming
+fn drop_time_warp_near_danger(
+    mut warp: ResMut<TimeWarp>,
+    registry: Res<TeamRegistry>,
+    rules: Res<MatchRules>,
+    sim_time: Res<SimulationTime>,
+    ships: Query<(&Position, &Velocity, &Team, &Size, &Collider), With<Ship>>,
+    missiles: Query<(&Position, &Velocity, &Team, &Lifespan), With<Missile>>,
+    planets: Query<(&Position, Option<&Velocity>, &Size, &Collider), With<GravitySource>>,
 ) {
-    let ship_trans = ship.iter().next().unwrap().translation;
-    camera.single_mut().translation = ship_trans;
+    // real seconds of warning the player gets
+    const WARNING_TIME: f32 = 2.0;
+    const MISSILE_WARNING_DISTANCE: f32 = 300.0;
+
+    if warp.factor <= 1. {
+        return;
+    }
+    let horizon = WARNING_TIME * warp.factor;
+
+    let in_danger = ships.iter().any(
+        |(Position(ship_pos), Velocity(ship_vel), ship_team, Size(ship_size), ship_collider)| {
+            // a ship can be turned any which way by the time it gets there, so these go by how
+            // far each shape reaches at most
+            let ship_radius = ship_collider.bounding_radius() * ship_size;
+            let hits_planet = planets.iter().any(
+                |(Position(planet_pos), planet_vel, Size(planet_size), planet_collider)| {
+                    let planet_vel = planet_vel.map_or(Vec2::ZERO, |v| v.0);
+                    time_to_contact(
+                        *ship_pos - *planet_pos,
+                        *ship_vel - planet_vel,
+                        planet_collider.bounding_radius() * planet_size,
+                    ) < horizon
+                },
+            );
+            let missile_incoming = missiles
+                .iter()
+                .filter(|(_, _, missile_team, lifespan)| {
+                    missile_can_hit(
+                        &registry,
+                        &rules,
+                        &sim_time,
+                        ship_team,
+                        missile_team,
+                        lifespan,
+                    )
+                })
+                .any(|(Position(missile_pos), Velocity(missile_vel), _, _)| {
+                    let (offset, rel_vel) = (*missile_pos - *ship_pos, *missile_vel - *ship_vel);
+                    // missiles that have already gone past aren't a worry
+                    let closing = offset.dot(rel_vel) < 0.;
+                    closing
+                        && (offset.length() < MISSILE_WARNING_DISTANCE
+                            || time_to_contact(offset, rel_vel, ship_radius) < horizon)
+                });
+            hits_planet || missile_incoming
+        },
+    );
+    if in_danger {
+        warp.factor = 1.;
+    }
+}
+
+fn end_game_when_match_is_decided(
+    match_state: Res<MatchState>,
+    mut state: ResMut<State<AppState>>,
+) {
+    if match_state.is_changed() && match_state.outcome.is_some() {
+        if let Err(e) = state.set(AppState::GameOver) {
+            warn!("issue changing state into AppState::GameOver??\n{}", e);
+        }
+    }
 }
 
 fn delete_all_entities(mut commands: Commands, entities: Query<Entity, Without<Camera>>) {
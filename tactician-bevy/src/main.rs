@@ -1,7 +1,48 @@
-use tactician_bevy::run_game;
+use tactician_bevy::{run_game, run_game_in, GeneratorSettings, StarSystem};
 
+fn main() {
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        let args: Vec<String> = std::env::args().skip(1).collect();
 
+        // `--connect <address>` joins a dedicated server instead of playing locally
+        if args.iter().any(|arg| arg == "--connect") {
+            match tactician_bevy::ClientConfig::from_args(&args) {
+                Ok(config) => return tactician_bevy::run_client(config),
+                Err(e) => {
+                    eprintln!("{}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+
+        // `--system <name>` picks one of the hand-made star systems
+        if let Some(idx) = args.iter().position(|arg| arg == "--system") {
+            match args.get(idx + 1).and_then(|name| StarSystem::from_name(name)) {
+                Some(system) => return run_game_in(system),
+                None => {
+                    eprintln!("--system needs to be one of classic, binary or triple");
+                    std::process::exit(1);
+                }
+            }
+        }
+
+        // `--seed <number>` plays in a generated star system instead of the classic one
+        if let Some(idx) = args.iter().position(|arg| arg == "--seed") {
+            match args.get(idx + 1).map(|seed| seed.parse::<u64>()) {
+                Some(Ok(seed)) => {
+                    return run_game_in(StarSystem::Generated(GeneratorSettings {
+                        seed,
+                        ..Default::default()
+                    }))
+                }
+                _ => {
+                    eprintln!("--seed needs a number");
+                    std::process::exit(1);
+                }
+            }
+        }
+    }
 
-fn main() {
     run_game();
 }
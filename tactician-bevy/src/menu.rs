@@ -1,7 +1,288 @@
+use crate::ai::Difficulty;
+use crate::controls::{detect_binding, Action, InputBindings};
+use crate::generator::GeneratedKind;
+use crate::minimap::{PLANET_COLOR, STAR_COLOR};
 use crate::misc::AppState;
+use crate::physics::TimeWarp;
+use crate::player::PlayerStats;
 use crate::resources::Typography;
+use crate::scenarios::{MatchSetup, Scenarios, MAX_TEAMS};
+use crate::settings::Settings;
+use crate::teams::{MatchState, TeamRegistry, VictoryCondition};
 use bevy::prelude::*;
 
+/// Choices for the settings that go up in steps. The zoom limits don't overlap, so the closest
+/// zoom can never end up further out than the furthest.
+pub const TRAIL_LENGTHS: [f32; 5] = [30., 60., 120., 240., 480.];
+pub const MIN_ZOOMS: [f32; 5] = [0.25, 0.5, 1., 2., 5.];
+pub const MAX_ZOOMS: [f32; 5] = [10., 20., 50., 100., 200.];
+pub const TICK_RATES: [f32; 4] = [0.5, 1., 2., 4.];
+const MISSILE_LIMITS: [Option<u32>; 5] = [Some(5), Some(10), Some(20), Some(50), None];
+const MISSILE_LIFESPANS: [Option<f64>; 5] = [Some(30.), Some(60.), Some(120.), Some(300.), None];
+
+/// Width and height of the scenario preview, in pixels
+const PREVIEW_SIZE: f32 = 220.;
+const SELECTED_COLOR: Color = Color::rgb(0.35, 0.5, 0.8);
+
+/// What a button in one of the menu screens does when clicked
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Component)]
+pub enum MenuButton {
+    /// On to the match setup screen
+    Play,
+    /// Starts the match that has been set up
+    Start,
+    Controls,
+    Settings,
+    /// Back to the main menu
+    Back,
+    Resume,
+    Results,
+    Respawn,
+    Spectate,
+}
+
+/// Root of the pause overlay
+#[derive(Debug, Clone, Copy, Component)]
+pub struct PauseMenu;
+
+/// Button on the controls screen that rebinds an action when clicked
+#[derive(Debug, Clone, Copy, Component)]
+pub struct RebindButton(Action);
+
+/// Text listing the bindings of an action
+#[derive(Debug, Clone, Copy, Component)]
+pub struct BindingLabel(Action);
+
+/// The action that the next key/button press gets bound to
+#[derive(Debug, Default)]
+pub struct Rebinding(Option<Action>);
+
+/// One of the options on the settings screen
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Setting {
+    TrailLength,
+    MinZoom,
+    MaxZoom,
+    TimeWarpCap,
+    PhysicsTickRate,
+    ColorblindPalette,
+    Volume,
+}
+
+impl Setting {
+    pub const ALL: [Setting; 7] = [
+        Setting::TrailLength,
+        Setting::MinZoom,
+        Setting::MaxZoom,
+        Setting::TimeWarpCap,
+        Setting::PhysicsTickRate,
+        Setting::ColorblindPalette,
+        Setting::Volume,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Setting::TrailLength => "Trail length",
+            Setting::MinZoom => "Closest zoom",
+            Setting::MaxZoom => "Furthest zoom",
+            Setting::TimeWarpCap => "Max time warp",
+            Setting::PhysicsTickRate => "Physics tick rate",
+            Setting::ColorblindPalette => "Colorblind palette",
+            Setting::Volume => "Volume",
+        }
+    }
+
+    fn describe(&self, settings: &Settings) -> String {
+        match self {
+            Setting::TrailLength => format!("{}s", settings.trail_seconds),
+            Setting::MinZoom => format!("{}", settings.min_zoom),
+            Setting::MaxZoom => format!("{}", settings.max_zoom),
+            Setting::TimeWarpCap => format!("x{}", settings.max_time_warp),
+            Setting::PhysicsTickRate => format!("x{}", settings.physics_tick_rate),
+            Setting::ColorblindPalette => if settings.colorblind_palette {
+                "on"
+            } else {
+                "off"
+            }
+            .to_string(),
+            Setting::Volume => format!("{:.0}%", settings.volume * 100.),
+        }
+    }
+
+    /// Moves the setting one step up, or down
+    fn adjust(&self, settings: &mut Settings, up: bool) {
+        match self {
+            Setting::TrailLength => {
+                settings.trail_seconds = step(&TRAIL_LENGTHS, settings.trail_seconds, up)
+            }
+            Setting::MinZoom => settings.min_zoom = step(&MIN_ZOOMS, settings.min_zoom, up),
+            Setting::MaxZoom => settings.max_zoom = step(&MAX_ZOOMS, settings.max_zoom, up),
+            // slower than real time isn't much of a cap
+            Setting::TimeWarpCap => {
+                settings.max_time_warp = step(&TimeWarp::LEVELS, settings.max_time_warp, up).max(1.)
+            }
+            Setting::PhysicsTickRate => {
+                settings.physics_tick_rate = step(&TICK_RATES, settings.physics_tick_rate, up)
+            }
+            Setting::ColorblindPalette => {
+                settings.colorblind_palette = !settings.colorblind_palette
+            }
+            Setting::Volume => {
+                let volume = settings.volume + if up { 0.1 } else { -0.1 };
+                settings.volume = ((volume * 10.).round() / 10.).clamp(0., 1.);
+            }
+        }
+    }
+}
+
+/// The next of `levels` above (or below) `current`, or `current` if there isn't one
+fn step(levels: &[f32], current: f32, up: bool) -> f32 {
+    let next = if up {
+        levels.iter().find(|level| **level > current)
+    } else {
+        levels.iter().rev().find(|level| **level < current)
+    };
+    next.copied().unwrap_or(current)
+}
+
+/// One of the options on the match setup screen, other than which scenario to play
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MatchOption {
+    Teams,
+    AiOpponents,
+    Difficulty,
+    MissileLimit,
+    MissileLifespan,
+    FriendlyFire,
+    Victory,
+    Sides,
+}
+
+impl MatchOption {
+    pub const ALL: [MatchOption; 8] = [
+        MatchOption::Victory,
+        MatchOption::Teams,
+        MatchOption::Sides,
+        MatchOption::AiOpponents,
+        MatchOption::Difficulty,
+        MatchOption::MissileLimit,
+        MatchOption::MissileLifespan,
+        MatchOption::FriendlyFire,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            MatchOption::Teams => "Teams",
+            MatchOption::AiOpponents => "AI opponents",
+            MatchOption::Difficulty => "Difficulty",
+            MatchOption::MissileLimit => "Missiles per ship",
+            MatchOption::MissileLifespan => "Missile lifespan",
+            MatchOption::FriendlyFire => "Friendly fire",
+            MatchOption::Victory => "Victory",
+            MatchOption::Sides => "Sides",
+        }
+    }
+
+    fn describe(&self, setup: &MatchSetup) -> String {
+        match self {
+            MatchOption::Teams => setup.teams.to_string(),
+            MatchOption::AiOpponents => setup.ai_opponents.to_string(),
+            MatchOption::Difficulty => setup.difficulty.name().to_string(),
+            MatchOption::MissileLimit => match setup.missile_limit {
+                Some(limit) => limit.to_string(),
+                None => "unlimited".to_string(),
+            },
+            MatchOption::MissileLifespan => match setup.missile_lifespan {
+                Some(lifespan) => format!("{}s", lifespan),
+                None => "forever".to_string(),
+            },
+            MatchOption::FriendlyFire => if setup.friendly_fire { "on" } else { "off" }.to_string(),
+            MatchOption::Victory => setup.victory.describe(),
+            MatchOption::Sides => if setup.two_sides {
+                "odd vs even teams"
+            } else {
+                "free for all"
+            }
+            .to_string(),
+        }
+    }
+
+    /// Moves the option one step up, or down
+    fn adjust(&self, setup: &mut MatchSetup, up: bool) {
+        match self {
+            MatchOption::Teams => {
+                setup.teams = if up { setup.teams + 1 } else { setup.teams - 1 }.clamp(2, MAX_TEAMS)
+            }
+            MatchOption::AiOpponents => {
+                setup.ai_opponents = if up {
+                    setup.ai_opponents + 1
+                } else {
+                    setup.ai_opponents.saturating_sub(1)
+                }
+            }
+            MatchOption::Difficulty => {
+                setup.difficulty = next_choice(&Difficulty::ALL, setup.difficulty, up)
+            }
+            MatchOption::MissileLimit => {
+                setup.missile_limit = next_choice(&MISSILE_LIMITS, setup.missile_limit, up)
+            }
+            MatchOption::MissileLifespan => {
+                setup.missile_lifespan = next_choice(&MISSILE_LIFESPANS, setup.missile_lifespan, up)
+            }
+            MatchOption::FriendlyFire => setup.friendly_fire = !setup.friendly_fire,
+            MatchOption::Victory => {
+                setup.victory = next_choice(&VictoryCondition::CHOICES, setup.victory, up)
+            }
+            MatchOption::Sides => setup.two_sides = !setup.two_sides,
+        }
+        // there's one ship per team, and one of them is the player's
+        setup.ai_opponents = setup.ai_opponents.min(setup.teams - 1);
+    }
+}
+
+/// The choice after (or before) `current` in `choices`, stopping at either end
+fn next_choice<T: Copy + PartialEq>(choices: &[T], current: T, up: bool) -> T {
+    let idx = choices.iter().position(|choice| *choice == current);
+    let next = match (idx, up) {
+        (Some(idx), true) => idx + 1,
+        (Some(idx), false) => idx.saturating_sub(1),
+        (None, _) => 0,
+    };
+    choices.get(next).copied().unwrap_or(current)
+}
+
+/// Button on the match setup screen that moves an option up (`true`) or down a step
+#[derive(Debug, Clone, Copy, Component)]
+pub struct AdjustMatchOption(MatchOption, bool);
+
+/// Text showing the value of a match option
+#[derive(Debug, Clone, Copy, Component)]
+pub struct MatchOptionLabel(MatchOption);
+
+/// Button that picks one of the [`Scenarios`]
+#[derive(Debug, Clone, Copy, Component)]
+pub struct ScenarioButton(usize);
+
+/// Text describing the picked scenario
+#[derive(Debug, Clone, Copy, Component)]
+pub struct ScenarioDescription;
+
+/// Box that a map of the picked scenario is drawn in, with a [`PreviewBlip`] for every body and
+/// spawn point
+#[derive(Debug, Clone, Copy, Component)]
+pub struct ScenarioPreview;
+
+#[derive(Debug, Clone, Copy, Component)]
+pub struct PreviewBlip;
+
+/// Button on the settings screen that moves a setting up (`true`) or down a step
+#[derive(Debug, Clone, Copy, Component)]
+pub struct AdjustSetting(Setting, bool);
+
+/// Text showing the value of a setting
+#[derive(Debug, Clone, Copy, Component)]
+pub struct SettingLabel(Setting);
+
 // struct MenuData {
 //     button_entity: Entity,
 // }
@@ -35,41 +316,738 @@ pub fn init_menu(
                 ..Default::default()
             });
 
+            spawn_menu_button(parent, &typography, "Play", MenuButton::Play);
+            spawn_menu_button(parent, &typography, "Settings", MenuButton::Settings);
+            spawn_menu_button(parent, &typography, "Controls", MenuButton::Controls);
+        });
+
+    // commands.insert_resource(MenuData {
+    //     button_entity: button_id,
+    // });
+}
+
+pub fn spawn_menu_button(
+    parent: &mut ChildBuilder,
+    typography: &Typography,
+    label: &str,
+    button: MenuButton,
+) {
+    parent
+        .spawn_bundle(ButtonBundle {
+            style: Style {
+                size: Size::new(Val::Percent(25.0), Val::Px(60.0)),
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..Default::default()
+            },
+            color: Color::GRAY.into(),
+            ..Default::default()
+        })
+        .insert(button)
+        .with_children(|parent| {
+            parent.spawn_bundle(TextBundle {
+                text: Text::from_section(label, typography.body.clone()),
+                ..Default::default()
+            });
+        });
+}
+
+pub fn update_menu(
+    mut state: ResMut<State<AppState>>,
+    query: Query<(&Interaction, &MenuButton), Changed<Interaction>>,
+) {
+    for (interaction, button) in query.iter() {
+        if *interaction == Interaction::Clicked {
+            let result = match button {
+                MenuButton::Play => state.set(AppState::MatchSetup),
+                MenuButton::Start => state.set(AppState::Game),
+                MenuButton::Controls => state.set(AppState::Controls),
+                MenuButton::Settings => state.set(AppState::Settings),
+                MenuButton::Results => state.set(AppState::Results),
+                // throws away the paused match too, if there is one
+                MenuButton::Back => state.replace(AppState::Menu),
+                MenuButton::Resume => state.pop(),
+                // handled in game, see player::update_death_menu
+                MenuButton::Respawn | MenuButton::Spectate => continue,
+            };
+            if let Err(e) = result {
+                warn!("issue changing state for {:?} button??\n{}", button, e);
+            }
+        }
+    }
+}
+
+pub fn init_controls_menu(
+    mut commands: Commands,
+    typography: Res<Typography>,
+    mut rebinding: ResMut<Rebinding>,
+) {
+    rebinding.0 = None;
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                flex_direction: FlexDirection::ColumnReverse,
+                justify_content: JustifyContent::SpaceEvenly,
+                align_items: AlignItems::Center,
+                ..Default::default()
+            },
+            color: Color::NONE.into(),
+            ..Default::default()
+        })
+        .with_children(|parent| {
+            parent.spawn_bundle(TextBundle {
+                text: Text::from_section("Controls", typography.heading.clone()),
+                ..Default::default()
+            });
+
+            for action in Action::ALL {
+                parent
+                    .spawn_bundle(NodeBundle {
+                        style: Style {
+                            size: Size::new(Val::Percent(60.0), Val::Px(40.0)),
+                            flex_direction: FlexDirection::Row,
+                            justify_content: JustifyContent::SpaceBetween,
+                            align_items: AlignItems::Center,
+                            ..Default::default()
+                        },
+                        color: Color::NONE.into(),
+                        ..Default::default()
+                    })
+                    .with_children(|row| {
+                        row.spawn_bundle(TextBundle {
+                            text: Text::from_section(action.name(), typography.body.clone()),
+                            ..Default::default()
+                        });
+
+                        row.spawn_bundle(ButtonBundle {
+                            style: Style {
+                                size: Size::new(Val::Percent(60.0), Val::Px(34.0)),
+                                justify_content: JustifyContent::Center,
+                                align_items: AlignItems::Center,
+                                ..Default::default()
+                            },
+                            color: Color::GRAY.into(),
+                            ..Default::default()
+                        })
+                        .insert(RebindButton(action))
+                        .with_children(|button| {
+                            button
+                                .spawn_bundle(TextBundle {
+                                    text: Text::from_section("", typography.body.clone()),
+                                    ..Default::default()
+                                })
+                                .insert(BindingLabel(action));
+                        });
+                    });
+            }
+
+            spawn_menu_button(parent, &typography, "Back", MenuButton::Back);
+        });
+}
+
+pub fn update_controls_menu(
+    mut rebinding: ResMut<Rebinding>,
+    query: Query<(&Interaction, &RebindButton), Changed<Interaction>>,
+) {
+    for (interaction, RebindButton(action)) in query.iter() {
+        if *interaction == Interaction::Clicked {
+            rebinding.0 = Some(*action);
+        }
+    }
+}
+
+/// While waiting for a new binding, binds the first key/button/stick that gets pressed.
+/// Escape cancels.
+pub fn capture_binding(
+    mut rebinding: ResMut<Rebinding>,
+    mut bindings: ResMut<InputBindings>,
+    keys: Res<Input<KeyCode>>,
+    gamepads: Res<Gamepads>,
+    gamepad_buttons: Res<Input<GamepadButton>>,
+    gamepad_axes: Res<Axis<GamepadAxis>>,
+) {
+    let action = match rebinding.0 {
+        Some(action) => action,
+        None => return,
+    };
+
+    if keys.just_pressed(KeyCode::Escape) {
+        rebinding.0 = None;
+    } else if let Some(binding) = detect_binding(&keys, &gamepads, &gamepad_buttons, &gamepad_axes)
+    {
+        bindings.rebind(action, binding);
+        rebinding.0 = None;
+    }
+}
+
+pub fn update_binding_labels(
+    rebinding: Res<Rebinding>,
+    bindings: Res<InputBindings>,
+    mut labels: Query<(&mut Text, &BindingLabel, ChangeTrackers<BindingLabel>)>,
+) {
+    let refresh_all = rebinding.is_changed() || bindings.is_changed();
+    for (mut text, BindingLabel(action), label_tracker) in labels.iter_mut() {
+        if !refresh_all && !label_tracker.is_added() {
+            continue;
+        }
+
+        text.sections[0].value = if rebinding.0 == Some(*action) {
+            "press a key or button...".to_string()
+        } else {
+            bindings
+                .get(*action)
+                .iter()
+                .map(|binding| binding.describe())
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+    }
+}
+
+pub fn init_settings_menu(mut commands: Commands, typography: Res<Typography>) {
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                flex_direction: FlexDirection::ColumnReverse,
+                justify_content: JustifyContent::SpaceEvenly,
+                align_items: AlignItems::Center,
+                ..Default::default()
+            },
+            color: Color::NONE.into(),
+            ..Default::default()
+        })
+        .with_children(|parent| {
+            parent.spawn_bundle(TextBundle {
+                text: Text::from_section("Settings", typography.heading.clone()),
+                ..Default::default()
+            });
+
+            for setting in Setting::ALL {
+                spawn_option_row(
+                    parent,
+                    &typography,
+                    setting.name(),
+                    (
+                        AdjustSetting(setting, false),
+                        SettingLabel(setting),
+                        AdjustSetting(setting, true),
+                    ),
+                );
+            }
+
+            spawn_menu_button(parent, &typography, "Key bindings", MenuButton::Controls);
+            spawn_menu_button(parent, &typography, "Back", MenuButton::Back);
+        });
+}
+
+/// A row with the name of an option on the left, and its value on the right between buttons that
+/// step it down and up. `components` go on the down button, the value and the up button.
+fn spawn_option_row(
+    parent: &mut ChildBuilder,
+    typography: &Typography,
+    name: &str,
+    components: (impl Component, impl Component, impl Component),
+) {
+    let (down, label, up) = components;
+    parent
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(50.0), Val::Px(40.0)),
+                flex_direction: FlexDirection::Row,
+                justify_content: JustifyContent::SpaceBetween,
+                align_items: AlignItems::Center,
+                ..Default::default()
+            },
+            color: Color::NONE.into(),
+            ..Default::default()
+        })
+        .with_children(|row| {
+            row.spawn_bundle(TextBundle {
+                text: Text::from_section(name, typography.body.clone()),
+                ..Default::default()
+            });
+
+            row.spawn_bundle(NodeBundle {
+                style: Style {
+                    size: Size::new(Val::Percent(50.0), Val::Px(34.0)),
+                    flex_direction: FlexDirection::Row,
+                    justify_content: JustifyContent::SpaceBetween,
+                    align_items: AlignItems::Center,
+                    ..Default::default()
+                },
+                color: Color::NONE.into(),
+                ..Default::default()
+            })
+            .with_children(|controls| {
+                spawn_adjust_button(controls, typography, "-", down);
+                controls
+                    .spawn_bundle(TextBundle {
+                        text: Text::from_section("", typography.body.clone()),
+                        ..Default::default()
+                    })
+                    .insert(label);
+                spawn_adjust_button(controls, typography, "+", up);
+            });
+        });
+}
+
+fn spawn_adjust_button(
+    parent: &mut ChildBuilder,
+    typography: &Typography,
+    label: &str,
+    button: impl Component,
+) {
+    parent
+        .spawn_bundle(ButtonBundle {
+            style: Style {
+                size: Size::new(Val::Px(34.0), Val::Px(34.0)),
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..Default::default()
+            },
+            color: Color::GRAY.into(),
+            ..Default::default()
+        })
+        .insert(button)
+        .with_children(|button| {
+            button.spawn_bundle(TextBundle {
+                text: Text::from_section(label, typography.body.clone()),
+                ..Default::default()
+            });
+        });
+}
+
+pub fn update_settings_menu(
+    mut settings: ResMut<Settings>,
+    query: Query<(&Interaction, &AdjustSetting), Changed<Interaction>>,
+) {
+    for (interaction, AdjustSetting(setting, up)) in query.iter() {
+        if *interaction == Interaction::Clicked {
+            setting.adjust(&mut settings, *up);
+        }
+    }
+}
+
+pub fn update_setting_labels(
+    settings: Res<Settings>,
+    mut labels: Query<(&mut Text, &SettingLabel, ChangeTrackers<SettingLabel>)>,
+) {
+    for (mut text, SettingLabel(setting), label_tracker) in labels.iter_mut() {
+        if settings.is_changed() || label_tracker.is_added() {
+            text.sections[0].value = setting.describe(&settings);
+        }
+    }
+}
+
+pub fn init_match_setup(
+    mut commands: Commands,
+    typography: Res<Typography>,
+    scenarios: Res<Scenarios>,
+    mut setup: ResMut<MatchSetup>,
+) {
+    // the scenarios might have changed since the last match
+    if setup.scenario >= scenarios.0.len() {
+        setup.scenario = 0;
+    }
+
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                flex_direction: FlexDirection::ColumnReverse,
+                justify_content: JustifyContent::SpaceEvenly,
+                align_items: AlignItems::Center,
+                ..Default::default()
+            },
+            color: Color::NONE.into(),
+            ..Default::default()
+        })
+        .with_children(|parent| {
+            parent.spawn_bundle(TextBundle {
+                text: Text::from_section("Match setup", typography.heading.clone()),
+                ..Default::default()
+            });
+
             parent
-                .spawn_bundle(ButtonBundle {
+                .spawn_bundle(NodeBundle {
                     style: Style {
-                        size: Size::new(Val::Percent(25.0), Val::Px(60.0)),
-                        justify_content: JustifyContent::Center,
-                        align_items: AlignItems::Center,
+                        size: Size::new(Val::Percent(50.0), Val::Px(PREVIEW_SIZE)),
+                        flex_direction: FlexDirection::Row,
+                        justify_content: JustifyContent::SpaceBetween,
                         ..Default::default()
                     },
-                    color: Color::GRAY.into(),
-                    // material: color_materials.add(Color::GRAY.into()),
+                    color: Color::NONE.into(),
                     ..Default::default()
                 })
-                .with_children(|parent| {
-                    parent.spawn_bundle(TextBundle {
-                        text: Text::from_section("Play", typography.body.clone()),
+                .with_children(|row| {
+                    row.spawn_bundle(NodeBundle {
+                        style: Style {
+                            size: Size::new(Val::Percent(50.0), Val::Percent(100.0)),
+                            flex_direction: FlexDirection::ColumnReverse,
+                            justify_content: JustifyContent::FlexStart,
+                            ..Default::default()
+                        },
+                        color: Color::NONE.into(),
                         ..Default::default()
+                    })
+                    .with_children(|list| {
+                        for (idx, scenario) in scenarios.0.iter().enumerate() {
+                            list.spawn_bundle(ButtonBundle {
+                                style: Style {
+                                    size: Size::new(Val::Percent(100.0), Val::Px(30.0)),
+                                    margin: UiRect {
+                                        bottom: Val::Px(4.0),
+                                        ..Default::default()
+                                    },
+                                    justify_content: JustifyContent::Center,
+                                    align_items: AlignItems::Center,
+                                    ..Default::default()
+                                },
+                                color: Color::GRAY.into(),
+                                ..Default::default()
+                            })
+                            .insert(ScenarioButton(idx))
+                            .with_children(|button| {
+                                button.spawn_bundle(TextBundle {
+                                    text: Text::from_section(
+                                        scenario.name.clone(),
+                                        typography.body.clone(),
+                                    ),
+                                    ..Default::default()
+                                });
+                            });
+                        }
+
+                        list.spawn_bundle(TextBundle {
+                            style: Style {
+                                max_size: Size::new(Val::Percent(100.0), Val::Undefined),
+                                ..Default::default()
+                            },
+                            text: Text::from_section("", typography.hud.clone()),
+                            ..Default::default()
+                        })
+                        .insert(ScenarioDescription);
                     });
+
+                    row.spawn_bundle(NodeBundle {
+                        style: Style {
+                            size: Size::new(Val::Px(PREVIEW_SIZE), Val::Px(PREVIEW_SIZE)),
+                            overflow: Overflow::Hidden,
+                            ..Default::default()
+                        },
+                        color: Color::rgba(0.0, 0.0, 0.0, 0.6).into(),
+                        ..Default::default()
+                    })
+                    .insert(ScenarioPreview);
                 });
-        });
 
-    // commands.insert_resource(MenuData {
-    //     button_entity: button_id,
-    // });
+            for option in MatchOption::ALL {
+                spawn_option_row(
+                    parent,
+                    &typography,
+                    option.name(),
+                    (
+                        AdjustMatchOption(option, false),
+                        MatchOptionLabel(option),
+                        AdjustMatchOption(option, true),
+                    ),
+                );
+            }
+
+            spawn_menu_button(parent, &typography, "Start", MenuButton::Start);
+            spawn_menu_button(parent, &typography, "Back", MenuButton::Back);
+        });
 }
 
-pub fn update_menu(
-    mut state: ResMut<State<AppState>>,
-    query: Query<&Interaction, (Changed<Interaction>, With<Button>)>,
+pub fn update_match_setup(
+    mut setup: ResMut<MatchSetup>,
+    options: Query<(&Interaction, &AdjustMatchOption), Changed<Interaction>>,
+    scenarios: Query<(&Interaction, &ScenarioButton), Changed<Interaction>>,
 ) {
-    for interaction in query.iter() {
+    for (interaction, AdjustMatchOption(option, up)) in options.iter() {
         if *interaction == Interaction::Clicked {
-            let result = state.set(AppState::Game);
-            if let Err(e) = result {
-                warn!("issue changing state into AppState::Game??\n{}", e);
+            option.adjust(&mut setup, *up);
+        }
+    }
+    for (interaction, ScenarioButton(idx)) in scenarios.iter() {
+        if *interaction == Interaction::Clicked {
+            setup.scenario = *idx;
+        }
+    }
+}
+
+/// Shows the value of every option, which scenario is picked and what it's like
+pub fn update_match_setup_labels(
+    setup: Res<MatchSetup>,
+    scenarios: Res<Scenarios>,
+    mut labels: Query<(
+        &mut Text,
+        &MatchOptionLabel,
+        ChangeTrackers<MatchOptionLabel>,
+    )>,
+    mut descriptions: Query<&mut Text, (With<ScenarioDescription>, Without<MatchOptionLabel>)>,
+    mut buttons: Query<(&mut UiColor, &ScenarioButton)>,
+) {
+    for (mut text, MatchOptionLabel(option), label_tracker) in labels.iter_mut() {
+        if setup.is_changed() || label_tracker.is_added() {
+            text.sections[0].value = option.describe(&setup);
+        }
+    }
+    if !setup.is_changed() {
+        return;
+    }
+
+    for mut text in descriptions.iter_mut() {
+        text.sections[0].value = scenarios
+            .0
+            .get(setup.scenario)
+            .map_or_else(String::new, |scenario| scenario.description.clone());
+    }
+    for (mut color, ScenarioButton(idx)) in buttons.iter_mut() {
+        *color = if *idx == setup.scenario {
+            SELECTED_COLOR
+        } else {
+            Color::GRAY
+        }
+        .into();
+    }
+}
+
+/// Redraws the map of the picked scenario whenever the setup changes, with the stars and planets
+/// where they start out and a blip in each team's color where its ship will spawn
+pub fn update_scenario_preview(
+    mut commands: Commands,
+    setup: Res<MatchSetup>,
+    scenarios: Res<Scenarios>,
+    registry: Res<TeamRegistry>,
+    previews: Query<(Entity, ChangeTrackers<ScenarioPreview>), With<ScenarioPreview>>,
+    blips: Query<Entity, With<PreviewBlip>>,
+) {
+    let (preview, preview_tracker) = match previews.get_single() {
+        Ok(preview) => preview,
+        Err(_) => return,
+    };
+    if !setup.is_changed() && !preview_tracker.is_added() {
+        return;
+    }
+    for blip in blips.iter() {
+        commands.entity(blip).despawn_recursive();
+    }
+    let scenario = match scenarios.0.get(setup.scenario) {
+        Some(scenario) => scenario,
+        None => return,
+    };
+
+    let system = scenario.system.build(&setup.teams());
+    // asteroids are left out of the framing, since there are a lot of them and belts are never
+    // the outermost thing anyway
+    let extent = system
+        .bodies
+        .iter()
+        .filter(|body| body.kind != GeneratedKind::Asteroid)
+        .map(|body| body.position)
+        .chain(system.spawn_points.iter().map(|spawn| spawn.position))
+        .map(|position| position.abs().max_element())
+        .fold(1., f32::max);
+    let scale = (PREVIEW_SIZE / 2. - 8.) / extent;
+
+    let mut blips: Vec<(Vec2, f32, Color)> = system
+        .bodies
+        .iter()
+        .enumerate()
+        .filter_map(|(idx, body)| match body.kind {
+            GeneratedKind::Star => Some((body.position, 10., STAR_COLOR)),
+            GeneratedKind::Planet => Some((body.position, 6., PLANET_COLOR)),
+            GeneratedKind::Moon => Some((body.position, 3., PLANET_COLOR)),
+            // enough of them to show where the belts are
+            GeneratedKind::Asteroid => {
+                (idx % 8 == 0).then(|| (body.position, 2., PLANET_COLOR * 0.6))
             }
+        })
+        .collect();
+    blips.extend(
+        system
+            .spawn_points
+            .iter()
+            .map(|spawn| (spawn.position, 6., registry.color(&spawn.team))),
+    );
+
+    commands.entity(preview).with_children(|parent| {
+        for (position, size, color) in blips {
+            let center = Vec2::splat(PREVIEW_SIZE / 2.) + position * scale;
+            parent
+                .spawn_bundle(NodeBundle {
+                    style: Style {
+                        position_type: PositionType::Absolute,
+                        position: UiRect {
+                            left: Val::Px(center.x - size / 2.),
+                            bottom: Val::Px(center.y - size / 2.),
+                            ..Default::default()
+                        },
+                        size: Size::new(Val::Px(size), Val::Px(size)),
+                        ..Default::default()
+                    },
+                    color: color.into(),
+                    ..Default::default()
+                })
+                .insert(PreviewBlip);
         }
+    });
+}
+
+pub fn init_game_over(
+    mut commands: Commands,
+    typography: Res<Typography>,
+    match_state: Res<MatchState>,
+    registry: Res<TeamRegistry>,
+) {
+    let (headline, color, reason) = match &match_state.outcome {
+        Some(outcome) => match &outcome.winner {
+            Some(winner) => (
+                format!("{} wins", registry.name(winner)),
+                registry.color(winner),
+                outcome.reason.clone(),
+            ),
+            None => ("Draw".to_string(), Color::WHITE, outcome.reason.clone()),
+        },
+        None => ("Game over".to_string(), Color::WHITE, String::new()),
+    };
+
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                flex_direction: FlexDirection::ColumnReverse,
+                justify_content: JustifyContent::SpaceEvenly,
+                align_items: AlignItems::Center,
+                ..Default::default()
+            },
+            color: Color::NONE.into(),
+            ..Default::default()
+        })
+        .with_children(|parent| {
+            parent.spawn_bundle(TextBundle {
+                text: Text::from_section(
+                    headline,
+                    TextStyle {
+                        color,
+                        ..typography.heading.clone()
+                    },
+                ),
+                ..Default::default()
+            });
+            parent.spawn_bundle(TextBundle {
+                text: Text::from_section(reason, typography.body.clone()),
+                ..Default::default()
+            });
+
+            spawn_menu_button(parent, &typography, "Results", MenuButton::Results);
+            spawn_menu_button(parent, &typography, "Main menu", MenuButton::Back);
+        });
+}
+
+pub fn init_results(
+    mut commands: Commands,
+    typography: Res<Typography>,
+    match_state: Res<MatchState>,
+    stats: Res<PlayerStats>,
+) {
+    let accuracy = match stats.accuracy() {
+        Some(accuracy) => format!("{:.1}%", accuracy * 100.0),
+        None => "-".to_string(),
+    };
+    let rows = [
+        ("Kills", stats.kills.to_string()),
+        ("Deaths", stats.deaths.to_string()),
+        ("Shots fired", stats.shots_fired.to_string()),
+        ("Accuracy", accuracy),
+        ("Survival time", format!("{:.0}s", stats.survival_time)),
+        ("Match length", format!("{:.0}s", match_state.elapsed)),
+    ];
+
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                flex_direction: FlexDirection::ColumnReverse,
+                justify_content: JustifyContent::SpaceEvenly,
+                align_items: AlignItems::Center,
+                ..Default::default()
+            },
+            color: Color::NONE.into(),
+            ..Default::default()
+        })
+        .with_children(|parent| {
+            parent.spawn_bundle(TextBundle {
+                text: Text::from_section("Results", typography.heading.clone()),
+                ..Default::default()
+            });
+
+            for (label, value) in rows {
+                parent
+                    .spawn_bundle(NodeBundle {
+                        style: Style {
+                            size: Size::new(Val::Percent(40.0), Val::Px(30.0)),
+                            flex_direction: FlexDirection::Row,
+                            justify_content: JustifyContent::SpaceBetween,
+                            ..Default::default()
+                        },
+                        color: Color::NONE.into(),
+                        ..Default::default()
+                    })
+                    .with_children(|row| {
+                        row.spawn_bundle(TextBundle {
+                            text: Text::from_section(label, typography.body.clone()),
+                            ..Default::default()
+                        });
+                        row.spawn_bundle(TextBundle {
+                            text: Text::from_section(value, typography.body.clone()),
+                            ..Default::default()
+                        });
+                    });
+            }
+
+            spawn_menu_button(parent, &typography, "Main menu", MenuButton::Back);
+        });
+}
+
+pub fn init_pause_menu(mut commands: Commands, typography: Res<Typography>) {
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                position_type: PositionType::Absolute,
+                flex_direction: FlexDirection::ColumnReverse,
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..Default::default()
+            },
+            color: Color::rgba(0.0, 0.0, 0.0, 0.6).into(),
+            ..Default::default()
+        })
+        .insert(PauseMenu)
+        .with_children(|parent| {
+            parent.spawn_bundle(TextBundle {
+                text: Text::from_section("Paused", typography.heading.clone()),
+                ..Default::default()
+            });
+            spawn_menu_button(parent, &typography, "Resume", MenuButton::Resume);
+            spawn_menu_button(parent, &typography, "Main menu", MenuButton::Back);
+        });
+}
+
+pub fn close_pause_menu(mut commands: Commands, menus: Query<Entity, With<PauseMenu>>) {
+    for menu in menus.iter() {
+        commands.entity(menu).despawn_recursive();
     }
 }
+
+pub fn save_bindings(bindings: Res<InputBindings>) {
+    bindings.save();
+}
+
+pub fn save_settings(settings: Res<Settings>) {
+    settings.save();
+}
@@ -0,0 +1,339 @@
+use std::collections::HashSet;
+
+use bevy::prelude::*;
+use bevy::render::render_resource::{Extent3d, TextureDimension, TextureFormat};
+use bevy::ui::{FocusPolicy, Size};
+
+use crate::camera::CameraController;
+use crate::components::*;
+use crate::physics::{dominant_source, hill_radius};
+use crate::teams::TeamRegistry;
+
+/// Width (and height) of the minimap, in pixels
+const MINIMAP_SIZE: f32 = 200.;
+
+/// Clicking within this many pixels of a blip follows it, rather than just looking at that spot
+const CLICK_RADIUS: f32 = 6.;
+
+pub const STAR_COLOR: Color = Color::rgb(1.0, 0.9, 0.5);
+pub const PLANET_COLOR: Color = Color::rgb(0.6, 0.6, 0.65);
+
+/// Images used to draw the minimap. These are generated rather than loaded, since they're
+/// just circles.
+pub struct MinimapImages {
+    dot: Handle<Image>,
+    ring: Handle<Image>,
+}
+
+impl FromWorld for MinimapImages {
+    fn from_world(world: &mut World) -> Self {
+        let mut images = world.get_resource_mut::<Assets<Image>>().expect(
+            "Unable to (mutably) get Assets<Image> when initializing MinimapImages resource",
+        );
+
+        MinimapImages {
+            dot: images.add(circle_image(32, 0.)),
+            ring: images.add(circle_image(256, 0.97)),
+        }
+    }
+}
+
+/// A white circle filling a square image, covering everything between `inner_radius` and the
+/// edge (as fractions of the image's half-width)
+fn circle_image(resolution: u32, inner_radius: f32) -> Image {
+    let half = resolution as f32 / 2.;
+    // soften the edges over about a pixel
+    let pixel = 1. / half;
+
+    let data = (0..resolution * resolution)
+        .flat_map(|idx| {
+            let (x, y) = (idx % resolution, idx / resolution);
+            let dist = (Vec2::new(x as f32, y as f32) + 0.5 - half).length() / half;
+            let coverage = ((1. - dist) / pixel).clamp(0., 1.)
+                * ((dist - inner_radius) / pixel + 1.).clamp(0., 1.);
+            [255, 255, 255, (coverage * 255.) as u8]
+        })
+        .collect();
+
+    Image::new(
+        Extent3d {
+            width: resolution,
+            height: resolution,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        data,
+        TextureFormat::Rgba8UnormSrgb,
+    )
+}
+
+/// The minimap itself. Clicking on it points the camera at wherever was clicked.
+#[derive(Debug, Default, Clone, Copy, Component)]
+pub struct Minimap;
+
+/// A dot on the minimap standing in for a body
+#[derive(Debug, Clone, Copy, Component)]
+pub struct MinimapBlip(pub Entity);
+
+/// The Hill sphere of a planet, drawn on the minimap
+#[derive(Debug, Clone, Copy, Component)]
+pub struct MinimapRing(pub Entity);
+
+/// Which part of the world the minimap is showing
+#[derive(Debug, Default)]
+pub struct MinimapView {
+    center: Vec2,
+    half_extent: f32,
+}
+
+impl MinimapView {
+    /// Converts a point in the world into pixels from the minimap's bottom left corner
+    fn to_minimap(&self, point: Vec2) -> Vec2 {
+        ((point - self.center) / self.half_extent + 1.) * MINIMAP_SIZE / 2.
+    }
+
+    fn to_world(&self, point: Vec2) -> Vec2 {
+        self.center + (point * 2. / MINIMAP_SIZE - 1.) * self.half_extent
+    }
+
+    /// Pixels per world unit
+    fn scale(&self) -> f32 {
+        MINIMAP_SIZE / (2. * self.half_extent)
+    }
+}
+
+pub fn spawn_minimap(mut commands: Commands) {
+    commands
+        .spawn_bundle(ButtonBundle {
+            style: Style {
+                size: Size::new(Val::Px(MINIMAP_SIZE), Val::Px(MINIMAP_SIZE)),
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    right: Val::Px(10.),
+                    bottom: Val::Px(10.),
+                    ..Default::default()
+                },
+                overflow: Overflow::Hidden,
+                ..Default::default()
+            },
+            color: Color::rgba(0.0, 0.0, 0.0, 0.6).into(),
+            ..Default::default()
+        })
+        .insert(Minimap);
+}
+
+/// Zooms the minimap so that the whole system (and every ship in it) fits
+pub fn update_minimap_view(
+    mut view: ResMut<MinimapView>,
+    bodies: Query<&Position, Or<(With<Star>, With<Planet>, With<Ship>)>>,
+) {
+    let (min, max) = bodies.iter().fold(
+        (Vec2::splat(f32::INFINITY), Vec2::splat(f32::NEG_INFINITY)),
+        |(min, max), Position(pos)| (min.min(*pos), max.max(*pos)),
+    );
+    if !min.is_finite() {
+        return;
+    }
+
+    view.center = (min + max) / 2.;
+    // leave a bit of room around the edges, and don't zoom in on a lone body forever
+    view.half_extent = ((max - min).max_element() / 2. * 1.1).max(100.);
+}
+
+/// Adds blips (and rings) for bodies that don't have one yet, and removes the ones whose body is
+/// gone
+pub fn sync_minimap_blips(
+    mut commands: Commands,
+    images: Res<MinimapImages>,
+    minimap: Query<Entity, With<Minimap>>,
+    bodies: Query<
+        (
+            Entity,
+            Option<&Star>,
+            Option<&Planet>,
+            Option<&Ship>,
+            Option<&GravitySource>,
+        ),
+        Or<(With<Star>, With<Planet>, With<Ship>, With<Missile>)>,
+    >,
+    blips: Query<(Entity, &MinimapBlip)>,
+    rings: Query<(Entity, &MinimapRing)>,
+) {
+    let minimap = match minimap.iter().next() {
+        Some(minimap) => minimap,
+        None => return,
+    };
+
+    for (blip, MinimapBlip(body)) in blips.iter() {
+        if !bodies.contains(*body) {
+            commands.entity(blip).despawn_recursive();
+        }
+    }
+    for (ring, MinimapRing(body)) in rings.iter() {
+        if !bodies.contains(*body) {
+            commands.entity(ring).despawn_recursive();
+        }
+    }
+
+    let shown: HashSet<Entity> = blips.iter().map(|(_, MinimapBlip(body))| *body).collect();
+    for (body, star, planet, ship, gravity_source) in bodies.iter() {
+        if shown.contains(&body) {
+            continue;
+        }
+
+        let size = match (star, planet, ship) {
+            (Some(_), _, _) => 10.,
+            (_, Some(_), _) => 6.,
+            (_, _, Some(_)) => 5.,
+            _ => 3.,
+        };
+        commands.entity(minimap).with_children(|parent| {
+            // rings go underneath everything else
+            if gravity_source.is_some() {
+                parent
+                    .spawn_bundle(ImageBundle {
+                        style: Style {
+                            position_type: PositionType::Absolute,
+                            ..Default::default()
+                        },
+                        image: UiImage(images.ring.clone()),
+                        focus_policy: FocusPolicy::Pass,
+                        ..Default::default()
+                    })
+                    .insert(MinimapRing(body));
+            }
+            parent
+                .spawn_bundle(ImageBundle {
+                    style: Style {
+                        size: Size::new(Val::Px(size), Val::Px(size)),
+                        position_type: PositionType::Absolute,
+                        ..Default::default()
+                    },
+                    image: UiImage(images.dot.clone()),
+                    focus_policy: FocusPolicy::Pass,
+                    ..Default::default()
+                })
+                .insert(MinimapBlip(body));
+        });
+    }
+}
+
+fn body_color(registry: &TeamRegistry, team: Option<&Team>, star: bool) -> Color {
+    match team {
+        Some(team) => registry.color(team),
+        None if star => STAR_COLOR,
+        None => PLANET_COLOR,
+    }
+}
+
+pub fn update_minimap_blips(
+    view: Res<MinimapView>,
+    registry: Res<TeamRegistry>,
+    bodies: Query<(&Position, Option<&Team>, Option<&Star>)>,
+    gravity_sources: Query<(Entity, &Position, &Mass, Option<&Star>), With<GravitySource>>,
+    mut blips: Query<
+        (&MinimapBlip, &mut Style, &mut UiColor, &mut Visibility),
+        Without<MinimapRing>,
+    >,
+    mut rings: Query<
+        (&MinimapRing, &mut Style, &mut UiColor, &mut Visibility),
+        Without<MinimapBlip>,
+    >,
+) {
+    let on_minimap = |center: Vec2, size: f32| {
+        center.cmpge(Vec2::splat(-size / 2.)).all()
+            && center.cmple(Vec2::splat(MINIMAP_SIZE + size / 2.)).all()
+    };
+
+    for (MinimapBlip(body), mut style, mut color, mut visibility) in blips.iter_mut() {
+        let (Position(pos), team, star) = match bodies.get(*body) {
+            Ok(body) => body,
+            Err(_) => continue,
+        };
+
+        let center = view.to_minimap(*pos);
+        let size = match style.size.width {
+            Val::Px(size) => size,
+            _ => 0.,
+        };
+        style.position.left = Val::Px(center.x - size / 2.);
+        style.position.bottom = Val::Px(center.y - size / 2.);
+        visibility.is_visible = on_minimap(center, size);
+        color.0 = body_color(&registry, team, star.is_some());
+    }
+
+    for (MinimapRing(body), mut style, mut color, mut visibility) in rings.iter_mut() {
+        let (Position(pos), team, star) = match bodies.get(*body) {
+            Ok(body) => body,
+            Err(_) => continue,
+        };
+
+        // a star's planets don't get to be its primary, only another star can. A star on its own
+        // has nothing to be pulled away towards, so it doesn't get a ring at all.
+        let radius = gravity_sources
+            .get(*body)
+            .ok()
+            .and_then(|(_, _, Mass(mass), _)| {
+                let (primary_pos, primary_mass) = dominant_source(
+                    *pos,
+                    gravity_sources
+                        .iter()
+                        .filter(|(source, _, _, other_star)| {
+                            source != body && (star.is_none() || other_star.is_some())
+                        })
+                        .map(|(_, Position(pos), Mass(mass), _)| (*pos, *mass)),
+                )?;
+                Some(hill_radius(*mass, primary_mass, primary_pos.distance(*pos)))
+            })
+            .unwrap_or(0.);
+
+        let center = view.to_minimap(*pos);
+        let size = 2. * radius * view.scale();
+        style.size = Size::new(Val::Px(size), Val::Px(size));
+        style.position.left = Val::Px(center.x - size / 2.);
+        style.position.bottom = Val::Px(center.y - size / 2.);
+        // too small to see past the body's own blip
+        visibility.is_visible = size > 8. && on_minimap(center, size);
+        color.0 = *body_color(&registry, team, star.is_some()).set_a(0.4);
+    }
+}
+
+/// Clicking on the minimap follows whatever was clicked on, or otherwise looks at that spot
+pub fn handle_minimap_click(
+    windows: Res<Windows>,
+    view: Res<MinimapView>,
+    mut controller: ResMut<CameraController>,
+    minimap: Query<(&Interaction, &Node, &GlobalTransform), (With<Minimap>, Changed<Interaction>)>,
+    blips: Query<&MinimapBlip>,
+    bodies: Query<&Position>,
+) {
+    let cursor = match windows
+        .get_primary()
+        .and_then(|window| window.cursor_position())
+    {
+        Some(cursor) => cursor,
+        None => return,
+    };
+
+    for (interaction, node, transform) in minimap.iter() {
+        if *interaction != Interaction::Clicked {
+            continue;
+        }
+
+        let bottom_left = transform.translation().truncate() - node.size / 2.;
+        let clicked = cursor - bottom_left;
+
+        let nearest = blips
+            .iter()
+            .filter_map(|MinimapBlip(body)| {
+                let Position(pos) = bodies.get(*body).ok()?;
+                Some((*body, view.to_minimap(*pos).distance(clicked)))
+            })
+            .filter(|(_, dist)| *dist < CLICK_RADIUS)
+            .min_by(|(_, a), (_, b)| a.total_cmp(b));
+        match nearest {
+            Some((body, _)) => controller.follow(body),
+            None => controller.look_at(view.to_world(clicked)),
+        }
+    }
+}
@@ -1,5 +1,12 @@
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum AppState {
     Menu,
+    Controls,
+    Settings,
+    MatchSetup,
     Game,
+    /// Pushed on top of `Game`, so the match is still there underneath
+    Paused,
+    GameOver,
+    Results,
 }
\ No newline at end of file
@@ -0,0 +1,1398 @@
+use std::collections::{HashMap, VecDeque};
+use std::io::ErrorKind;
+use std::net::{SocketAddr, UdpSocket};
+use std::time::Duration;
+
+use bevy::prelude::*;
+
+use crate::bundles::*;
+use crate::components::Size;
+use crate::components::*;
+use crate::controls::{Action, ActionState};
+use crate::events::*;
+use crate::generator::SeededRng;
+use crate::physics::{gravitational_acceleration, PhysicsSystem};
+
+/// Biggest payload that fits in a single UDP datagram
+const MAX_PACKET_SIZE: usize = 65_507;
+
+/// Number of snapshots both ends remember so that they can be used as delta baselines
+const SNAPSHOT_HISTORY: usize = 64;
+
+/// Clients that haven't sent us anything for this long are dropped
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How often a client that hasn't been welcomed yet says hello again
+const HELLO_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Snapshots are never extrapolated further than this into the future
+const MAX_EXTRAPOLATION: f64 = 0.5;
+
+/// Written in place of a baseline tick when a snapshot is not delta compressed
+const NO_BASELINE: u32 = u32::MAX;
+
+/// Artificial network conditions applied to every packet we send.
+/// Useful for seeing how the interpolation copes with a bad connection without needing one.
+#[derive(Debug, Clone, Default)]
+pub struct LinkConditions {
+    /// One way delay added to every packet
+    pub latency: Duration,
+    /// Extra random delay (somewhere between zero and this) added to every packet
+    pub jitter: Duration,
+    /// Chance (between 0 and 1) that a packet is dropped instead of sent
+    pub packet_loss: f32,
+    /// Decides which packets get dropped and how much jitter each one gets, so that a bad
+    /// connection can be played back exactly the same way again
+    pub seed: u64,
+}
+
+impl LinkConditions {
+    /// Picks `--latency <ms>`, `--jitter <ms>` and `--loss <fraction>` out of the command line arguments
+    fn from_args(args: &[String]) -> Result<Self, String> {
+        let mut conditions = LinkConditions::default();
+        if let Some(ms) = arg_value(args, "--latency")? {
+            conditions.latency = Duration::from_millis(ms);
+        }
+        if let Some(ms) = arg_value(args, "--jitter")? {
+            conditions.jitter = Duration::from_millis(ms);
+        }
+        if let Some(loss) = arg_value::<f32>(args, "--loss")? {
+            conditions.packet_loss = loss.clamp(0.0, 1.0);
+        }
+        Ok(conditions)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    pub bind_address: SocketAddr,
+    /// Snapshots broadcast per second
+    pub snapshot_rate: f32,
+    pub link: LinkConditions,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            bind_address: ([0, 0, 0, 0], 7777).into(),
+            snapshot_rate: 20.0,
+            link: LinkConditions::default(),
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Understands `--bind <addr>` and `--snapshot-rate <hz>`, plus the [`LinkConditions`] arguments
+    pub fn from_args(args: &[String]) -> Result<Self, String> {
+        let mut config = ServerConfig::default();
+        if let Some(bind_address) = arg_value(args, "--bind")? {
+            config.bind_address = bind_address;
+        }
+        if let Some(snapshot_rate) = arg_value(args, "--snapshot-rate")? {
+            config.snapshot_rate = snapshot_rate;
+        }
+        config.link = LinkConditions::from_args(args)?;
+        Ok(config)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ClientConfig {
+    pub server_address: SocketAddr,
+    /// How far in the past we render the world. Should be a few snapshot intervals so that there
+    /// is (usually) a snapshot on either side of the time we are drawing.
+    pub interpolation_delay: Duration,
+    pub link: LinkConditions,
+}
+
+impl ClientConfig {
+    /// Understands `--connect <addr>` and `--interp-delay <ms>`, plus the [`LinkConditions`] arguments
+    pub fn from_args(args: &[String]) -> Result<Self, String> {
+        let server_address =
+            arg_value(args, "--connect")?.ok_or("--connect <address> is required")?;
+        let interpolation_delay =
+            Duration::from_millis(arg_value(args, "--interp-delay")?.unwrap_or(100));
+        Ok(ClientConfig {
+            server_address,
+            interpolation_delay,
+            link: LinkConditions::from_args(args)?,
+        })
+    }
+}
+
+fn arg_value<T: std::str::FromStr>(args: &[String], flag: &str) -> Result<Option<T>, String> {
+    match args.iter().position(|arg| arg == flag) {
+        None => Ok(None),
+        Some(idx) => {
+            let value = args
+                .get(idx + 1)
+                .ok_or_else(|| format!("{} expects a value", flag))?;
+            value
+                .parse()
+                .map(Some)
+                .map_err(|_| format!("invalid value for {}: {}", flag, value))
+        }
+    }
+}
+
+/// Non-blocking UDP socket that applies [`LinkConditions`] to outgoing packets. Delays are
+/// measured against [`Time`], so a test that steps time by hand gets the same delays every run.
+struct Link {
+    socket: UdpSocket,
+    conditions: LinkConditions,
+    rng: SeededRng,
+    /// Packets held back until the time since startup reaches the first field
+    delayed: Vec<(Duration, SocketAddr, Vec<u8>)>,
+}
+
+impl Link {
+    fn bind(address: SocketAddr, conditions: LinkConditions) -> std::io::Result<Self> {
+        let socket = UdpSocket::bind(address)?;
+        socket.set_nonblocking(true)?;
+        Ok(Link {
+            socket,
+            rng: SeededRng::new(conditions.seed),
+            conditions,
+            delayed: Vec::new(),
+        })
+    }
+
+    fn send(&mut self, to: SocketAddr, packet: Vec<u8>, now: Duration) {
+        if self.conditions.packet_loss > 0.0 && self.rng.chance(self.conditions.packet_loss) {
+            return;
+        }
+
+        let delay = self.conditions.latency + self.conditions.jitter.mul_f32(self.rng.next_f32());
+        if delay.is_zero() {
+            self.send_now(to, &packet);
+        } else {
+            self.delayed.push((now + delay, to, packet));
+        }
+    }
+
+    fn send_now(&self, to: SocketAddr, packet: &[u8]) {
+        if let Err(e) = self.socket.send_to(packet, to) {
+            warn!("unable to send packet to {}: {}", to, e);
+        }
+    }
+
+    /// Sends the packets whose artificial delay has run out
+    fn flush(&mut self, now: Duration) {
+        let (due, pending): (Vec<_>, Vec<_>) = std::mem::take(&mut self.delayed)
+            .into_iter()
+            .partition(|(send_at, _, _)| *send_at <= now);
+        self.delayed = pending;
+        for (_, to, packet) in due {
+            self.send_now(to, &packet);
+        }
+    }
+
+    fn recv(&self, buf: &mut [u8]) -> Option<(usize, SocketAddr)> {
+        loop {
+            match self.socket.recv_from(buf) {
+                Ok(received) => return Some(received),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return None,
+                // some platforms report an earlier send to a closed port as an error on the next recv
+                Err(e) if e.kind() == ErrorKind::ConnectionReset => continue,
+                Err(e) => {
+                    warn!("unable to receive packet: {}", e);
+                    return None;
+                }
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct PacketWriter(Vec<u8>);
+
+impl PacketWriter {
+    fn u8(&mut self, v: u8) {
+        self.0.push(v);
+    }
+
+    fn u16(&mut self, v: u16) {
+        self.0.extend_from_slice(&v.to_le_bytes());
+    }
+
+    fn u32(&mut self, v: u32) {
+        self.0.extend_from_slice(&v.to_le_bytes());
+    }
+
+    fn f32(&mut self, v: f32) {
+        self.0.extend_from_slice(&v.to_le_bytes());
+    }
+
+    fn f64(&mut self, v: f64) {
+        self.0.extend_from_slice(&v.to_le_bytes());
+    }
+
+    fn vec2(&mut self, v: Vec2) {
+        self.f32(v.x);
+        self.f32(v.y);
+    }
+}
+
+struct PacketReader<'a>(&'a [u8]);
+
+impl<'a> PacketReader<'a> {
+    fn take<const N: usize>(&mut self) -> Option<[u8; N]> {
+        if self.0.len() < N {
+            return None;
+        }
+        let (head, rest) = self.0.split_at(N);
+        self.0 = rest;
+        head.try_into().ok()
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.take::<1>().map(|[b]| b)
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        self.take().map(u16::from_le_bytes)
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        self.take().map(u32::from_le_bytes)
+    }
+
+    fn f32(&mut self) -> Option<f32> {
+        self.take().map(f32::from_le_bytes)
+    }
+
+    fn f64(&mut self) -> Option<f64> {
+        self.take().map(f64::from_le_bytes)
+    }
+
+    fn vec2(&mut self) -> Option<Vec2> {
+        Some(Vec2::new(self.f32()?, self.f32()?))
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct PlayerInput {
+    /// Fraction of the engine's max acceleration, between -1 and 1
+    thrust: f32,
+    /// Fraction of the engine's max turn rate, between -1 and 1
+    turn: f32,
+    fire: bool,
+}
+
+enum ClientMessage {
+    Hello,
+    Input {
+        seq: u32,
+        /// Newest snapshot the client has, which the server can delta compress against
+        acked_tick: Option<u32>,
+        input: PlayerInput,
+    },
+    Goodbye,
+}
+
+impl ClientMessage {
+    fn encode(&self) -> Vec<u8> {
+        let mut w = PacketWriter::default();
+        match self {
+            ClientMessage::Hello => w.u8(0),
+            ClientMessage::Input {
+                seq,
+                acked_tick,
+                input,
+            } => {
+                w.u8(1);
+                w.u32(*seq);
+                w.u32(acked_tick.unwrap_or(NO_BASELINE));
+                w.f32(input.thrust);
+                w.f32(input.turn);
+                w.u8(input.fire as u8);
+            }
+            ClientMessage::Goodbye => w.u8(2),
+        }
+        w.0
+    }
+
+    fn decode(packet: &[u8]) -> Option<Self> {
+        let mut r = PacketReader(packet);
+        match r.u8()? {
+            0 => Some(ClientMessage::Hello),
+            1 => Some(ClientMessage::Input {
+                seq: r.u32()?,
+                acked_tick: Some(r.u32()?).filter(|tick| *tick != NO_BASELINE),
+                input: PlayerInput {
+                    thrust: r.f32()?.clamp(-1.0, 1.0),
+                    turn: r.f32()?.clamp(-1.0, 1.0),
+                    fire: r.u8()? != 0,
+                },
+            }),
+            2 => Some(ClientMessage::Goodbye),
+            _ => None,
+        }
+    }
+}
+
+const WELCOME: u8 = 0;
+const SNAPSHOT: u8 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BodyKind {
+    Star,
+    Planet,
+    Ship,
+    Missile,
+}
+
+impl BodyKind {
+    fn from_u8(v: u8) -> Option<Self> {
+        match v {
+            0 => Some(BodyKind::Star),
+            1 => Some(BodyKind::Planet),
+            2 => Some(BodyKind::Ship),
+            3 => Some(BodyKind::Missile),
+            _ => None,
+        }
+    }
+}
+
+/// Everything the client needs to know about one replicated body
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct BodyState {
+    kind: BodyKind,
+    position: Vec2,
+    velocity: Vec2,
+    team: u32,
+    size: f32,
+    mass: f32,
+}
+
+// Bits of the per-body field mask. A field is only written when it differs from the baseline.
+const FIELD_KIND: u8 = 1 << 0;
+const FIELD_POSITION: u8 = 1 << 1;
+const FIELD_VELOCITY: u8 = 1 << 2;
+const FIELD_TEAM: u8 = 1 << 3;
+const FIELD_SIZE: u8 = 1 << 4;
+const FIELD_MASS: u8 = 1 << 5;
+const ALL_FIELDS: u8 = (1 << 6) - 1;
+
+impl BodyState {
+    fn changed_fields(&self, baseline: &BodyState) -> u8 {
+        let mut mask = 0;
+        if self.kind != baseline.kind {
+            mask |= FIELD_KIND;
+        }
+        if self.position != baseline.position {
+            mask |= FIELD_POSITION;
+        }
+        if self.velocity != baseline.velocity {
+            mask |= FIELD_VELOCITY;
+        }
+        if self.team != baseline.team {
+            mask |= FIELD_TEAM;
+        }
+        if self.size != baseline.size {
+            mask |= FIELD_SIZE;
+        }
+        if self.mass != baseline.mass {
+            mask |= FIELD_MASS;
+        }
+        mask
+    }
+
+    fn write_fields(&self, mask: u8, w: &mut PacketWriter) {
+        if mask & FIELD_KIND != 0 {
+            w.u8(self.kind as u8);
+        }
+        if mask & FIELD_POSITION != 0 {
+            w.vec2(self.position);
+        }
+        if mask & FIELD_VELOCITY != 0 {
+            w.vec2(self.velocity);
+        }
+        if mask & FIELD_TEAM != 0 {
+            w.u32(self.team);
+        }
+        if mask & FIELD_SIZE != 0 {
+            w.f32(self.size);
+        }
+        if mask & FIELD_MASS != 0 {
+            w.f32(self.mass);
+        }
+    }
+
+    /// Reads the fields in `mask`, taking the rest from `baseline`.
+    /// Without a baseline every field has to be present.
+    fn read_fields(mask: u8, baseline: Option<&BodyState>, r: &mut PacketReader) -> Option<Self> {
+        let field = |bit: u8| mask & bit != 0;
+        if baseline.is_none() && mask != ALL_FIELDS {
+            return None;
+        }
+
+        let kind = if field(FIELD_KIND) {
+            BodyKind::from_u8(r.u8()?)?
+        } else {
+            baseline?.kind
+        };
+        let position = if field(FIELD_POSITION) {
+            r.vec2()?
+        } else {
+            baseline?.position
+        };
+        let velocity = if field(FIELD_VELOCITY) {
+            r.vec2()?
+        } else {
+            baseline?.velocity
+        };
+        let team = if field(FIELD_TEAM) {
+            r.u32()?
+        } else {
+            baseline?.team
+        };
+        let size = if field(FIELD_SIZE) {
+            r.f32()?
+        } else {
+            baseline?.size
+        };
+        let mass = if field(FIELD_MASS) {
+            r.f32()?
+        } else {
+            baseline?.mass
+        };
+
+        Some(BodyState {
+            kind,
+            position,
+            velocity,
+            team,
+            size,
+            mass,
+        })
+    }
+}
+
+/// State of every replicated body at one server tick
+#[derive(Debug, Clone, Default)]
+struct Snapshot {
+    tick: u32,
+    /// Seconds since the server started
+    server_time: f64,
+    bodies: HashMap<u32, BodyState>,
+}
+
+/// Cosmetic explosion that happened since the last snapshot (position, velocity)
+type ExplosionNotice = (Vec2, Vec2);
+
+/// Writes `snapshot`, only including the bodies and fields that changed since `baseline`
+fn encode_snapshot(
+    snapshot: &Snapshot,
+    baseline: Option<&Snapshot>,
+    explosions: &[ExplosionNotice],
+) -> Vec<u8> {
+    let mut w = PacketWriter::default();
+    w.u8(SNAPSHOT);
+    w.u32(snapshot.tick);
+    w.u32(baseline.map_or(NO_BASELINE, |b| b.tick));
+    w.f64(snapshot.server_time);
+
+    let changed: Vec<(u32, &BodyState, u8)> = snapshot
+        .bodies
+        .iter()
+        .filter_map(|(id, body)| {
+            let mask = match baseline.and_then(|b| b.bodies.get(id)) {
+                Some(old) => body.changed_fields(old),
+                None => ALL_FIELDS,
+            };
+            (mask != 0).then(|| (*id, body, mask))
+        })
+        .collect();
+    w.u16(changed.len() as u16);
+    for (id, body, mask) in changed {
+        w.u32(id);
+        w.u8(mask);
+        body.write_fields(mask, &mut w);
+    }
+
+    let removed: Vec<u32> = baseline
+        .map(|b| {
+            b.bodies
+                .keys()
+                .filter(|id| !snapshot.bodies.contains_key(id))
+                .copied()
+                .collect()
+        })
+        .unwrap_or_default();
+    w.u16(removed.len() as u16);
+    for id in removed {
+        w.u32(id);
+    }
+
+    w.u16(explosions.len() as u16);
+    for (position, velocity) in explosions {
+        w.vec2(*position);
+        w.vec2(*velocity);
+    }
+
+    w.0
+}
+
+/// Rebuilds a full snapshot from a (possibly delta compressed) packet.
+/// Returns `None` for malformed packets and for deltas against a baseline we no longer have.
+fn decode_snapshot(
+    r: &mut PacketReader,
+    history: &VecDeque<Snapshot>,
+) -> Option<(Snapshot, Vec<ExplosionNotice>)> {
+    let tick = r.u32()?;
+    let baseline_tick = r.u32()?;
+    let server_time = r.f64()?;
+
+    let baseline = if baseline_tick == NO_BASELINE {
+        None
+    } else {
+        Some(history.iter().find(|s| s.tick == baseline_tick)?)
+    };
+    let mut bodies = baseline.map(|b| b.bodies.clone()).unwrap_or_default();
+
+    for _ in 0..r.u16()? {
+        let id = r.u32()?;
+        let mask = r.u8()?;
+        let body = BodyState::read_fields(mask, bodies.get(&id), r)?;
+        bodies.insert(id, body);
+    }
+    for _ in 0..r.u16()? {
+        bodies.remove(&r.u32()?);
+    }
+
+    let mut explosions = Vec::new();
+    for _ in 0..r.u16()? {
+        explosions.push((r.vec2()?, r.vec2()?));
+    }
+
+    Some((
+        Snapshot {
+            tick,
+            server_time,
+            bodies,
+        },
+        explosions,
+    ))
+}
+
+struct RemoteClient {
+    ship: Entity,
+    ship_id: u32,
+    /// Time since startup when we last got a packet from them
+    last_heard: Duration,
+    last_input_seq: u32,
+    acked_tick: Option<u32>,
+    input: PlayerInput,
+}
+
+struct NetServer {
+    link: Link,
+    clients: HashMap<SocketAddr, RemoteClient>,
+    history: VecDeque<Snapshot>,
+    tick: u32,
+    next_net_id: u32,
+    next_team: u32,
+    snapshot_timer: Timer,
+    pending_explosions: Vec<ExplosionNotice>,
+}
+
+impl NetServer {
+    fn net_id(&mut self) -> NetId {
+        self.next_net_id += 1;
+        NetId(self.next_net_id)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, SystemLabel)]
+enum NetSystem {
+    Receive,
+    CollectExplosions,
+}
+
+/// Runs the authoritative side of a networked match: accepts clients, gives each one a ship,
+/// applies their inputs and broadcasts snapshots of the world.
+pub struct ServerPlugin(pub ServerConfig);
+
+impl Plugin for ServerPlugin {
+    fn build(&self, app: &mut App) {
+        let link = Link::bind(self.0.bind_address, self.0.link.clone())
+            .expect("Unable to bind server socket");
+        info!("listening on {}", self.0.bind_address);
+
+        app.insert_resource(NetServer {
+            link,
+            clients: HashMap::new(),
+            history: VecDeque::with_capacity(SNAPSHOT_HISTORY),
+            tick: 0,
+            next_net_id: 0,
+            next_team: 0,
+            snapshot_timer: Timer::from_seconds(1.0 / self.0.snapshot_rate, true),
+            pending_explosions: Vec::new(),
+        })
+        .add_system(server_receive.label(NetSystem::Receive))
+        .add_system(server_apply_inputs.after(NetSystem::Receive))
+        .add_system(assign_net_ids)
+        .add_system(collect_explosions.label(NetSystem::CollectExplosions))
+        .add_system(
+            broadcast_snapshots
+                .after(NetSystem::CollectExplosions)
+                .after(PhysicsSystem::Integrate),
+        );
+    }
+}
+
+fn server_receive(time: Res<Time>, mut commands: Commands, mut server: ResMut<NetServer>) {
+    let mut buf = vec![0; MAX_PACKET_SIZE];
+    let now = time.time_since_startup();
+
+    while let Some((len, from)) = server.link.recv(&mut buf) {
+        let message = match ClientMessage::decode(&buf[..len]) {
+            Some(message) => message,
+            None => continue,
+        };
+
+        match message {
+            ClientMessage::Hello => {
+                let ship_id = match server.clients.get_mut(&from) {
+                    Some(client) => {
+                        client.last_heard = now;
+                        client.ship_id
+                    }
+                    None => {
+                        server.next_team += 1;
+                        let team = server.next_team;
+                        let net_id = server.net_id();
+
+                        // spread the players out around the star
+                        let angle = team as f32 * 2.4;
+                        let (sin, cos) = angle.sin_cos();
+                        let ship = commands
+                            .spawn_bundle(ShipBundle {
+                                position: Position(Vec2::new(cos, sin) * 300.),
+                                mass: Mass(0.0001),
+                                velocity: Velocity(Vec2::new(sin, -cos) * 40.),
+                                size: Size(0.3),
+                                engine: EnginePhysics {
+                                    max_accel: 1.0,
+                                    ..Default::default()
+                                },
+                                team: Team(std::num::NonZeroU32::new(team)),
+                                ..Default::default()
+                            })
+                            .insert(net_id)
+                            .id();
+
+                        info!("{} joined on team {}", from, team);
+                        server.clients.insert(
+                            from,
+                            RemoteClient {
+                                ship,
+                                ship_id: net_id.0,
+                                last_heard: now,
+                                last_input_seq: 0,
+                                acked_tick: None,
+                                input: PlayerInput::default(),
+                            },
+                        );
+                        net_id.0
+                    }
+                };
+
+                let mut w = PacketWriter::default();
+                w.u8(WELCOME);
+                w.u32(ship_id);
+                server.link.send(from, w.0, now);
+            }
+            ClientMessage::Input {
+                seq,
+                acked_tick,
+                input,
+            } => {
+                if let Some(client) = server.clients.get_mut(&from) {
+                    client.last_heard = now;
+                    // inputs can arrive out of order, only the newest one counts
+                    if seq > client.last_input_seq {
+                        client.last_input_seq = seq;
+                        client.acked_tick = acked_tick;
+                        client.input = input;
+                    }
+                }
+            }
+            ClientMessage::Goodbye => {
+                if let Some(client) = server.clients.remove(&from) {
+                    info!("{} left", from);
+                    commands.entity(client.ship).despawn();
+                }
+            }
+        }
+    }
+
+    server.clients.retain(|addr, client| {
+        let alive = now.saturating_sub(client.last_heard) < CLIENT_TIMEOUT;
+        if !alive {
+            info!("{} timed out", addr);
+            commands.entity(client.ship).despawn();
+        }
+        alive
+    });
+}
+
+fn server_apply_inputs(
+    server: Res<NetServer>,
+    mut ships: Query<
+        (
+            &Position,
+            &Velocity,
+            &Team,
+            &mut EnginePhysics,
+            &mut Magazine,
+        ),
+        With<Ship>,
+    >,
+    mut spawn_missile_event: EventWriter<SpawnMissileFromShip>,
+) {
+    for client in server.clients.values() {
+        // the ship might have been blown up already
+        if let Ok((ship_pos, ship_vel, ship_team, mut engine, mut magazine)) =
+            ships.get_mut(client.ship)
+        {
+            engine.set_controls(client.input.thrust, client.input.turn);
+            if client.input.fire && magazine.take() {
+                spawn_missile_event.send(SpawnMissileFromShip {
+                    ship: client.ship,
+                    position: ship_pos.clone(),
+                    velocity: Velocity(ship_vel.0 + (45.0 * ship_vel.0.normalize())),
+                    team: ship_team.clone(),
+                });
+            }
+        }
+    }
+}
+
+/// Gives every replicated body that was spawned on the server an id that clients can refer to it by
+fn assign_net_ids(
+    mut commands: Commands,
+    mut server: ResMut<NetServer>,
+    bodies: Query<
+        Entity,
+        (
+            Without<NetId>,
+            Or<(With<GravitySource>, With<Ship>, With<Missile>)>,
+        ),
+    >,
+) {
+    for id in bodies.iter() {
+        let net_id = server.net_id();
+        commands.entity(id).insert(net_id);
+    }
+}
+
+fn collect_explosions(
+    mut server: ResMut<NetServer>,
+    mut explosion_events: EventReader<CreateExplosionEvent>,
+) {
+    for ev in explosion_events.iter() {
+        server
+            .pending_explosions
+            .push((ev.position.0, ev.velocity.0));
+    }
+}
+
+fn broadcast_snapshots(
+    time: Res<Time>,
+    mut server: ResMut<NetServer>,
+    bodies: Query<(
+        &NetId,
+        &Position,
+        Option<&Velocity>,
+        Option<&Team>,
+        &Size,
+        &Mass,
+        Option<&Star>,
+        Option<&Planet>,
+        Option<&Ship>,
+    )>,
+) {
+    let server = &mut *server;
+    let now = time.time_since_startup();
+    server.link.flush(now);
+    if !server.snapshot_timer.tick(time.delta()).just_finished() {
+        return;
+    }
+
+    server.tick += 1;
+    let snapshot = Snapshot {
+        tick: server.tick,
+        server_time: time.seconds_since_startup(),
+        bodies: bodies
+            .iter()
+            .map(|(net_id, pos, vel, team, size, mass, star, planet, ship)| {
+                let kind = if star.is_some() {
+                    BodyKind::Star
+                } else if planet.is_some() {
+                    BodyKind::Planet
+                } else if ship.is_some() {
+                    BodyKind::Ship
+                } else {
+                    BodyKind::Missile
+                };
+                let state = BodyState {
+                    kind,
+                    position: pos.0,
+                    velocity: vel.map_or(Vec2::ZERO, |v| v.0),
+                    team: team.and_then(|t| t.0).map_or(0, |t| t.get()),
+                    size: size.0,
+                    mass: mass.0,
+                };
+                (net_id.0, state)
+            })
+            .collect(),
+    };
+
+    for (addr, client) in server.clients.iter() {
+        let baseline = client
+            .acked_tick
+            .and_then(|tick| server.history.iter().find(|s| s.tick == tick));
+        let packet = encode_snapshot(&snapshot, baseline, &server.pending_explosions);
+        server.link.send(*addr, packet, now);
+    }
+    server.pending_explosions.clear();
+
+    if server.history.len() == SNAPSHOT_HISTORY {
+        server.history.pop_front();
+    }
+    server.history.push_back(snapshot);
+}
+
+struct NetClient {
+    link: Link,
+    server: SocketAddr,
+    interpolation_delay: f64,
+    /// Received snapshots, oldest first
+    snapshots: VecDeque<Snapshot>,
+    /// Net id of the ship the server gave us
+    own_ship: Option<u32>,
+    /// Time since startup when we last said hello
+    last_hello: Option<Duration>,
+    input_seq: u32,
+    /// Estimate of (server time - local time)
+    clock_offset: Option<f64>,
+    entities: HashMap<u32, Entity>,
+}
+
+impl Drop for NetClient {
+    fn drop(&mut self) {
+        // best effort, so that our ship doesn't hang around until the server times us out
+        self.link
+            .send_now(self.server, &ClientMessage::Goodbye.encode());
+    }
+}
+
+/// Connects to a [`ServerPlugin`], sends it our inputs and mirrors the world it describes.
+///
+/// Bodies are drawn `interpolation_delay` in the past, smoothly interpolated between the two
+/// snapshots on either side of that time. If snapshots stop arriving they are extrapolated with
+/// the local gravity model instead.
+pub struct ClientPlugin(pub ClientConfig);
+
+impl Plugin for ClientPlugin {
+    fn build(&self, app: &mut App) {
+        let link = Link::bind(([0, 0, 0, 0], 0).into(), self.0.link.clone())
+            .expect("Unable to bind client socket");
+
+        app.insert_resource(NetClient {
+            link,
+            server: self.0.server_address,
+            interpolation_delay: self.0.interpolation_delay.as_secs_f64(),
+            snapshots: VecDeque::with_capacity(SNAPSHOT_HISTORY),
+            own_ship: None,
+            last_hello: None,
+            input_seq: 0,
+            clock_offset: None,
+            entities: HashMap::new(),
+        })
+        .add_system(client_send_input)
+        .add_system(client_receive.label(NetSystem::Receive))
+        .add_system(client_sync_entities.after(NetSystem::Receive))
+        .add_system(
+            client_interpolate
+                .after(NetSystem::Receive)
+                .before(PhysicsSystem::SyncSprites),
+        );
+    }
+}
+
+fn client_send_input(time: Res<Time>, actions: Res<ActionState>, mut client: ResMut<NetClient>) {
+    let client = &mut *client;
+    let server = client.server;
+    let now = time.time_since_startup();
+
+    if client.own_ship.is_none() {
+        let due = client
+            .last_hello
+            .map_or(true, |sent| now.saturating_sub(sent) >= HELLO_INTERVAL);
+        if due {
+            client.last_hello = Some(now);
+            client.link.send(server, ClientMessage::Hello.encode(), now);
+        }
+    } else {
+        client.input_seq += 1;
+        let message = ClientMessage::Input {
+            seq: client.input_seq,
+            acked_tick: client.snapshots.back().map(|s| s.tick),
+            input: PlayerInput {
+                thrust: actions.throttle(),
+                turn: actions.turn(),
+                fire: actions.pressed(Action::Fire),
+            },
+        };
+        client.link.send(server, message.encode(), now);
+    }
+
+    client.link.flush(now);
+}
+
+fn client_receive(
+    time: Res<Time>,
+    mut client: ResMut<NetClient>,
+    mut explosion_event: EventWriter<CreateExplosionEvent>,
+) {
+    let mut buf = vec![0; MAX_PACKET_SIZE];
+    while let Some((len, from)) = client.link.recv(&mut buf) {
+        if from != client.server {
+            continue;
+        }
+
+        let mut r = PacketReader(&buf[..len]);
+        match r.u8() {
+            Some(WELCOME) => {
+                if let Some(ship_id) = r.u32() {
+                    client.own_ship = Some(ship_id);
+                }
+            }
+            Some(SNAPSHOT) => {
+                let (snapshot, explosions) = match decode_snapshot(&mut r, &client.snapshots) {
+                    Some(decoded) => decoded,
+                    None => continue,
+                };
+                // stale (reordered) snapshots are useless to us
+                if client
+                    .snapshots
+                    .back()
+                    .map_or(false, |newest| newest.tick >= snapshot.tick)
+                {
+                    continue;
+                }
+
+                // latency only ever makes the server look further behind than it is, so jump
+                // forwards straight away but only drift backwards slowly
+                let offset_sample = snapshot.server_time - time.seconds_since_startup();
+                client.clock_offset = Some(match client.clock_offset {
+                    Some(offset) if offset_sample < offset => {
+                        offset + (offset_sample - offset) * 0.05
+                    }
+                    _ => offset_sample,
+                });
+
+                for (position, velocity) in explosions {
+                    explosion_event.send(CreateExplosionEvent {
+                        position: Position(position),
+                        velocity: Velocity(velocity),
+                    });
+                }
+
+                if client.snapshots.len() == SNAPSHOT_HISTORY {
+                    client.snapshots.pop_front();
+                }
+                client.snapshots.push_back(snapshot);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Spawns and despawns entities so that we have exactly one per body in the newest snapshot
+fn client_sync_entities(mut commands: Commands, mut client: ResMut<NetClient>) {
+    let client = &mut *client;
+    let newest = match client.snapshots.back() {
+        Some(newest) => newest,
+        None => return,
+    };
+
+    client.entities.retain(|net_id, entity| {
+        let exists = newest.bodies.contains_key(net_id);
+        if !exists {
+            commands.entity(*entity).despawn();
+        }
+        exists
+    });
+
+    for (net_id, body) in newest.bodies.iter() {
+        if client.entities.contains_key(net_id) {
+            continue;
+        }
+
+        let position = Position(body.position);
+        let velocity = Velocity(body.velocity);
+        let mass = Mass(body.mass);
+        let size = Size(body.size);
+        let team = Team(std::num::NonZeroU32::new(body.team));
+
+        let mut entity = match body.kind {
+            BodyKind::Star => commands.spawn_bundle(StarBundle {
+                position,
+                velocity,
+                mass,
+                size,
+                ..Default::default()
+            }),
+            BodyKind::Planet => commands.spawn_bundle(PlanetBundle {
+                position,
+                velocity,
+                mass,
+                size,
+                ..Default::default()
+            }),
+            BodyKind::Ship => commands.spawn_bundle(ShipBundle {
+                position,
+                velocity,
+                mass,
+                size,
+                team,
+                ..Default::default()
+            }),
+            BodyKind::Missile => commands.spawn_bundle(MissileBundle {
+                position,
+                velocity,
+                mass,
+                size,
+                team,
+                ..Default::default()
+            }),
+        };
+        entity.insert(NetId(*net_id));
+        if client.own_ship == Some(*net_id) {
+            entity.insert(LocalPlayer);
+        }
+        client.entities.insert(*net_id, entity.id());
+    }
+}
+
+/// Cubic hermite interpolation between two (position, velocity) samples that are `dt` seconds apart.
+/// Using the velocities as tangents makes curved orbits come out curved, not as straight segments.
+fn hermite(a: &BodyState, b: &BodyState, dt: f32, s: f32) -> (Vec2, Vec2) {
+    let s2 = s * s;
+    let s3 = s2 * s;
+
+    let h00 = 2. * s3 - 3. * s2 + 1.;
+    let h10 = s3 - 2. * s2 + s;
+    let h01 = -2. * s3 + 3. * s2;
+    let h11 = s3 - s2;
+    let position =
+        h00 * a.position + h10 * dt * a.velocity + h01 * b.position + h11 * dt * b.velocity;
+
+    let dh00 = 6. * s2 - 6. * s;
+    let dh10 = 3. * s2 - 4. * s + 1.;
+    let dh01 = -6. * s2 + 6. * s;
+    let dh11 = 3. * s2 - 2. * s;
+    let velocity =
+        (dh00 * a.position + dh01 * b.position) / dt + dh10 * a.velocity + dh11 * b.velocity;
+
+    (position, velocity)
+}
+
+/// Moves every body `dt` seconds past `snapshot` using the same gravity as the server.
+/// Gravity sources are only moved in straight lines, which is plenty for the short gaps this covers.
+fn extrapolate(snapshot: &Snapshot, dt: f64) -> HashMap<u32, (Vec2, Vec2)> {
+    const STEP: f64 = 1.0 / 60.0;
+
+    let mut bodies: HashMap<u32, (Vec2, Vec2)> = snapshot
+        .bodies
+        .iter()
+        .map(|(id, body)| (*id, (body.position, body.velocity)))
+        .collect();
+    let sources: Vec<(u32, f32)> = snapshot
+        .bodies
+        .iter()
+        .filter(|(_, body)| matches!(body.kind, BodyKind::Star | BodyKind::Planet))
+        .map(|(id, body)| (*id, body.mass))
+        .collect();
+
+    let mut remaining = dt;
+    while remaining > 0.0 {
+        let step = remaining.min(STEP);
+        remaining -= step;
+
+        let source_positions: Vec<(Vec2, f32)> = sources
+            .iter()
+            .map(|(id, mass)| (bodies[id].0, *mass))
+            .collect();
+        for (id, (position, velocity)) in bodies.iter_mut() {
+            let kind = snapshot.bodies[id].kind;
+            if kind != BodyKind::Star && kind != BodyKind::Planet {
+                *velocity +=
+                    gravitational_acceleration(*position, source_positions.iter().copied())
+                        * step as f32;
+            }
+            *position += *velocity * step as f32;
+        }
+    }
+
+    bodies
+}
+
+fn client_interpolate(
+    time: Res<Time>,
+    client: Res<NetClient>,
+    mut replicated: Query<(&NetId, &mut Position, &mut Velocity)>,
+    mut local_only: Query<(&mut Position, &Velocity), Without<NetId>>,
+) {
+    // nothing simulates the purely cosmetic stuff (e.g explosions) on a client, so just let it drift
+    for (mut pos, vel) in local_only.iter_mut() {
+        pos.0 += vel.0 * time.delta_seconds();
+    }
+
+    let (offset, newest) = match (client.clock_offset, client.snapshots.back()) {
+        (Some(offset), Some(newest)) => (offset, newest),
+        _ => return,
+    };
+    let render_time = time.seconds_since_startup() + offset - client.interpolation_delay;
+
+    // find the pair of snapshots surrounding render_time
+    let bracket = client
+        .snapshots
+        .iter()
+        .zip(client.snapshots.iter().skip(1))
+        .find(|(a, b)| a.server_time <= render_time && render_time < b.server_time);
+
+    if let Some((a, b)) = bracket {
+        let dt = (b.server_time - a.server_time) as f32;
+        let s = ((render_time - a.server_time) as f32 / dt).clamp(0.0, 1.0);
+        for (net_id, mut pos, mut vel) in replicated.iter_mut() {
+            let state = match (a.bodies.get(&net_id.0), b.bodies.get(&net_id.0)) {
+                (Some(a), Some(b)) => hermite(a, b, dt, s),
+                (_, Some(b)) => (b.position, b.velocity),
+                _ => continue,
+            };
+            pos.0 = state.0;
+            vel.0 = state.1;
+        }
+    } else {
+        // we've run out of snapshots (or are still waiting for the second one)
+        let dt = (render_time - newest.server_time).clamp(0.0, MAX_EXTRAPOLATION);
+        let extrapolated = extrapolate(newest, dt);
+        for (net_id, mut pos, mut vel) in replicated.iter_mut() {
+            if let Some((position, velocity)) = extrapolated.get(&net_id.0) {
+                pos.0 = *position;
+                vel.0 = *velocity;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use bevy::core::CorePlugin;
+
+    use super::*;
+    use crate::physics::{PhysicsPlugin, SimulationAuthority};
+
+    /// How far the clocks are moved on between updates
+    const TICK: Duration = Duration::from_millis(10);
+
+    /// A bad but usable connection, applied in both directions
+    fn lossy_link(seed: u64) -> LinkConditions {
+        LinkConditions {
+            latency: Duration::from_millis(40),
+            jitter: Duration::from_millis(20),
+            packet_loss: 0.2,
+            seed,
+        }
+    }
+
+    /// The bare minimum to run the systems, with a clock that only moves when [`step`] says so
+    fn headless_app() -> App {
+        let mut app = App::new();
+        app.add_plugin(CorePlugin::default())
+            .init_resource::<Time>()
+            .add_plugin(PhysicsPlugin);
+        app
+    }
+
+    fn server_app() -> App {
+        let mut app = headless_app();
+        app.add_event::<SpawnMissileFromShip>()
+            .add_event::<CreateExplosionEvent>()
+            .add_plugin(ServerPlugin(ServerConfig {
+                bind_address: ([127, 0, 0, 1], 0).into(),
+                link: lossy_link(1),
+                ..Default::default()
+            }))
+            .add_startup_system(crate::initialize_server_world);
+        app
+    }
+
+    fn client_app(server_address: SocketAddr) -> App {
+        let mut app = headless_app();
+        app.insert_resource(SimulationAuthority::Remote)
+            .init_resource::<ActionState>()
+            .add_event::<CreateExplosionEvent>()
+            .add_plugin(ClientPlugin(ClientConfig {
+                server_address,
+                interpolation_delay: Duration::from_millis(150),
+                link: lossy_link(2),
+            }));
+        app
+    }
+
+    fn body(kind: BodyKind, position: Vec2, velocity: Vec2) -> BodyState {
+        BodyState {
+            kind,
+            position,
+            velocity,
+            team: 0,
+            size: 1.,
+            mass: 1.,
+        }
+    }
+
+    #[test]
+    fn delta_snapshot_round_trips() {
+        let baseline = Snapshot {
+            tick: 7,
+            server_time: 1.,
+            bodies: HashMap::from([
+                (1, body(BodyKind::Star, Vec2::ZERO, Vec2::ZERO)),
+                (2, body(BodyKind::Planet, Vec2::X, Vec2::Y)),
+                (3, body(BodyKind::Missile, Vec2::Y, Vec2::X)),
+            ]),
+        };
+        let mut snapshot = baseline.clone();
+        snapshot.tick = 8;
+        snapshot.server_time = 1.05;
+        snapshot.bodies.get_mut(&2).unwrap().position = Vec2::new(1., 0.05);
+        snapshot.bodies.remove(&3);
+        snapshot
+            .bodies
+            .insert(4, body(BodyKind::Ship, Vec2::NEG_X, Vec2::ZERO));
+
+        let packet = encode_snapshot(&snapshot, Some(&baseline), &[(Vec2::ONE, Vec2::ZERO)]);
+        let full = encode_snapshot(&snapshot, None, &[]);
+        assert!(packet.len() < full.len());
+
+        let mut r = PacketReader(&packet);
+        assert_eq!(r.u8(), Some(SNAPSHOT));
+        let history = VecDeque::from([baseline]);
+        let (decoded, explosions) = decode_snapshot(&mut r, &history).unwrap();
+        assert_eq!(decoded.tick, snapshot.tick);
+        assert_eq!(decoded.bodies, snapshot.bodies);
+        assert_eq!(explosions, vec![(Vec2::ONE, Vec2::ZERO)]);
+
+        // without the baseline the delta is useless, and has to be thrown away
+        let mut r = PacketReader(&packet);
+        r.u8();
+        assert!(decode_snapshot(&mut r, &VecDeque::new()).is_none());
+    }
+
+    /// Advances `app`'s clock to `now` by hand and runs one update, so that the delays on the
+    /// link come out the same every run
+    fn step(app: &mut App, now: Instant) {
+        app.world.resource_mut::<Time>().update_with_instant(now);
+        app.update();
+    }
+
+    /// Runs a server and a client against each other over localhost, with latency, jitter and
+    /// packet loss on both ends, and checks that what the client shows matches the server
+    #[test]
+    fn client_converges_over_lossy_localhost() {
+        let mut server = server_app();
+        let server_address = server
+            .world
+            .resource::<NetServer>()
+            .link
+            .socket
+            .local_addr()
+            .unwrap();
+        let mut client = client_app(server_address);
+
+        // where every body was on the server at each update, by server time
+        let mut server_history: Vec<(f64, HashMap<u32, Vec2>)> = Vec::new();
+        let started = Instant::now();
+        for tick in 1..=400 {
+            let now = started + TICK * tick;
+            step(&mut server, now);
+            let mut bodies = server.world.query::<(&NetId, &Position)>();
+            server_history.push((
+                server.world.resource::<Time>().seconds_since_startup(),
+                bodies
+                    .iter(&server.world)
+                    .map(|(id, Position(pos))| (id.0, *pos))
+                    .collect(),
+            ));
+            step(&mut client, now);
+        }
+
+        let net_client = client.world.resource::<NetClient>();
+        let own_ship = net_client.own_ship.expect("the server never welcomed us");
+        let newest = net_client.snapshots.back().expect("no snapshots arrived");
+        assert!(newest.bodies.contains_key(&own_ship));
+
+        // the client keeps acking what it has, so the server should be sending deltas against
+        // snapshots that the client can still decode
+        let net_server = server.world.resource::<NetServer>();
+        let acked_tick = net_server
+            .clients
+            .values()
+            .next()
+            .and_then(|remote| remote.acked_tick)
+            .expect("the client never acked a snapshot");
+        let baseline = net_server
+            .history
+            .iter()
+            .find(|s| s.tick == acked_tick)
+            .expect("acked snapshot has fallen out of the server's history");
+        let latest = net_server.history.back().unwrap();
+        let packet = encode_snapshot(latest, Some(baseline), &[]);
+        let mut r = PacketReader(&packet);
+        r.u8();
+        let (decoded, _) = decode_snapshot(&mut r, &net_client.snapshots)
+            .expect("delta against the acked tick didn't decode");
+        assert_eq!(decoded.bodies, latest.bodies);
+
+        // the client draws everything a little in the past, so compare against where the server
+        // had things at that time
+        let render_time = client.world.resource::<Time>().seconds_since_startup()
+            + net_client.clock_offset.unwrap()
+            - net_client.interpolation_delay;
+        let next = server_history.partition_point(|(time, _)| *time <= render_time);
+        assert!(
+            next > 0 && next < server_history.len(),
+            "client is drawing {}s, which the server never got to",
+            render_time
+        );
+        let ((before_time, before), (after_time, after)) =
+            (&server_history[next - 1], &server_history[next]);
+        let s = ((render_time - before_time) / (after_time - before_time)) as f32;
+
+        let mut compared = 0;
+        let mut client_bodies = client.world.query::<(&NetId, &Position)>();
+        for (id, Position(pos)) in client_bodies.iter(&client.world) {
+            let expected = match (before.get(&id.0), after.get(&id.0)) {
+                (Some(a), Some(b)) => a.lerp(*b, s),
+                _ => continue,
+            };
+            assert!(
+                pos.distance(expected) < 0.5,
+                "body {} is at {} on the client but was at {} on the server",
+                id.0,
+                pos,
+                expected
+            );
+            compared += 1;
+        }
+        assert!(compared > 1);
+    }
+}
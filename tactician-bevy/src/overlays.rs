@@ -0,0 +1,518 @@
+use bevy::diagnostic::Diagnostics;
+use bevy::prelude::*;
+use bevy_prototype_lyon::prelude::{
+    tess::path::path::Builder, DrawMode, FillMode, Geometry, GeometryBuilder, StrokeMode,
+    StrokeOptions,
+};
+use lyon_geom::euclid::default::Point2D;
+
+use crate::camera::{CameraController, CameraFrame};
+use crate::colliders::Collider;
+use crate::components::Size;
+use crate::components::*;
+use crate::controls::{Action, ActionState};
+use crate::diagnostics::ConservationDiagnosticsPlugin;
+use crate::physics::{AccelerationBreakdowns, CorotatingFrame, LagrangePoint, OrbitalBody};
+use crate::resources::Typography;
+
+/// Cells along each side of the grid that the zero-velocity contours are traced on
+const CONTOUR_RESOLUTION: usize = 96;
+
+const LAGRANGE_COLOR: Color = Color::rgba(0.5, 0.9, 1.0, 0.8);
+const CONTOUR_COLOR: Color = Color::rgba(0.5, 0.9, 1.0, 0.3);
+const COLLIDER_COLOR: Color = Color::rgba(1.0, 0.3, 0.3, 0.8);
+
+const VELOCITY_COLOR: Color = Color::rgba(0.3, 1.0, 0.4, 0.9);
+const GRAVITY_COLOR: Color = Color::rgba(1.0, 0.85, 0.2, 0.9);
+const SOURCE_PULL_COLOR: Color = Color::rgba(1.0, 0.85, 0.2, 0.35);
+const THRUST_COLOR: Color = Color::rgba(1.0, 0.5, 0.1, 0.9);
+const DRAG_COLOR: Color = Color::rgba(0.4, 0.7, 1.0, 0.9);
+
+/// Colors of the energy, momentum and angular momentum drifts in the conservation graph
+const DRIFT_COLORS: [Color; 3] = [
+    Color::rgba(1.0, 0.4, 0.4, 0.9),
+    Color::rgba(0.4, 1.0, 0.4, 0.9),
+    Color::rgba(0.4, 0.6, 1.0, 0.9),
+];
+/// Size of the conservation graph on screen, in pixels
+const GRAPH_SIZE: Vec2 = Vec2::new(300., 120.);
+/// Gap between the conservation graph and the bottom left corner of the screen, in pixels
+const GRAPH_MARGIN: f32 = 10.;
+
+/// Velocity arrows reach where the body will be this many seconds from now
+const VELOCITY_ARROW_SECONDS: f32 = 1.0;
+/// Acceleration arrows are this many times longer than the acceleration, which is usually tiny
+/// next to the velocity
+const ACCELERATION_ARROW_SCALE: f32 = 20.0;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, SystemLabel)]
+pub enum OverlaySystem {
+    /// Gets rid of everything drawn last update
+    Clear,
+    Draw,
+}
+
+/// Which debug/planning overlays are being drawn on top of the game
+#[derive(Debug, Default)]
+pub struct Overlays {
+    /// Lagrange points and zero-velocity contours of the planet being looked at
+    pub lagrange: bool,
+    /// Outlines of the hitboxes of everything
+    pub colliders: bool,
+    /// Velocity, gravity, thrust and drag of everything that moves
+    pub vectors: bool,
+    /// Graph of how far energy and momentum have drifted since the match started
+    pub conservation: bool,
+}
+
+/// Everything drawn by an overlay. It's all despawned and redrawn every update.
+#[derive(Debug, Default, Clone, Copy, Component)]
+pub struct OverlayMarker;
+
+/// A bunch of separate straight lines
+struct LineSegments(Vec<(Vec2, Vec2)>);
+
+impl Geometry for LineSegments {
+    fn add_geometry(&self, b: &mut Builder) {
+        let point = |p: Vec2| Point2D::new(p.x, p.y);
+        for (start, end) in self.0.iter() {
+            b.begin(point(*start));
+            b.line_to(point(*end));
+            b.end(false);
+        }
+    }
+}
+
+/// A line along `vector` from `start` with a little arrowhead on the end
+fn arrow(start: Vec2, vector: Vec2, head: f32) -> [(Vec2, Vec2); 3] {
+    let end = start + vector;
+    let back = -vector.normalize_or_zero() * head.min(vector.length() / 2.);
+    [
+        (start, end),
+        (end, end + back + back.perp() * 0.5),
+        (end, end + back - back.perp() * 0.5),
+    ]
+}
+
+pub fn toggle_overlays(
+    actions: Res<ActionState>,
+    mut overlays: ResMut<Overlays>,
+    mut breakdowns: ResMut<AccelerationBreakdowns>,
+) {
+    if actions.just_pressed(Action::LagrangeOverlay) {
+        overlays.lagrange = !overlays.lagrange;
+    }
+    if actions.just_pressed(Action::ColliderOverlay) {
+        overlays.colliders = !overlays.colliders;
+    }
+    if actions.just_pressed(Action::ConservationGraph) {
+        overlays.conservation = !overlays.conservation;
+    }
+    if actions.just_pressed(Action::VectorOverlay) {
+        overlays.vectors = !overlays.vectors;
+        // the physics only keeps track of what makes up each acceleration while it's being shown
+        breakdowns.enabled = overlays.vectors;
+        breakdowns.bodies.clear();
+    }
+}
+
+pub fn clear_overlays(mut commands: Commands, drawn: Query<Entity, With<OverlayMarker>>) {
+    for entity in drawn.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+/// The planet the camera is paying attention to (the one whose frame it's in, or the one it's
+/// following), falling back to the heaviest one
+fn focused_planet(
+    controller: &CameraController,
+    planets: &Query<(Entity, &Mass), With<Planet>>,
+) -> Option<Entity> {
+    let focused = match controller.frame {
+        CameraFrame::Rotating(body) => Some(body),
+        CameraFrame::Inertial | CameraFrame::CenterOfMass => controller.target,
+    };
+    focused.filter(|body| planets.contains(*body)).or_else(|| {
+        planets
+            .iter()
+            .max_by(|(_, Mass(a)), (_, Mass(b))| a.total_cmp(b))
+            .map(|(planet, _)| planet)
+    })
+}
+
+/// Traces the contour lines where `values` (sampled on a square grid of `resolution + 1` points a
+/// side) crosses `level`, using marching squares. Points come back in grid coordinates.
+fn contour_segments(values: &[f32], resolution: usize, level: f32) -> Vec<(Vec2, Vec2)> {
+    let stride = resolution + 1;
+    let mut segments = Vec::new();
+    for y in 0..resolution {
+        for x in 0..resolution {
+            // corners counterclockwise from the bottom left
+            let corners = [(x, y), (x + 1, y), (x + 1, y + 1), (x, y + 1)];
+            let value = |(cx, cy): (usize, usize)| values[cy * stride + cx];
+            // where the contour crosses the edge from corner `a` to corner `b`, if it does
+            let crossing = |a: usize, b: usize| {
+                let (va, vb) = (value(corners[a]), value(corners[b]));
+                if (va < level) == (vb < level) {
+                    return None;
+                }
+                let t = (level - va) / (vb - va);
+                let (pa, pb) = (corners[a], corners[b]);
+                Some(
+                    Vec2::new(pa.0 as f32, pa.1 as f32)
+                        .lerp(Vec2::new(pb.0 as f32, pb.1 as f32), t),
+                )
+            };
+
+            let crossings: Vec<Vec2> = (0..4)
+                .filter_map(|edge| crossing(edge, (edge + 1) % 4))
+                .collect();
+            // two crossings is a line through the cell, four is a saddle which we just split
+            // into two lines
+            for pair in crossings.chunks_exact(2) {
+                segments.push((pair[0], pair[1]));
+            }
+        }
+    }
+    segments
+}
+
+pub fn draw_lagrange_overlay(
+    mut commands: Commands,
+    overlays: Res<Overlays>,
+    typography: Res<Typography>,
+    controller: Res<CameraController>,
+    camera: Query<&Transform, With<ShipCamera>>,
+    planets: Query<(Entity, &Mass), With<Planet>>,
+    gravity_sources: Query<(Entity, &Position, &Mass, Option<&Velocity>), With<GravitySource>>,
+) {
+    if !overlays.lagrange {
+        return;
+    }
+
+    let as_orbital_body = |(_, Position(position), Mass(mass), velocity): (
+        Entity,
+        &Position,
+        &Mass,
+        Option<&Velocity>,
+    )| OrbitalBody {
+        position: *position,
+        velocity: velocity.map_or(Vec2::ZERO, |v| v.0),
+        mass: *mass,
+    };
+    let secondary = match focused_planet(&controller, &planets)
+        .and_then(|planet| gravity_sources.get(planet).ok())
+    {
+        Some(secondary) => secondary,
+        None => return,
+    };
+    // whatever pulls hardest on the planet is what it is orbiting
+    let primary = gravity_sources
+        .iter()
+        .filter(|(source, _, _, _)| *source != secondary.0)
+        .max_by(
+            |(_, Position(a), Mass(a_mass), _), (_, Position(b), Mass(b_mass), _)| {
+                let pull = |pos: &Vec2, mass: f32| mass / pos.distance_squared(secondary.1 .0);
+                pull(a, *a_mass).total_cmp(&pull(b, *b_mass))
+            },
+        );
+    let primary = match primary {
+        Some(primary) => primary,
+        None => return,
+    };
+    let frame = CorotatingFrame::new(as_orbital_body(primary), as_orbital_body(secondary));
+
+    let camera = camera.single();
+    let cam_scale = camera.scale.x;
+    for point in LagrangePoint::ALL {
+        let position = frame.lagrange_point(point);
+        commands
+            .spawn_bundle(GeometryBuilder::build_as(
+                &bevy_prototype_lyon::shapes::Circle {
+                    radius: 4.0 * cam_scale,
+                    center: position,
+                },
+                DrawMode::Fill(FillMode::color(LAGRANGE_COLOR)),
+                Transform::from_xyz(0., 0., 2.),
+            ))
+            .insert(OverlayMarker);
+        commands
+            .spawn_bundle(Text2dBundle {
+                text: Text::from_section(format!("{:?}", point), typography.body.clone())
+                    .with_alignment(TextAlignment::CENTER),
+                transform: Transform {
+                    translation: (position + Vec2::Y * 16. * cam_scale).extend(2.),
+                    // keep the labels upright and the same size on screen
+                    rotation: camera.rotation,
+                    scale: Vec3::splat(cam_scale),
+                },
+                ..Default::default()
+            })
+            .insert(OverlayMarker);
+    }
+
+    // sample the potential on a grid around the pair, big enough to take in L3
+    let half_extent = 1.6 * frame.primary.position.distance(frame.secondary.position);
+    let cell = 2. * half_extent / CONTOUR_RESOLUTION as f32;
+    let origin = frame.barycenter - Vec2::splat(half_extent);
+    let mut values = Vec::with_capacity((CONTOUR_RESOLUTION + 1).pow(2));
+    for y in 0..=CONTOUR_RESOLUTION {
+        for x in 0..=CONTOUR_RESOLUTION {
+            let position = origin + Vec2::new(x as f32, y as f32) * cell;
+            values.push(frame.effective_potential(position));
+        }
+    }
+
+    // the classic zero-velocity curves are the ones through the collinear points, where the
+    // regions around each body join up
+    let mut segments = Vec::new();
+    for point in [LagrangePoint::L1, LagrangePoint::L2, LagrangePoint::L3] {
+        let level = frame.effective_potential(frame.lagrange_point(point));
+        segments.extend(
+            contour_segments(&values, CONTOUR_RESOLUTION, level)
+                .into_iter()
+                .map(|(start, end)| (origin + start * cell, origin + end * cell)),
+        );
+    }
+    commands
+        .spawn_bundle(GeometryBuilder::build_as(
+            &LineSegments(segments),
+            DrawMode::Stroke(StrokeMode {
+                options: StrokeOptions::default().with_line_width(cam_scale),
+                color: CONTOUR_COLOR,
+            }),
+            Transform::from_xyz(0., 0., 2.),
+        ))
+        .insert(OverlayMarker);
+}
+
+pub fn draw_collider_overlay(
+    mut commands: Commands,
+    overlays: Res<Overlays>,
+    camera: Query<&Transform, With<ShipCamera>>,
+    bodies: Query<(&Position, Option<&Velocity>, &Size, &Collider)>,
+) {
+    if !overlays.colliders {
+        return;
+    }
+
+    let cam_scale = camera.single().scale.x;
+    for (Position(pos), velocity, size, collider) in bodies.iter() {
+        let velocity = velocity.map_or(Vec2::ZERO, |v| v.0);
+        commands
+            .spawn_bundle(GeometryBuilder::build_as(
+                &bevy_prototype_lyon::shapes::Polygon {
+                    points: collider.placed(*pos, velocity, size).outline(),
+                    closed: true,
+                },
+                DrawMode::Stroke(StrokeMode {
+                    options: StrokeOptions::default().with_line_width(cam_scale),
+                    color: COLLIDER_COLOR,
+                }),
+                Transform::from_xyz(0., 0., 2.),
+            ))
+            .insert(OverlayMarker);
+    }
+}
+
+pub fn draw_vector_overlay(
+    mut commands: Commands,
+    overlays: Res<Overlays>,
+    typography: Res<Typography>,
+    breakdowns: Res<AccelerationBreakdowns>,
+    camera: Query<&Transform, With<ShipCamera>>,
+    bodies: Query<(Entity, &Position, &Velocity, Option<&Mass>)>,
+    gravity_sources: Query<(Entity, &Position), With<GravitySource>>,
+) {
+    if !overlays.vectors {
+        return;
+    }
+
+    let camera = camera.single();
+    let cam_scale = camera.scale.x;
+    let head = 6. * cam_scale;
+    let mut velocities = Vec::new();
+    let mut gravity = Vec::new();
+    let mut source_pulls = Vec::new();
+    let mut thrust = Vec::new();
+    let mut drag = Vec::new();
+    for (entity, Position(pos), Velocity(vel), mass) in bodies.iter() {
+        velocities.extend(arrow(*pos, *vel * VELOCITY_ARROW_SECONDS, head));
+
+        // these come straight from the last physics step, so they're missing until the next one
+        // after the overlay is turned on (and altogether when the server runs the physics)
+        if let Some(breakdown) = breakdowns.bodies.get(&entity) {
+            let scaled = |accel: Vec2| accel * ACCELERATION_ARROW_SCALE;
+            if !breakdown.gravity.is_empty() {
+                gravity.extend(arrow(*pos, scaled(breakdown.net_gravity()), head));
+            }
+            // with only the one source, its pull is the same arrow as the net gravity
+            if breakdown.gravity.len() > 1 {
+                for (_, pull) in breakdown.gravity.iter() {
+                    source_pulls.extend(arrow(*pos, scaled(*pull), head));
+                }
+            }
+            if breakdown.thrust != Vec2::ZERO {
+                thrust.extend(arrow(*pos, scaled(breakdown.thrust), head));
+            }
+            if breakdown.drag != Vec2::ZERO {
+                drag.extend(arrow(*pos, scaled(breakdown.drag), head));
+            }
+        }
+
+        let nearest_source = gravity_sources
+            .iter()
+            .filter(|(source, _)| *source != entity)
+            .map(|(_, Position(source_pos))| source_pos.distance(*pos))
+            .min_by(|a, b| a.total_cmp(b));
+        let mut label = format!("{:.1} px/s", vel.length());
+        if let Some(Mass(mass)) = mass {
+            label += &format!("\nmass {:.3e}", mass);
+        }
+        if let Some(distance) = nearest_source {
+            label += &format!("\n{:.0} px from nearest source", distance);
+        }
+        commands
+            .spawn_bundle(Text2dBundle {
+                text: Text::from_section(label, typography.body.clone())
+                    .with_alignment(TextAlignment::TOP_LEFT),
+                transform: Transform {
+                    translation: (*pos + Vec2::new(12., -12.) * cam_scale).extend(2.),
+                    // keep the labels upright and the same size on screen
+                    rotation: camera.rotation,
+                    scale: Vec3::splat(cam_scale),
+                },
+                ..Default::default()
+            })
+            .insert(OverlayMarker);
+    }
+
+    for (segments, color) in [
+        (source_pulls, SOURCE_PULL_COLOR),
+        (gravity, GRAVITY_COLOR),
+        (drag, DRAG_COLOR),
+        (thrust, THRUST_COLOR),
+        (velocities, VELOCITY_COLOR),
+    ] {
+        commands
+            .spawn_bundle(GeometryBuilder::build_as(
+                &LineSegments(segments),
+                DrawMode::Stroke(StrokeMode {
+                    options: StrokeOptions::default().with_line_width(1.5 * cam_scale),
+                    color,
+                }),
+                Transform::from_xyz(0., 0., 2.),
+            ))
+            .insert(OverlayMarker);
+    }
+}
+
+/// Plots the energy and momentum drifts in the bottom left corner of the screen. It's drawn into
+/// the world like the other overlays, just pinned to wherever the camera is.
+pub fn draw_conservation_graph(
+    mut commands: Commands,
+    overlays: Res<Overlays>,
+    typography: Res<Typography>,
+    diagnostics: Res<Diagnostics>,
+    windows: Res<Windows>,
+    camera: Query<&Transform, With<ShipCamera>>,
+) {
+    if !overlays.conservation {
+        return;
+    }
+    let window = match windows.get_primary() {
+        Some(window) => window,
+        None => return,
+    };
+
+    let camera = camera.single();
+    let cam_scale = camera.scale.x;
+    let corner = Vec2::new(-window.width(), -window.height()) / 2. + GRAPH_MARGIN;
+    let to_world = |screen: Vec2| {
+        camera.translation.truncate()
+            + (camera.rotation * ((corner + screen) * cam_scale).extend(0.)).truncate()
+    };
+
+    let series: Vec<_> = ConservationDiagnosticsPlugin::DRIFTS
+        .iter()
+        .filter_map(|id| diagnostics.get(*id))
+        .collect();
+    // the same scale for every series, symmetric around zero, so they can be compared
+    let largest = series
+        .iter()
+        .flat_map(|diagnostic| diagnostic.values())
+        .fold(0., |largest: f64, value| largest.max(value.abs()))
+        .max(1e-9);
+
+    let axes = [
+        (Vec2::ZERO, Vec2::new(0., GRAPH_SIZE.y)),
+        // zero
+        (
+            Vec2::new(0., GRAPH_SIZE.y / 2.),
+            Vec2::new(GRAPH_SIZE.x, GRAPH_SIZE.y / 2.),
+        ),
+    ];
+    commands
+        .spawn_bundle(GeometryBuilder::build_as(
+            &LineSegments(
+                axes.into_iter()
+                    .map(|(start, end)| (to_world(start), to_world(end)))
+                    .collect(),
+            ),
+            DrawMode::Stroke(StrokeMode {
+                options: StrokeOptions::default().with_line_width(cam_scale),
+                color: Color::rgba(1.0, 1.0, 1.0, 0.4),
+            }),
+            Transform::from_xyz(0., 0., 3.),
+        ))
+        .insert(OverlayMarker);
+
+    let mut label = vec![TextSection::new(
+        format!("drift since start (±{:.2e}%)", largest),
+        typography.body.clone(),
+    )];
+    for (diagnostic, color) in series.iter().zip(DRIFT_COLORS) {
+        let values: Vec<f64> = diagnostic.values().copied().collect();
+        let step = GRAPH_SIZE.x / (diagnostic.get_max_history_length().max(2) - 1) as f32;
+        let points: Vec<Vec2> = values
+            .iter()
+            .enumerate()
+            .map(|(idx, value)| {
+                let height = (0.5 + 0.5 * value / largest) as f32 * GRAPH_SIZE.y;
+                to_world(Vec2::new(idx as f32 * step, height))
+            })
+            .collect();
+        commands
+            .spawn_bundle(GeometryBuilder::build_as(
+                &LineSegments(points.windows(2).map(|pair| (pair[0], pair[1])).collect()),
+                DrawMode::Stroke(StrokeMode {
+                    options: StrokeOptions::default().with_line_width(1.5 * cam_scale),
+                    color,
+                }),
+                Transform::from_xyz(0., 0., 3.),
+            ))
+            .insert(OverlayMarker);
+
+        if let Some(latest) = values.last() {
+            label.push(TextSection::new(
+                format!("\n{} {:+.2e}%", diagnostic.name, latest),
+                TextStyle {
+                    color,
+                    ..typography.body.clone()
+                },
+            ));
+        }
+    }
+
+    commands
+        .spawn_bundle(Text2dBundle {
+            text: Text::from_sections(label).with_alignment(TextAlignment::BOTTOM_LEFT),
+            transform: Transform {
+                translation: to_world(Vec2::new(0., GRAPH_SIZE.y + 4.)).extend(3.),
+                rotation: camera.rotation,
+                scale: Vec3::splat(cam_scale),
+            },
+            ..Default::default()
+        })
+        .insert(OverlayMarker);
+}
@@ -0,0 +1,285 @@
+use std::f32::consts::TAU;
+
+use bevy::prelude::*;
+use bevy::render::mesh::{Indices, PrimitiveTopology};
+use bevy::render::view::NoFrustumCulling;
+use bevy::sprite::{MaterialMesh2dBundle, Mesh2dHandle};
+
+use crate::bundles::ParticleFieldBundle;
+use crate::colliders::{Collider, PlacedCollider};
+use crate::components::Size;
+use crate::components::*;
+use crate::events::{CreateExplosionEvent, ShipDestroyedEvent};
+use crate::generator::SeededRng;
+use crate::physics::SimulationTime;
+use crate::resources::Destroyed;
+use crate::teams::TeamRegistry;
+
+/// Sprites are 50 pixels across at size 1, and particles are drawn the same way
+const PIXELS_PER_SIZE: f32 = 50.;
+
+/// How many fragments a destroyed ship breaks into
+const DEBRIS_COUNT: (f32, f32) = (12., 24.);
+/// How fast the fragments fly apart, on top of the ship's own velocity
+const DEBRIS_SPEED: (f32, f32) = (15., 60.);
+/// Simulated seconds that a fragment lasts before it has broken up into nothing
+const DEBRIS_LIFETIME: (f32, f32) = (60., 240.);
+
+/// Lots of small, massless bodies (asteroids, debris) stored side by side rather than as an
+/// entity each. They feel gravity but don't pull on anything, so the physics can step them all
+/// in one go after the real bodies, and they're all drawn as a single mesh.
+#[derive(Debug, Clone, Component)]
+pub struct ParticleField {
+    pub positions: Vec<Vec2>,
+    pub velocities: Vec<Vec2>,
+    pub sizes: Vec<f32>,
+    /// Simulated seconds until each particle decays, which is forever for asteroids
+    pub lifetimes: Vec<f32>,
+    pub color: Color,
+}
+
+impl Default for ParticleField {
+    fn default() -> Self {
+        Self {
+            positions: Vec::new(),
+            velocities: Vec::new(),
+            sizes: Vec::new(),
+            lifetimes: Vec::new(),
+            color: Color::rgb(0.6, 0.55, 0.5),
+        }
+    }
+}
+
+impl ParticleField {
+    pub fn len(&self) -> usize {
+        self.positions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.positions.is_empty()
+    }
+
+    /// Adds a particle that lasts forever
+    pub fn push(&mut self, position: Vec2, velocity: Vec2, size: f32) {
+        self.push_decaying(position, velocity, size, f32::INFINITY);
+    }
+
+    pub fn push_decaying(&mut self, position: Vec2, velocity: Vec2, size: f32, lifetime: f32) {
+        self.positions.push(position);
+        self.velocities.push(velocity);
+        self.sizes.push(size);
+        self.lifetimes.push(lifetime);
+    }
+
+    /// Removes a particle by moving the last one into its place, so it doesn't keep the order
+    pub fn swap_remove(&mut self, idx: usize) {
+        self.positions.swap_remove(idx);
+        self.velocities.swap_remove(idx);
+        self.sizes.swap_remove(idx);
+        self.lifetimes.swap_remove(idx);
+    }
+
+    fn radius(&self, idx: usize) -> f32 {
+        PIXELS_PER_SIZE * 0.5 * self.sizes[idx]
+    }
+}
+
+/// Gives every particle field a mesh to be drawn with
+pub fn attach_particle_meshes(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    fields: Query<(Entity, &ParticleField), Without<Mesh2dHandle>>,
+) {
+    for (entity, field) in fields.iter() {
+        commands
+            .entity(entity)
+            .insert_bundle(MaterialMesh2dBundle {
+                mesh: meshes
+                    .add(Mesh::new(PrimitiveTopology::TriangleList))
+                    .into(),
+                material: materials.add(ColorMaterial::from(field.color)),
+                transform: Transform::from_xyz(0., 0., 0.5),
+                ..Default::default()
+            })
+            // the particles move around, so the bounds worked out when the mesh was empty are
+            // no good for culling
+            .insert(NoFrustumCulling);
+    }
+}
+
+/// Rebuilds every field's mesh with a square for each particle
+pub fn update_particle_meshes(
+    mut meshes: ResMut<Assets<Mesh>>,
+    fields: Query<(&ParticleField, &Mesh2dHandle)>,
+) {
+    for (field, Mesh2dHandle(handle)) in fields.iter() {
+        let mesh = match meshes.get_mut(handle) {
+            Some(mesh) => mesh,
+            None => continue,
+        };
+
+        let mut positions = Vec::with_capacity(field.len() * 4);
+        let mut uvs = Vec::with_capacity(field.len() * 4);
+        let mut indices = Vec::with_capacity(field.len() * 6);
+        for idx in 0..field.len() {
+            let (pos, half) = (field.positions[idx], field.radius(idx));
+            let first = positions.len() as u32;
+            for (corner, uv) in [
+                (Vec2::new(-half, -half), [0., 1.]),
+                (Vec2::new(half, -half), [1., 1.]),
+                (Vec2::new(half, half), [1., 0.]),
+                (Vec2::new(-half, half), [0., 0.]),
+            ] {
+                positions.push((pos + corner).extend(0.).to_array());
+                uvs.push(uv);
+            }
+            indices.extend([first, first + 1, first + 2, first, first + 2, first + 3]);
+        }
+
+        mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, vec![[0., 0., 1.]; positions.len()]);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
+        mesh.set_indices(Some(Indices::U32(indices)));
+    }
+}
+
+/// Particles that run into a star or planet are absorbed, while ships and missiles that run into
+/// a particle are destroyed along with it
+pub fn collide_particles(
+    mut commands: Commands,
+    mut destroyed: ResMut<Destroyed>,
+    mut fields: Query<&mut ParticleField>,
+    gravity_sources: Query<(&Position, Option<&Velocity>, &Size, &Collider), With<GravitySource>>,
+    ships: Query<(Entity, &Position, &Velocity, &Team, &Size, &Collider), With<Ship>>,
+    missiles: Query<(Entity, &Position, &Velocity, &Size, &Collider), With<Missile>>,
+    mut explosion_event: EventWriter<CreateExplosionEvent>,
+    mut destroyed_event: EventWriter<ShipDestroyedEvent>,
+) {
+    // placed once up front, rather than for every particle
+    let source_hitboxes: Vec<PlacedCollider> = gravity_sources
+        .iter()
+        .map(|(Position(pos), vel, size, collider)| {
+            collider.placed(*pos, vel.map_or(Vec2::ZERO, |v| v.0), size)
+        })
+        .collect();
+    let missile_hitboxes: Vec<PlacedCollider> = missiles
+        .iter()
+        .map(|(_, Position(pos), Velocity(vel), size, collider)| collider.placed(*pos, *vel, size))
+        .collect();
+    let ship_hitboxes: Vec<PlacedCollider> = ships
+        .iter()
+        .map(|(_, Position(pos), Velocity(vel), _, size, collider)| {
+            collider.placed(*pos, *vel, size)
+        })
+        .collect();
+
+    for mut field in fields.iter_mut() {
+        // backwards, so that removing a particle doesn't skip the one moved into its place
+        'particles: for idx in (0..field.len()).rev() {
+            let particle = PlacedCollider::circle(field.positions[idx], field.radius(idx));
+
+            if source_hitboxes
+                .iter()
+                .any(|hitbox| hitbox.overlaps(&particle))
+            {
+                field.swap_remove(idx);
+                continue 'particles;
+            }
+
+            for ((missile, Position(missile_pos), Velocity(missile_vel), _, _), hitbox) in
+                missiles.iter().zip(&missile_hitboxes)
+            {
+                if !destroyed.0.contains(&missile) && hitbox.overlaps(&particle) {
+                    commands.entity(missile).despawn();
+                    destroyed.0.insert(missile);
+                    explosion_event.send(CreateExplosionEvent {
+                        position: Position(*missile_pos),
+                        velocity: Velocity(*missile_vel),
+                    });
+                    field.swap_remove(idx);
+                    continue 'particles;
+                }
+            }
+
+            for ((ship, Position(ship_pos), Velocity(ship_vel), team, _, _), hitbox) in
+                ships.iter().zip(&ship_hitboxes)
+            {
+                if !destroyed.0.contains(&ship) && hitbox.overlaps(&particle) {
+                    commands.entity(ship).despawn();
+                    destroyed.0.insert(ship);
+                    explosion_event.send(CreateExplosionEvent {
+                        position: Position(*ship_pos),
+                        velocity: Velocity(*ship_vel),
+                    });
+                    // flying into a rock (or what's left of a ship) is nobody's fault but your
+                    // own, so nobody gets the kill
+                    destroyed_event.send(ShipDestroyedEvent {
+                        ship,
+                        team: team.clone(),
+                        killer_team: Team(None),
+                        killer: None,
+                        position: Position(*ship_pos),
+                        velocity: Velocity(*ship_vel),
+                    });
+                    field.swap_remove(idx);
+                    continue 'particles;
+                }
+            }
+        }
+    }
+}
+
+/// Breaks every destroyed ship up into a cloud of fragments, which carry on along the ship's orbit
+/// while spreading out from where it was hit
+pub fn spawn_ship_debris(
+    mut commands: Commands,
+    sim_time: Res<SimulationTime>,
+    registry: Res<TeamRegistry>,
+    mut destroyed_events: EventReader<ShipDestroyedEvent>,
+) {
+    for ev in destroyed_events.iter() {
+        let mut rng = SeededRng::new(ev.ship.to_bits() ^ sim_time.elapsed.to_bits());
+        let mut debris = ParticleField {
+            color: registry.color(&ev.team) * 0.6,
+            ..Default::default()
+        };
+        for _ in 0..rng.range(DEBRIS_COUNT.0, DEBRIS_COUNT.1) as u32 {
+            let dir = Vec2::from(rng.range(0., TAU).sin_cos());
+            debris.push_decaying(
+                ev.position.0 + dir * rng.range(2., 8.),
+                ev.velocity.0 + dir * rng.range(DEBRIS_SPEED.0, DEBRIS_SPEED.1),
+                rng.range(0.04, 0.08),
+                rng.range(DEBRIS_LIFETIME.0, DEBRIS_LIFETIME.1),
+            );
+        }
+        commands
+            .spawn_bundle(ParticleFieldBundle { field: debris })
+            .insert(Debris);
+    }
+}
+
+/// Counts down the lifetimes of decaying particles, removing the ones that have run out (and any
+/// debris clouds with nothing left in them)
+pub fn decay_particles(
+    mut commands: Commands,
+    sim_time: Res<SimulationTime>,
+    mut fields: Query<(Entity, &mut ParticleField, Option<&Debris>)>,
+) {
+    for (entity, mut field, debris) in fields.iter_mut() {
+        if debris.is_some() && field.is_empty() {
+            commands.entity(entity).despawn_recursive();
+            continue;
+        }
+        if sim_time.delta <= 0. {
+            continue;
+        }
+
+        for idx in (0..field.len()).rev() {
+            field.lifetimes[idx] -= sim_time.delta;
+            if field.lifetimes[idx] <= 0. {
+                field.swap_remove(idx);
+            }
+        }
+    }
+}
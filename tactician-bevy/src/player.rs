@@ -0,0 +1,185 @@
+use std::collections::HashSet;
+
+use bevy::prelude::*;
+use bevy::ui::Size;
+
+use crate::components::*;
+use crate::controls::{Action, ActionState};
+use crate::events::ShipDestroyedEvent;
+use crate::generator::SpawnPoints;
+use crate::menu::{spawn_menu_button, MenuButton};
+use crate::misc::AppState;
+use crate::physics::SimulationTime;
+use crate::resources::Typography;
+use crate::teams::{MatchRules, TeamRegistry};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlayerStatus {
+    /// Waiting for our ship to show up (at the start of the match, or after respawning)
+    Spawning,
+    Alive,
+    /// Our ship was destroyed and we haven't decided what to do about it yet
+    Dead,
+    /// Watching the rest of the match without a ship
+    Spectating,
+}
+
+impl Default for PlayerStatus {
+    fn default() -> Self {
+        Self::Spawning
+    }
+}
+
+/// How the local player has done this match. Shown on the results screen.
+#[derive(Debug, Default)]
+pub struct PlayerStats {
+    pub team: Team,
+    pub kills: u32,
+    pub deaths: u32,
+    pub shots_fired: u32,
+    /// Seconds spent alive, over all lives
+    pub survival_time: f64,
+    /// Every ship we've flown this match. Missiles fired from one of them still count towards our
+    /// kills after it's gone.
+    ships: HashSet<Entity>,
+}
+
+impl PlayerStats {
+    /// Fraction of missiles fired that destroyed an enemy, if any were fired at all
+    pub fn accuracy(&self) -> Option<f32> {
+        (self.shots_fired > 0).then(|| self.kills as f32 / self.shots_fired as f32)
+    }
+}
+
+/// Root of the "you died" overlay
+#[derive(Debug, Clone, Copy, Component)]
+pub struct DeathMenu;
+
+pub fn reset_player(mut status: ResMut<PlayerStatus>, mut stats: ResMut<PlayerStats>) {
+    *status = PlayerStatus::Spawning;
+    *stats = PlayerStats::default();
+}
+
+pub fn toggle_pause(actions: Res<ActionState>, mut state: ResMut<State<AppState>>) {
+    if !actions.just_pressed(Action::Pause) {
+        return;
+    }
+
+    let current = state.current().clone();
+    let result = match current {
+        AppState::Game => state.push(AppState::Paused),
+        AppState::Paused => state.pop(),
+        _ => return,
+    };
+    if let Err(e) = result {
+        warn!("issue toggling pause??\n{}", e);
+    }
+}
+
+pub fn track_player_stats(
+    sim_time: Res<SimulationTime>,
+    registry: Res<TeamRegistry>,
+    mut stats: ResMut<PlayerStats>,
+    player: Query<(Entity, &Team), With<LocalPlayer>>,
+    mut destroyed_events: EventReader<ShipDestroyedEvent>,
+) {
+    if let Some((ship, team)) = player.iter().next() {
+        stats.survival_time += sim_time.delta as f64;
+        stats.ships.insert(ship);
+        if stats.team != *team {
+            stats.team = team.clone();
+        }
+    }
+
+    for ev in destroyed_events.iter() {
+        let ours = ev
+            .killer
+            .map_or(false, |killer| stats.ships.contains(&killer));
+        if ours && !registry.are_allied(&ev.team, &stats.team) {
+            stats.kills += 1;
+        }
+    }
+}
+
+/// Notices when our ship has been destroyed and offers to respawn or spectate
+pub fn detect_player_death(
+    mut commands: Commands,
+    typography: Res<Typography>,
+    rules: Res<MatchRules>,
+    spawn_points: Res<SpawnPoints>,
+    mut status: ResMut<PlayerStatus>,
+    mut stats: ResMut<PlayerStats>,
+    player: Query<(), With<LocalPlayer>>,
+) {
+    match *status {
+        PlayerStatus::Spawning if !player.is_empty() => *status = PlayerStatus::Alive,
+        PlayerStatus::Alive if player.is_empty() => {
+            *status = PlayerStatus::Dead;
+            stats.deaths += 1;
+
+            // there's nowhere to come back to if our team has no spawn point
+            let can_respawn =
+                rules.victory.allows_respawn() && spawn_points.for_team(&stats.team).is_some();
+            commands
+                .spawn_bundle(NodeBundle {
+                    style: Style {
+                        size: Size::new(Val::Percent(100.0), Val::Percent(40.0)),
+                        position_type: PositionType::Absolute,
+                        flex_direction: FlexDirection::ColumnReverse,
+                        justify_content: JustifyContent::SpaceEvenly,
+                        align_items: AlignItems::Center,
+                        ..Default::default()
+                    },
+                    color: Color::rgba(0.0, 0.0, 0.0, 0.5).into(),
+                    ..Default::default()
+                })
+                .insert(DeathMenu)
+                .with_children(|parent| {
+                    parent.spawn_bundle(TextBundle {
+                        text: Text::from_section(
+                            "Your ship was destroyed",
+                            typography.heading.clone(),
+                        ),
+                        ..Default::default()
+                    });
+                    if can_respawn {
+                        spawn_menu_button(parent, &typography, "Respawn", MenuButton::Respawn);
+                    }
+                    spawn_menu_button(parent, &typography, "Spectate", MenuButton::Spectate);
+                });
+        }
+        _ => {}
+    }
+}
+
+pub fn update_death_menu(
+    mut commands: Commands,
+    mut status: ResMut<PlayerStatus>,
+    stats: Res<PlayerStats>,
+    spawn_points: Res<SpawnPoints>,
+    buttons: Query<(&Interaction, &MenuButton), Changed<Interaction>>,
+    death_menus: Query<Entity, With<DeathMenu>>,
+) {
+    for (interaction, button) in buttons.iter() {
+        if *interaction != Interaction::Clicked {
+            continue;
+        }
+
+        match button {
+            MenuButton::Respawn => match spawn_points.for_team(&stats.team) {
+                Some(spawn) => {
+                    crate::spawn_player_ship(&mut commands, spawn);
+                    *status = PlayerStatus::Spawning;
+                }
+                // leave the menu up, so that spectating is still on offer
+                None => continue,
+            },
+            MenuButton::Spectate => *status = PlayerStatus::Spectating,
+            _ => continue,
+        }
+
+        for menu in death_menus.iter() {
+            commands.entity(menu).despawn_recursive();
+        }
+    }
+}
@@ -1,9 +1,14 @@
 use bevy::prelude::*;
+use std::collections::HashSet;
 
 pub struct Typography {
     pub default_font: Handle<Font>,
     pub heading: TextStyle,
     pub body: TextStyle,
+    /// Readouts on the in-game HUD
+    pub hud: TextStyle,
+    /// What each HUD readout is, next to its value
+    pub hud_label: TextStyle,
 }
 
 impl Typography {
@@ -28,6 +33,16 @@ impl FromWorld for Typography {
                 font_size: 20.0,
                 color: Color::WHITE,
             },
+            hud: TextStyle {
+                font: default_font.clone(),
+                font_size: 16.0,
+                color: Color::WHITE,
+            },
+            hud_label: TextStyle {
+                font: default_font.clone(),
+                font_size: 16.0,
+                color: Color::rgb(0.6, 0.6, 0.6),
+            },
             default_font,
         }
     }
@@ -59,3 +74,8 @@ impl FromWorld for Materials {
         }
     }
 }
+
+/// Bodies the hit tests have already destroyed this frame. They're only despawned once every
+/// substep has run, so until then the other hit tests have to skip them or they'd die twice.
+#[derive(Debug, Default)]
+pub struct Destroyed(pub HashSet<Entity>);
@@ -0,0 +1,155 @@
+use std::num::NonZeroU32;
+
+use bevy::prelude::*;
+use serde::Deserialize;
+
+use crate::ai::Difficulty;
+use crate::components::*;
+use crate::generator::StarSystem;
+use crate::teams::VictoryCondition;
+
+/// Where scenarios are looked for. Any `.ron` file in here shows up in the match setup screen, in
+/// order of file name.
+#[cfg(not(target_arch = "wasm32"))]
+const SCENARIO_DIR: &str = "assets/scenarios";
+
+/// The scenarios that come with the game. The web build can't list the assets directory, so it
+/// gets these compiled in instead (as does a native build that can't find it).
+const BUNDLED_SCENARIOS: [&str; 4] = [
+    include_str!("../assets/scenarios/1-classic.ron"),
+    include_str!("../assets/scenarios/2-binary.ron"),
+    include_str!("../assets/scenarios/3-triple.ron"),
+    include_str!("../assets/scenarios/4-crowded.ron"),
+];
+
+/// Most teams that a match can be set up with
+pub const MAX_TEAMS: u32 = 6;
+
+/// A star system to fight in, along with what to call it
+#[derive(Debug, Clone, Deserialize)]
+pub struct Scenario {
+    pub name: String,
+    pub description: String,
+    pub system: StarSystem,
+}
+
+/// Every scenario that can be picked in the match setup screen
+#[derive(Debug, Clone, Default)]
+pub struct Scenarios(pub Vec<Scenario>);
+
+impl Scenarios {
+    pub fn load() -> Self {
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            match Self::load_dir(SCENARIO_DIR) {
+                Ok(scenarios) if !scenarios.0.is_empty() => return scenarios,
+                Ok(_) => warn!("no scenarios in {}, using the bundled ones", SCENARIO_DIR),
+                Err(e) => warn!(
+                    "unable to list {}, using the bundled scenarios: {}",
+                    SCENARIO_DIR, e
+                ),
+            }
+        }
+
+        Self(
+            BUNDLED_SCENARIOS
+                .iter()
+                .filter_map(|contents| match ron::from_str(contents) {
+                    Ok(scenario) => Some(scenario),
+                    Err(e) => {
+                        warn!("ignoring malformed bundled scenario: {}", e);
+                        None
+                    }
+                })
+                .collect(),
+        )
+    }
+
+    /// Reads every scenario in `dir`, skipping (and warning about) any that are malformed
+    #[cfg(not(target_arch = "wasm32"))]
+    fn load_dir(dir: &str) -> std::io::Result<Self> {
+        let mut paths: Vec<_> = std::fs::read_dir(dir)?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().map_or(false, |ext| ext == "ron"))
+            .collect();
+        paths.sort();
+
+        Ok(Self(
+            paths
+                .iter()
+                .filter_map(|path| {
+                    let scenario = std::fs::read_to_string(path)
+                        .map_err(|e| e.to_string())
+                        .and_then(|contents| ron::from_str(&contents).map_err(|e| e.to_string()));
+                    match scenario {
+                        Ok(scenario) => Some(scenario),
+                        Err(e) => {
+                            warn!("ignoring scenario {}: {}", path.display(), e);
+                            None
+                        }
+                    }
+                })
+                .collect(),
+        ))
+    }
+}
+
+/// How the next match is going to be played, as picked in the match setup screen
+#[derive(Debug, Clone, PartialEq)]
+pub struct MatchSetup {
+    /// Which of the [`Scenarios`] to play
+    pub scenario: usize,
+    pub teams: u32,
+    /// How many of the enemy ships are flown by the computer. The rest just drift along.
+    pub ai_opponents: u32,
+    pub difficulty: Difficulty,
+    /// Missiles each ship gets, or `None` for as many as it likes
+    pub missile_limit: Option<u32>,
+    /// Simulated seconds before a missile falls apart, or `None` to keep them forever
+    pub missile_lifespan: Option<f64>,
+    /// Whether missiles can hit ships on their own side
+    pub friendly_fire: bool,
+    pub victory: VictoryCondition,
+    /// Splits the teams into two allied sides (odd and even numbered teams), rather than every
+    /// team fighting for itself
+    pub two_sides: bool,
+}
+
+impl Default for MatchSetup {
+    fn default() -> Self {
+        Self {
+            scenario: 0,
+            teams: 2,
+            ai_opponents: 1,
+            difficulty: Difficulty::default(),
+            missile_limit: None,
+            missile_lifespan: None,
+            friendly_fire: false,
+            victory: VictoryCondition::LastTeamStanding,
+            two_sides: false,
+        }
+    }
+}
+
+impl MatchSetup {
+    pub fn teams(&self) -> Vec<Team> {
+        (1..=self.teams)
+            .map(|id| Team(NonZeroU32::new(id)))
+            .collect()
+    }
+
+    /// Every pair of teams that fight on the same side
+    pub fn alliances(&self) -> Vec<(NonZeroU32, NonZeroU32)> {
+        if !self.two_sides {
+            return Vec::new();
+        }
+        let ids = || (1..=self.teams).filter_map(NonZeroU32::new);
+        ids()
+            .flat_map(|a| {
+                ids()
+                    .filter(move |b| a < *b && a.get() % 2 == b.get() % 2)
+                    .map(move |b| (a, b))
+            })
+            .collect()
+    }
+}
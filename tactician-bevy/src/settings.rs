@@ -0,0 +1,158 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::camera::{ZoomLimits, MAX_SCALE, MIN_SCALE};
+use crate::menu::{MAX_ZOOMS, MIN_ZOOMS, TICK_RATES, TRAIL_LENGTHS};
+use crate::physics::{TickRate, TimeWarp};
+use crate::teams::TeamRegistry;
+use crate::trails::{TrailLength, DEFAULT_TRAIL_HISTORY};
+
+/// Options picked in the settings screen, which last from one run to the next. Each one is handed
+/// on to whatever resource it affects by [`apply_settings`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    /// Simulated seconds of history that planets' snail trails keep (see [`TrailLength`])
+    pub trail_seconds: f32,
+    /// Closest the camera can zoom in, in world units per pixel
+    pub min_zoom: f32,
+    /// Furthest the camera can zoom out, in world units per pixel
+    pub max_zoom: f32,
+    /// Fastest that time can be warped
+    pub max_time_warp: f32,
+    /// How many times more substeps than usual the physics takes, which makes it more accurate at
+    /// the cost of speed
+    pub physics_tick_rate: f32,
+    /// Team colors that can be told apart with any kind of color blindness
+    pub colorblind_palette: bool,
+    /// Master volume, between 0 and 1
+    pub volume: f32,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            trail_seconds: DEFAULT_TRAIL_HISTORY,
+            min_zoom: MIN_SCALE,
+            max_zoom: MAX_SCALE,
+            max_time_warp: *TimeWarp::LEVELS.last().unwrap(),
+            physics_tick_rate: 1.,
+            colorblind_palette: false,
+            volume: 0.8,
+        }
+    }
+}
+
+impl Settings {
+    pub const CONFIG_PATH: &'static str = "settings.ron";
+
+    /// Reads the settings saved last time, falling back to the defaults if there aren't any.
+    /// Settings that didn't exist yet when they were saved get their defaults.
+    pub fn load() -> Self {
+        match read_config(Self::CONFIG_PATH) {
+            Ok(Some(contents)) => match ron::from_str(&contents) {
+                Ok(settings) => return settings,
+                Err(e) => warn!("ignoring malformed {}: {}", Self::CONFIG_PATH, e),
+            },
+            Ok(None) => {}
+            Err(e) => warn!("unable to read {}: {}", Self::CONFIG_PATH, e),
+        }
+        Self::default()
+    }
+
+    /// Master volume, kept between 0 and 1 in case the file was edited by hand
+    pub fn clamped_volume(&self) -> f32 {
+        self.volume.clamp(0., 1.)
+    }
+
+    pub fn save(&self) {
+        let result = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())
+            .map_err(|e| e.to_string())
+            .and_then(|contents| write_config(Self::CONFIG_PATH, &contents));
+        if let Err(e) = result {
+            warn!("unable to save {}: {}", Self::CONFIG_PATH, e);
+        }
+    }
+}
+
+/// Reads a config file saved by [`write_config`], or `None` if it hasn't been saved yet. On the web
+/// these live in the browser's local storage rather than on disk.
+pub fn read_config(name: &str) -> Result<Option<String>, String> {
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        match std::fs::read_to_string(name) {
+            Ok(contents) => Ok(Some(contents)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+    #[cfg(target_arch = "wasm32")]
+    {
+        local_storage()?
+            .get_item(name)
+            .map_err(|e| format!("{:?}", e))
+    }
+}
+
+pub fn write_config(name: &str, contents: &str) -> Result<(), String> {
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        std::fs::write(name, contents).map_err(|e| e.to_string())
+    }
+    #[cfg(target_arch = "wasm32")]
+    {
+        local_storage()?
+            .set_item(name, contents)
+            .map_err(|e| format!("{:?}", e))
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+fn local_storage() -> Result<web_sys::Storage, String> {
+    web_sys::window()
+        .ok_or_else(|| "no browser window".to_string())?
+        .local_storage()
+        .map_err(|e| format!("{:?}", e))?
+        .ok_or_else(|| "local storage is unavailable".to_string())
+}
+
+pub struct SettingsPlugin;
+
+impl Plugin for SettingsPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(Settings::load())
+            .add_system(apply_settings);
+    }
+}
+
+/// Hands the settings on to the resources they affect, whenever they change
+pub fn apply_settings(
+    settings: Res<Settings>,
+    mut zoom: ResMut<ZoomLimits>,
+    mut trail_length: ResMut<TrailLength>,
+    mut warp: ResMut<TimeWarp>,
+    mut tick_rate: ResMut<TickRate>,
+    mut registry: ResMut<TeamRegistry>,
+) {
+    if !settings.is_changed() {
+        return;
+    }
+    // the file might have been edited by hand, so keep everything to what the settings screen
+    // offers. The zoom ranges don't overlap, so the limits can't end up the wrong way around.
+    *zoom = ZoomLimits {
+        min: within(&MIN_ZOOMS, settings.min_zoom),
+        max: within(&MAX_ZOOMS, settings.max_zoom),
+    };
+    trail_length.0 = within(&TRAIL_LENGTHS, settings.trail_seconds);
+    warp.max_factor = settings
+        .max_time_warp
+        .clamp(1., *TimeWarp::LEVELS.last().unwrap());
+    warp.factor = warp.factor.min(warp.max_factor);
+    tick_rate.0 = within(&TICK_RATES, settings.physics_tick_rate);
+    registry.use_palette(settings.colorblind_palette);
+}
+
+/// `value`, moved into the range between the first and last of `choices`
+fn within(choices: &[f32], value: f32) -> f32 {
+    value.clamp(choices[0], choices[choices.len() - 1])
+}
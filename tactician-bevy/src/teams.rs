@@ -0,0 +1,383 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::num::NonZeroU32;
+
+use bevy::prelude::*;
+
+use crate::components::*;
+use crate::events::ShipDestroyedEvent;
+use crate::physics::SimulationTime;
+
+#[derive(Debug, Clone)]
+pub struct TeamInfo {
+    pub name: String,
+    pub color: Color,
+}
+
+/// Names, colors and alliances of every team taking part in the match
+#[derive(Debug, Clone)]
+pub struct TeamRegistry {
+    teams: BTreeMap<NonZeroU32, TeamInfo>,
+    /// Pairs of allied teams, smallest id first
+    alliances: BTreeSet<(NonZeroU32, NonZeroU32)>,
+    /// Whether the palette's colors come from [`TeamRegistry::COLORBLIND_PALETTE`]
+    colorblind: bool,
+}
+
+impl Default for TeamRegistry {
+    fn default() -> Self {
+        let mut registry = TeamRegistry {
+            teams: BTreeMap::new(),
+            alliances: BTreeSet::new(),
+            colorblind: false,
+        };
+        for (id, (name, color)) in (1..).zip(Self::PALETTE) {
+            registry.register(NonZeroU32::new(id).unwrap(), name, color);
+        }
+        registry
+    }
+}
+
+impl TeamRegistry {
+    /// Used for the default teams, and for any team that shows up without being registered
+    const PALETTE: [(&'static str, Color); 6] = [
+        ("Blue", Color::rgb(0.35, 0.6, 1.0)),
+        ("Red", Color::rgb(1.0, 0.35, 0.3)),
+        ("Green", Color::rgb(0.4, 0.9, 0.4)),
+        ("Yellow", Color::rgb(1.0, 0.85, 0.3)),
+        ("Purple", Color::rgb(0.75, 0.45, 1.0)),
+        ("Orange", Color::rgb(1.0, 0.6, 0.2)),
+    ];
+
+    /// Stand-ins for the colors in [`TeamRegistry::PALETTE`] that can be told apart with any kind
+    /// of color blindness (Okabe and Ito's palette, lightened a little to show up against space)
+    const COLORBLIND_PALETTE: [Color; 6] = [
+        Color::rgb(0.34, 0.71, 0.91),
+        Color::rgb(0.9, 0.4, 0.1),
+        Color::rgb(0.1, 0.7, 0.5),
+        Color::rgb(0.94, 0.89, 0.26),
+        Color::rgb(0.8, 0.47, 0.65),
+        Color::rgb(0.9, 0.62, 0.0),
+    ];
+
+    /// The palette color for the team with this id
+    fn palette_color(&self, id: NonZeroU32) -> Color {
+        let idx = (id.get() as usize - 1) % Self::PALETTE.len();
+        if self.colorblind {
+            Self::COLORBLIND_PALETTE[idx]
+        } else {
+            Self::PALETTE[idx].1
+        }
+    }
+
+    /// Switches between the usual palette and the colorblind one. Teams that were given a color of
+    /// their own keep it.
+    pub fn use_palette(&mut self, colorblind: bool) {
+        if colorblind == self.colorblind {
+            return;
+        }
+        let recolored: Vec<NonZeroU32> = self
+            .teams
+            .iter()
+            .filter(|(id, info)| info.color == self.palette_color(**id))
+            .map(|(id, _)| *id)
+            .collect();
+        self.colorblind = colorblind;
+        for id in recolored {
+            let color = self.palette_color(id);
+            if let Some(info) = self.teams.get_mut(&id) {
+                info.color = color;
+            }
+        }
+    }
+
+    pub fn register(&mut self, id: NonZeroU32, name: impl Into<String>, color: Color) {
+        self.teams.insert(
+            id,
+            TeamInfo {
+                name: name.into(),
+                color,
+            },
+        );
+    }
+
+    pub fn ally(&mut self, a: NonZeroU32, b: NonZeroU32) {
+        self.alliances.insert((a.min(b), a.max(b)));
+    }
+
+    /// Breaks up every alliance, ready for the next match to set up its own
+    pub fn clear_alliances(&mut self) {
+        self.alliances.clear();
+    }
+
+    /// Whether `a` and `b` are on the same side. A team is always allied with itself, and ships
+    /// without a team are only allied with each other.
+    pub fn are_allied(&self, a: &Team, b: &Team) -> bool {
+        match (a.0, b.0) {
+            (Some(a), Some(b)) => a == b || self.alliances.contains(&(a.min(b), a.max(b))),
+            (a, b) => a == b,
+        }
+    }
+
+    pub fn color(&self, team: &Team) -> Color {
+        match team.0 {
+            Some(id) => self
+                .teams
+                .get(&id)
+                .map_or_else(|| self.palette_color(id), |info| info.color),
+            None => Color::WHITE,
+        }
+    }
+
+    pub fn name(&self, team: &Team) -> String {
+        match team.0 {
+            Some(id) => self
+                .teams
+                .get(&id)
+                .map_or_else(|| format!("Team {}", id), |info| info.name.clone()),
+            None => "Unaffiliated".to_string(),
+        }
+    }
+
+    /// Groups `teams` into sides, where every team on a side is allied with every other one.
+    /// Used to work out whether a match is over.
+    fn sides(&self, teams: &BTreeSet<Option<NonZeroU32>>) -> Vec<Vec<Option<NonZeroU32>>> {
+        let mut sides: Vec<Vec<Option<NonZeroU32>>> = Vec::new();
+        for team in teams {
+            let side = sides.iter_mut().find(|side| {
+                side.iter()
+                    .all(|other| self.are_allied(&Team(*team), &Team(*other)))
+            });
+            match side {
+                Some(side) => side.push(*team),
+                None => sides.push(vec![*team]),
+            }
+        }
+        sides
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VictoryCondition {
+    /// The match ends once only one side has any ships left. Nobody respawns.
+    LastTeamStanding,
+    /// Every kill scores a point, and whoever has the most points after `duration` seconds wins
+    TimedScore { duration: f64 },
+    /// Whoever keeps ships within `radius` of the [`CaptureTarget`] planet, without any enemies
+    /// around, for a total of `hold_time` seconds wins
+    CapturePlanet { radius: f32, hold_time: f64 },
+}
+
+impl VictoryCondition {
+    /// The ones that can be picked in the match setup screen
+    pub const CHOICES: [VictoryCondition; 3] = [
+        VictoryCondition::LastTeamStanding,
+        VictoryCondition::TimedScore { duration: 300. },
+        VictoryCondition::CapturePlanet {
+            radius: 100.,
+            hold_time: 60.,
+        },
+    ];
+
+    /// Whether destroyed ships get to come back. Nobody does in a last team standing match, or it
+    /// would never end.
+    pub fn allows_respawn(&self) -> bool {
+        *self != VictoryCondition::LastTeamStanding
+    }
+
+    pub fn describe(&self) -> String {
+        match self {
+            VictoryCondition::LastTeamStanding => "last team standing".to_string(),
+            VictoryCondition::TimedScore { duration } => {
+                format!("most kills in {:.0}s", duration)
+            }
+            VictoryCondition::CapturePlanet { hold_time, .. } => {
+                format!("hold the planet for {:.0}s", hold_time)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MatchRules {
+    pub victory: VictoryCondition,
+    /// Missiles each ship gets, or `None` for as many as it likes
+    pub missile_limit: Option<u32>,
+    /// Simulated seconds before a missile falls apart, or `None` to keep them forever
+    pub missile_lifespan: Option<f64>,
+    /// Whether missiles can hit ships on their own side (once they're clear of the ship that
+    /// fired them)
+    pub friendly_fire: bool,
+}
+
+impl Default for MatchRules {
+    fn default() -> Self {
+        Self {
+            victory: VictoryCondition::LastTeamStanding,
+            missile_limit: None,
+            missile_lifespan: None,
+            friendly_fire: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MatchOutcome {
+    /// `None` if the match was a draw
+    pub winner: Option<Team>,
+    pub reason: String,
+}
+
+/// Progress of the match that is currently being played
+#[derive(Debug, Default)]
+pub struct MatchState {
+    /// Seconds of simulated play so far
+    pub elapsed: f64,
+    pub scores: HashMap<Option<NonZeroU32>, u32>,
+    pub capture_progress: HashMap<Option<NonZeroU32>, f64>,
+    /// Most sides that have had ships alive at once. Stops a match that only ever had one side
+    /// from being "won" straight away.
+    most_sides_seen: usize,
+    pub outcome: Option<MatchOutcome>,
+}
+
+/// The planet that has to be held for [`VictoryCondition::CapturePlanet`]
+#[derive(Debug, Default, Clone, Copy, Component)]
+pub struct CaptureTarget;
+
+pub struct TeamsPlugin;
+
+impl Plugin for TeamsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<TeamRegistry>()
+            .init_resource::<MatchRules>()
+            .init_resource::<MatchState>();
+    }
+}
+
+/// Clears out the results of the previous match
+pub fn reset_match(mut match_state: ResMut<MatchState>) {
+    *match_state = MatchState::default();
+}
+
+pub fn score_kills(
+    mut match_state: ResMut<MatchState>,
+    registry: Res<TeamRegistry>,
+    mut destroyed_events: EventReader<ShipDestroyedEvent>,
+) {
+    for ev in destroyed_events.iter() {
+        // no points for shooting your friends, or for the ships that crash by themselves
+        if ev.killer_team.0.is_some() && !registry.are_allied(&ev.killer_team, &ev.team) {
+            *match_state.scores.entry(ev.killer_team.0).or_default() += 1;
+        }
+    }
+}
+
+pub fn pick_capture_target(
+    mut commands: Commands,
+    rules: Res<MatchRules>,
+    targets: Query<(), With<CaptureTarget>>,
+    planets: Query<(Entity, &Mass), With<Planet>>,
+) {
+    if !matches!(rules.victory, VictoryCondition::CapturePlanet { .. }) || !targets.is_empty() {
+        return;
+    }
+
+    let heaviest = planets
+        .iter()
+        .max_by(|(_, Mass(a)), (_, Mass(b))| a.total_cmp(b));
+    if let Some((planet, _)) = heaviest {
+        commands.entity(planet).insert(CaptureTarget);
+    }
+}
+
+pub fn check_victory(
+    sim_time: Res<SimulationTime>,
+    rules: Res<MatchRules>,
+    registry: Res<TeamRegistry>,
+    mut match_state: ResMut<MatchState>,
+    ships: Query<(&Position, &Team), With<Ship>>,
+    target: Query<&Position, With<CaptureTarget>>,
+) {
+    if match_state.outcome.is_some() {
+        return;
+    }
+    match_state.elapsed += sim_time.delta as f64;
+
+    let alive: BTreeSet<Option<NonZeroU32>> = ships.iter().map(|(_, Team(id))| *id).collect();
+    let sides = registry.sides(&alive);
+    match_state.most_sides_seen = match_state.most_sides_seen.max(sides.len());
+
+    match rules.victory {
+        VictoryCondition::LastTeamStanding => {
+            if match_state.most_sides_seen > 1 && sides.len() <= 1 {
+                match_state.outcome = Some(match sides.first() {
+                    Some(side) => {
+                        let winner = Team(side[0]);
+                        MatchOutcome {
+                            reason: format!("{} is the last team standing", registry.name(&winner)),
+                            winner: Some(winner),
+                        }
+                    }
+                    None => MatchOutcome {
+                        winner: None,
+                        reason: "Nobody survived".to_string(),
+                    },
+                });
+            }
+        }
+        VictoryCondition::TimedScore { duration } => {
+            if match_state.elapsed >= duration {
+                let best = match_state.scores.values().copied().max().unwrap_or(0);
+                let leaders: Vec<_> = match_state
+                    .scores
+                    .iter()
+                    .filter(|(_, score)| **score == best)
+                    .map(|(team, _)| *team)
+                    .collect();
+                match_state.outcome = Some(match leaders.as_slice() {
+                    [leader] => {
+                        let winner = Team(*leader);
+                        MatchOutcome {
+                            reason: format!(
+                                "{} scored the most kills ({})",
+                                registry.name(&winner),
+                                best
+                            ),
+                            winner: Some(winner),
+                        }
+                    }
+                    _ => MatchOutcome {
+                        winner: None,
+                        reason: "Time ran out with the scores tied".to_string(),
+                    },
+                });
+            }
+        }
+        VictoryCondition::CapturePlanet { radius, hold_time } => {
+            let Position(target_pos) = match target.iter().next() {
+                Some(target_pos) => target_pos,
+                None => return,
+            };
+
+            let nearby: BTreeSet<Option<NonZeroU32>> = ships
+                .iter()
+                .filter(|(Position(pos), _)| pos.distance(*target_pos) < radius)
+                .map(|(_, Team(id))| *id)
+                .collect();
+
+            // contested planets don't make progress for anyone
+            if let [side] = registry.sides(&nearby).as_slice() {
+                let holder = side[0];
+                let progress = match_state.capture_progress.entry(holder).or_default();
+                *progress += sim_time.delta as f64;
+                if *progress >= hold_time {
+                    let winner = Team(holder);
+                    match_state.outcome = Some(MatchOutcome {
+                        reason: format!("{} captured the planet", registry.name(&winner)),
+                        winner: Some(winner),
+                    });
+                }
+            }
+        }
+    }
+}
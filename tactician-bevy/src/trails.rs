@@ -0,0 +1,665 @@
+use std::collections::VecDeque;
+
+use bevy::prelude::*;
+use bevy_prototype_lyon::prelude::{
+    tess::path::path::Builder, DrawMode, Geometry, GeometryBuilder, Path, ShapePath, StrokeMode,
+    StrokeOptions,
+};
+use lyon_geom::euclid::default::Point2D;
+
+use crate::camera::{CameraController, CameraFrame};
+use crate::components::*;
+use crate::controls::{Action, ActionState};
+use crate::physics::SimulationTime;
+use crate::teams::TeamRegistry;
+
+/// Each trail is drawn in this many sections, split up by age. A line can only be stroked in one
+/// color, so this is how trails fade from one color to another.
+const TRAIL_SECTIONS: usize = 8;
+/// Sections start at fixed times, so there's one more of them on screen while the oldest runs out
+const TRAIL_RENDERERS: usize = TRAIL_SECTIONS + 1;
+const ALL_STALE: u32 = (1 << TRAIL_RENDERERS) - 1;
+
+/// Simulated seconds of history that planets' trails keep, unless the settings say otherwise
+pub const DEFAULT_TRAIL_HISTORY: f32 = 120.;
+
+#[derive(Debug, Clone, Copy)]
+pub struct TrailPoint {
+    pub position: Vec2,
+    /// Simulated seconds since startup when the point was recorded (see [`SimulationTime`])
+    pub time: f64,
+    /// How far along the trail the point is, from wherever the body started. Dashes are measured
+    /// along this so that they stay put as the trail grows.
+    pub distance: f32,
+}
+
+/// Where a body has been recently. The trail is drawn by separate entities (see
+/// [`SnailTrailEntityMarker`]), one per section, which are kept around and only rebuilt when the
+/// points in their section change.
+#[derive(Debug, Clone, Component)]
+pub struct SnailTrail {
+    /// Oldest first. Points are dropped once they're more than `history` seconds old.
+    pub points: VecDeque<TrailPoint>,
+    /// Simulated seconds of history to keep
+    pub history: f32,
+    /// Seconds of history this trail keeps at the default [`TrailLength`]
+    base_history: f32,
+    /// Simulated seconds between points
+    pub sample_interval: f32,
+    /// Points closer than this to the previous one are skipped, so that slow bodies don't fill
+    /// their trail with points on top of each other
+    pub min_distance: f32,
+    since_sample: f32,
+    /// Entities drawing each section of this trail, once they have been spawned. Section `n` is
+    /// drawn by the renderer `n` modulo [`TRAIL_RENDERERS`], so they take turns being the newest.
+    renderers: Vec<Entity>,
+    /// Renderers whose section has changed since it was last drawn, one bit each
+    stale: u32,
+    /// The newest section, as of the last point recorded. Colors go by how old each section is, so
+    /// they all need redrawing when a new one starts.
+    newest_section: i64,
+}
+
+impl Default for SnailTrail {
+    fn default() -> Self {
+        Self::with_history(DEFAULT_TRAIL_HISTORY)
+    }
+}
+
+impl SnailTrail {
+    pub fn with_history(history: f32) -> Self {
+        Self {
+            points: VecDeque::new(),
+            history,
+            base_history: history,
+            sample_interval: 1. / 15.,
+            min_distance: 2.,
+            since_sample: f32::INFINITY,
+            renderers: Vec::new(),
+            stale: ALL_STALE,
+            newest_section: 0,
+        }
+    }
+
+    /// Changes how much history the trail keeps. The sections are measured in fractions of it,
+    /// so they all need redrawing.
+    fn set_history(&mut self, history: f32) {
+        if self.history != history {
+            self.history = history;
+            self.stale = ALL_STALE;
+        }
+    }
+
+    /// Forgets the points that have got too old, then adds `point` to the trail if it's time for
+    /// a new one and it's far enough from the last. Returns whether anything changed.
+    pub fn record(&mut self, point: Vec2, now: f64, dt: f32) -> bool {
+        let newest = self.section_of(now);
+        if newest != self.newest_section {
+            self.newest_section = newest;
+            self.stale = ALL_STALE;
+        }
+
+        let mut changed = false;
+        while let Some(oldest) = self.points.front().copied() {
+            if now - oldest.time <= self.history as f64 {
+                break;
+            }
+            self.points.pop_front();
+            self.mark_stale(self.section_of(oldest.time));
+            changed = true;
+        }
+
+        self.since_sample += dt;
+        if self.since_sample < self.sample_interval {
+            return changed;
+        }
+        let distance = match self.points.back() {
+            Some(last) => {
+                let step = last.position.distance(point);
+                if step < self.min_distance {
+                    return changed;
+                }
+                last.distance + step
+            }
+            None => 0.,
+        };
+
+        // the previous section ends with the new point too if it's the first of its own
+        if let Some(last) = self.points.back().copied() {
+            self.mark_stale(self.section_of(last.time));
+        }
+        self.mark_stale(newest);
+        self.since_sample = 0.;
+        self.points.push_back(TrailPoint {
+            position: point,
+            time: now,
+            distance,
+        });
+        true
+    }
+
+    /// Where the body was at `time`, going by its trail (and its `current` position at `now`).
+    /// `None` if that's further back than the trail remembers.
+    pub fn position_at(&self, time: f64, now: f64, current: Vec2) -> Option<Vec2> {
+        if now - time > self.history as f64 {
+            return None;
+        }
+        let next = self.points.partition_point(|point| point.time <= time);
+        let (from, to) = match (next.checked_sub(1), self.points.get(next)) {
+            (Some(prev), Some(next)) => (self.points[prev], *next),
+            (Some(prev), None) => (
+                self.points[prev],
+                TrailPoint {
+                    position: current,
+                    time: now,
+                    distance: 0.,
+                },
+            ),
+            // from before the trail started, which is only ever because the body hadn't moved
+            (None, Some(next)) => return Some(next.position),
+            (None, None) => return Some(current),
+        };
+
+        let t = if to.time > from.time {
+            ((time - from.time) / (to.time - from.time)) as f32
+        } else {
+            1.
+        };
+        Some(from.position.lerp(to.position, t.clamp(0., 1.)))
+    }
+
+    /// Which section something recorded at `time` falls in. Sections are [`TRAIL_SECTIONS`]ths of
+    /// the history long and start at fixed times, so points stay in the same one as they age.
+    fn section_of(&self, time: f64) -> i64 {
+        (time * TRAIL_SECTIONS as f64 / self.history as f64).floor() as i64
+    }
+
+    /// The section drawn by `renderer`, which is one of the last [`TRAIL_RENDERERS`]
+    fn section_drawn_by(&self, renderer: usize) -> i64 {
+        let newest = self.newest_section;
+        newest - (newest - renderer as i64).rem_euclid(TRAIL_RENDERERS as i64)
+    }
+
+    fn mark_stale(&mut self, section: i64) {
+        self.stale |= 1 << section.rem_euclid(TRAIL_RENDERERS as i64);
+    }
+
+    /// The points of one section, plus the first point of the next one so that the sections join
+    /// up. The points are in order, so the section is found without going through all of them.
+    fn section(&self, section: i64) -> Vec<TrailPoint> {
+        let start = self
+            .points
+            .partition_point(|point| self.section_of(point.time) < section);
+        let end = self
+            .points
+            .partition_point(|point| self.section_of(point.time) <= section);
+        if start == end {
+            return Vec::new();
+        }
+        self.points
+            .range(start..(end + 1).min(self.points.len()))
+            .copied()
+            .collect()
+    }
+}
+
+/// Simulated seconds of history that planets' trails keep. Other trails keep the same fraction of
+/// it as they do by default, so ships' trails are always half as long.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TrailLength(pub f32);
+
+impl Default for TrailLength {
+    fn default() -> Self {
+        Self(DEFAULT_TRAIL_HISTORY)
+    }
+}
+
+/// Which frame every trail is drawn in. Trails are always recorded where the bodies actually were,
+/// and moved into the frame whenever they're drawn, so this can be changed at any time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrailFrame {
+    /// Where the bodies actually went
+    Inertial,
+    /// Relative to whatever each body is orbiting, so that a moon's trail goes around its planet
+    /// rather than looping along behind it
+    Parent,
+    /// Relative to whatever the camera is following, which makes intercepts easy to read
+    CameraTarget,
+    /// Relative to a planet and turning with it around its star, like
+    /// [`CameraFrame::Rotating`]. That's the planet the camera is in the frame of or following,
+    /// or otherwise the heaviest one.
+    Rotating,
+}
+
+impl Default for TrailFrame {
+    fn default() -> Self {
+        Self::Inertial
+    }
+}
+
+impl TrailFrame {
+    fn next(self) -> Self {
+        match self {
+            Self::Inertial => Self::Parent,
+            Self::Parent => Self::CameraTarget,
+            Self::CameraTarget => Self::Rotating,
+            Self::Rotating => Self::Inertial,
+        }
+    }
+}
+
+/// What a particular trail is drawn relative to, going by the [`TrailFrame`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Reference {
+    Body(Entity),
+    /// Centered on `secondary` and turning with it around `primary`
+    Rotating {
+        primary: Entity,
+        secondary: Entity,
+    },
+}
+
+/// How a body's trail is drawn. Each bundle kind comes with its own, so that missiles, ships and
+/// planets can be told apart at a glance.
+#[derive(Debug, Clone, Component)]
+pub struct TrailStyle {
+    /// Color of the trail right behind the body
+    pub newest_color: Color,
+    /// Color that the trail shades into as it gets older
+    pub oldest_color: Color,
+    pub width: f32,
+    /// Lengths of each dash and the gap after it, or `None` for a solid line
+    pub dashes: Option<(f32, f32)>,
+    /// Seconds at the old end of the trail over which it fades away completely
+    pub fade_out: f32,
+    /// Whether the colors are multiplied by the body's team color
+    pub team_tinted: bool,
+}
+
+impl Default for TrailStyle {
+    fn default() -> Self {
+        Self::planet()
+    }
+}
+
+impl TrailStyle {
+    pub fn planet() -> Self {
+        Self {
+            newest_color: Color::rgba(0.7, 0.75, 0.85, 0.6),
+            oldest_color: Color::rgba(0.5, 0.55, 0.7, 0.2),
+            width: 1.,
+            dashes: None,
+            fade_out: 30.,
+            team_tinted: false,
+        }
+    }
+
+    /// Stars mostly sit still, but the ones in binaries leave a trail like anything else. Their
+    /// trails also say where they were, for drawing other trails relative to them.
+    pub fn star() -> Self {
+        Self {
+            newest_color: Color::rgba(1., 0.9, 0.6, 0.5),
+            oldest_color: Color::rgba(0.9, 0.7, 0.4, 0.15),
+            ..Self::planet()
+        }
+    }
+
+    pub fn ship() -> Self {
+        Self {
+            newest_color: Color::rgba(1., 1., 1., 0.9),
+            oldest_color: Color::rgba(1., 1., 1., 0.3),
+            width: 2.,
+            dashes: None,
+            fade_out: 15.,
+            team_tinted: true,
+        }
+    }
+
+    pub fn missile() -> Self {
+        Self {
+            newest_color: Color::rgba(1., 1., 1., 1.),
+            oldest_color: Color::rgba(1., 1., 1., 0.4),
+            width: 1.,
+            dashes: Some((6., 4.)),
+            fade_out: 1.,
+            team_tinted: true,
+        }
+    }
+
+    /// Color of a section `age` sections older than the newest one, on a trail `history` seconds
+    /// long
+    fn section_color(&self, age: i64, history: f32, team: Option<Color>) -> Color {
+        let age = (age as f32 / TRAIL_SECTIONS as f32).clamp(0., 1.);
+        let mut color = Vec4::from(self.newest_color.as_rgba_f32())
+            .lerp(Vec4::from(self.oldest_color.as_rgba_f32()), age);
+        if let (true, Some(team)) = (self.team_tinted, team) {
+            color *= Vec4::from(team.as_rgba_f32());
+        }
+
+        let seconds_left = history * (1. - age);
+        if seconds_left < self.fade_out {
+            color.w *= seconds_left / self.fade_out;
+        }
+        color.into()
+    }
+
+    fn draw_mode(&self, age: i64, history: f32, team: Option<Color>) -> DrawMode {
+        DrawMode::Stroke(StrokeMode {
+            options: StrokeOptions::default().with_line_width(self.width),
+            color: self.section_color(age, history, team),
+        })
+    }
+}
+
+/// One section of a trail, ready to be drawn
+struct TrailSection {
+    points: Vec<TrailPoint>,
+    dashes: Option<(f32, f32)>,
+}
+
+impl Geometry for TrailSection {
+    fn add_geometry(&self, b: &mut Builder) {
+        let point = |p: Vec2| Point2D::new(p.x, p.y);
+        let (dash, gap) = match self.dashes {
+            Some(dashes) => dashes,
+            None => {
+                let mut points = self.points.iter();
+                if let Some(first) = points.next() {
+                    b.begin(point(first.position));
+                    for p in points {
+                        b.line_to(point(p.position));
+                    }
+                    b.end(false);
+                }
+                return;
+            }
+        };
+
+        // cut every segment up wherever a dash starts or stops
+        let period = dash + gap;
+        for pair in self.points.windows(2) {
+            let (from, to) = (pair[0], pair[1]);
+            let length = to.distance - from.distance;
+            if length <= 0. {
+                continue;
+            }
+            let at = |distance: f32| {
+                from.position
+                    .lerp(to.position, (distance - from.distance) / length)
+            };
+
+            let mut distance = from.distance;
+            while distance < to.distance {
+                let phase = distance.rem_euclid(period);
+                let in_dash = phase < dash;
+                let boundary = distance - phase + if in_dash { dash } else { period };
+                let end = boundary.min(to.distance);
+                if in_dash {
+                    b.begin(point(at(distance)));
+                    b.line_to(point(at(end)));
+                    b.end(false);
+                }
+                distance = end;
+            }
+        }
+    }
+}
+
+/// One section of the line drawn for the snail trail of a body. It goes away once the body (or
+/// its trail) does.
+#[derive(Debug, Clone, Copy, Component)]
+pub struct SnailTrailEntityMarker(pub Entity);
+
+pub fn cycle_trail_frame(actions: Res<ActionState>, mut frame: ResMut<TrailFrame>) {
+    if actions.just_pressed(Action::TrailFrame) {
+        *frame = frame.next();
+    }
+}
+
+/// Whatever pulls hardest on `body` (other than itself), as an entity
+fn parent_of(
+    body: Entity,
+    position: Vec2,
+    gravity_sources: &Query<(Entity, &Position, &Mass), With<GravitySource>>,
+) -> Option<Entity> {
+    gravity_sources
+        .iter()
+        .filter(|(source, Position(source_pos), _)| {
+            *source != body && source_pos.distance_squared(position) > f32::EPSILON
+        })
+        .max_by(
+            |(_, Position(a), Mass(a_mass)), (_, Position(b), Mass(b_mass))| {
+                let pull = |pos: &Vec2, mass: f32| mass / pos.distance_squared(position);
+                pull(a, *a_mass).total_cmp(&pull(b, *b_mass))
+            },
+        )
+        .map(|(source, _, _)| source)
+}
+
+/// Samples every trail, and redraws the sections that have changed. Trails drawn relative to
+/// something that moves have to be redrawn completely every update.
+pub fn update_snail_trails(
+    mut commands: Commands,
+    sim_time: Res<SimulationTime>,
+    registry: Res<TeamRegistry>,
+    frame: Res<TrailFrame>,
+    length: Res<TrailLength>,
+    controller: Res<CameraController>,
+    mut bodies: Query<(
+        Entity,
+        &Position,
+        &mut SnailTrail,
+        Option<&TrailStyle>,
+        Option<&Team>,
+    )>,
+    positions: Query<&Position>,
+    gravity_sources: Query<(Entity, &Position, &Mass), With<GravitySource>>,
+    planets: Query<(Entity, &Mass), With<Planet>>,
+    local_player: Query<Entity, With<LocalPlayer>>,
+    mut renderers: Query<(&mut Path, &mut DrawMode), With<SnailTrailEntityMarker>>,
+) {
+    let now = sim_time.elapsed;
+    let mut redraw = Vec::new();
+    for (body, Position(pos), mut trail, style, team) in bodies.iter_mut() {
+        let history = trail.base_history * length.0 / DEFAULT_TRAIL_HISTORY;
+        trail.set_history(history);
+        trail.record(*pos, now, sim_time.delta);
+        let stale = std::mem::take(&mut trail.stale);
+
+        let spawned = trail.renderers.len() == TRAIL_RENDERERS
+            && trail
+                .renderers
+                .iter()
+                .all(|renderer| renderers.contains(*renderer));
+        if spawned {
+            if frame.is_changed() || *frame != TrailFrame::Inertial {
+                redraw.push((body, ALL_STALE));
+            } else if stale != 0 {
+                redraw.push((body, stale));
+            }
+            continue;
+        }
+
+        // drawn where the body actually went to start with, and moved into the frame next update
+        for renderer in trail.renderers.drain(..) {
+            if let Some(mut renderer) = commands.get_entity(renderer) {
+                renderer.despawn();
+            }
+        }
+        let style = style.cloned().unwrap_or_default();
+        let team_color = team.map(|team| registry.color(team));
+        for idx in 0..TRAIL_RENDERERS {
+            let section = trail.section_drawn_by(idx);
+            let renderer = commands
+                .spawn_bundle(GeometryBuilder::build_as(
+                    &TrailSection {
+                        points: trail.section(section),
+                        dashes: style.dashes,
+                    },
+                    style.draw_mode(trail.newest_section - section, trail.history, team_color),
+                    Transform::default(),
+                ))
+                .insert(SnailTrailEntityMarker(body))
+                .id();
+            trail.renderers.push(renderer);
+        }
+    }
+
+    // the references that are the same for every trail
+    let camera_target = controller
+        .target
+        .filter(|target| positions.contains(*target))
+        .or_else(|| local_player.iter().next());
+    let rotating = {
+        let secondary = match controller.frame {
+            CameraFrame::Rotating(body) => Some(body),
+            CameraFrame::Inertial | CameraFrame::CenterOfMass => controller.target,
+        };
+        let secondary = secondary
+            .filter(|body| planets.contains(*body))
+            .or_else(|| {
+                planets
+                    .iter()
+                    .max_by(|(_, Mass(a)), (_, Mass(b))| a.total_cmp(b))
+                    .map(|(planet, _)| planet)
+            });
+        secondary.and_then(|secondary| {
+            let Position(pos) = positions.get(secondary).ok()?;
+            let primary = parent_of(secondary, *pos, &gravity_sources)?;
+            Some(Reference::Rotating { primary, secondary })
+        })
+    };
+
+    // where a body was at some point in the past, going by its own trail. Every gravity source has
+    // one, so only camera targets without one (e.g explosions) are taken to be where they are now.
+    let position_at = |body: Entity, at: f64| {
+        let Position(current) = positions.get(body).ok()?;
+        match bodies.get(body) {
+            Ok((_, _, trail, _, _)) => trail.position_at(at, now, *current),
+            Err(_) => Some(*current),
+        }
+    };
+    // origin and rotation of a reference at some point in the past
+    let frame_at = |reference: Reference, at: f64| match reference {
+        Reference::Body(body) => Some((position_at(body, at)?, 0.)),
+        Reference::Rotating { primary, secondary } => {
+            let secondary = position_at(secondary, at)?;
+            let outward = secondary - position_at(primary, at)?;
+            Some((secondary, outward.y.atan2(outward.x)))
+        }
+    };
+
+    for (body, stale) in redraw {
+        let (_, Position(pos), trail, style, team) = match bodies.get(body) {
+            Ok(body) => body,
+            Err(_) => continue,
+        };
+        let reference = match *frame {
+            TrailFrame::Inertial => None,
+            TrailFrame::Parent => parent_of(body, *pos, &gravity_sources).map(Reference::Body),
+            // a trail relative to its own body would just be a dot
+            TrailFrame::CameraTarget => camera_target
+                .filter(|target| *target != body)
+                .map(Reference::Body),
+            TrailFrame::Rotating => rotating,
+        };
+        // the frame as it is now, which the whole trail is drawn as if it had been in
+        let current = reference.and_then(|reference| Some((reference, frame_at(reference, now)?)));
+
+        let style = style.cloned().unwrap_or_default();
+        let team_color = team.map(|team| registry.color(team));
+        for (idx, renderer) in trail.renderers.iter().enumerate() {
+            if stale & (1 << idx) == 0 {
+                continue;
+            }
+            let section = trail.section_drawn_by(idx);
+            let mut points = trail.section(section);
+            if let Some((reference, (origin, rotation))) = current {
+                // points from further back than the frame is known for are left out
+                points = points
+                    .into_iter()
+                    .filter_map(|point| {
+                        let (then_origin, then_rotation) = frame_at(reference, point.time)?;
+                        Some(TrailPoint {
+                            position: origin
+                                + Mat2::from_angle(rotation - then_rotation)
+                                    * (point.position - then_origin),
+                            ..point
+                        })
+                    })
+                    .collect();
+            }
+            if let Ok((mut path, mut draw_mode)) = renderers.get_mut(*renderer) {
+                *path = ShapePath::build_as(&TrailSection {
+                    points,
+                    dashes: style.dashes,
+                });
+                *draw_mode =
+                    style.draw_mode(trail.newest_section - section, trail.history, team_color);
+            }
+        }
+    }
+}
+
+/// Gets rid of the trails of bodies that are gone
+pub fn despawn_orphaned_trails(
+    mut commands: Commands,
+    trails: Query<(Entity, &SnailTrailEntityMarker)>,
+    bodies: Query<(), With<SnailTrail>>,
+) {
+    for (trail, SnailTrailEntityMarker(body)) in trails.iter() {
+        if !bodies.contains(*body) {
+            commands.entity(trail).despawn();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A trail 8 seconds long, so that each section is a second, with a point every quarter of a
+    /// second (which add up exactly)
+    fn recorded_trail(seconds: f64) -> SnailTrail {
+        let mut trail = SnailTrail::with_history(8.);
+        trail.sample_interval = 0.25;
+        trail.min_distance = 0.;
+        for step in 0..(seconds * 4.) as u32 {
+            let now = step as f64 / 4.;
+            trail.record(Vec2::new(now as f32, 0.), now, 0.25);
+        }
+        trail
+    }
+
+    #[test]
+    fn sections_join_up_and_cover_the_trail() {
+        let trail = recorded_trail(20.);
+        let sections: Vec<Vec<TrailPoint>> = (0..TRAIL_RENDERERS)
+            .map(|idx| trail.section(trail.section_drawn_by(idx)))
+            .collect();
+        let mut sections: Vec<&Vec<TrailPoint>> =
+            sections.iter().filter(|s| !s.is_empty()).collect();
+        sections.sort_by(|a, b| a[0].time.total_cmp(&b[0].time));
+
+        for pair in sections.windows(2) {
+            assert_eq!(pair[0].last().unwrap().time, pair[1][0].time);
+        }
+        let drawn: usize = sections.iter().map(|s| s.len()).sum::<usize>() - (sections.len() - 1);
+        assert_eq!(drawn, trail.points.len());
+    }
+
+    #[test]
+    fn recording_only_touches_the_ends() {
+        let mut trail = recorded_trail(20.5);
+        trail.stale = 0;
+        trail.record(Vec2::new(20.5, 0.), 20.5, 0.25);
+
+        // the newest section gets the point and the oldest loses one, and nothing else changes
+        let newest = trail.section_of(20.5);
+        let oldest = trail.section_of(trail.points[0].time);
+        let expected = [newest, oldest].iter().fold(0, |stale, section| {
+            stale | 1 << section.rem_euclid(TRAIL_RENDERERS as i64)
+        });
+        assert_eq!(trail.stale, expected);
+    }
+}